@@ -0,0 +1,172 @@
+use std::ffi::CString; //Libreria para pasar los nombres de los archivos a las funciones de C
+use std::fs::File; // Liberia para el manejo de archivos
+use std::io::{self, Read, Write}; //Libreria para el manejo de entradas y salidas
+use std::os::unix::io::{AsRawFd, FromRawFd}; //Libreria para usar los descriptores de archivo de Unix
+use libc::{c_int, O_CREAT, O_DIRECTORY, O_RDONLY, O_RDWR, O_TRUNC, O_WRONLY, O_CLOEXEC}; //Libreria para el manejo de banderas del codigo en C
+
+// Nombres de los archivos del disco dentro de su directorio
+pub const INODE_FILE: &str = "inode.qrfs";
+pub const DATA_FILE: &str = "disco.qrfs";
+// Copias nuevas de los archivos, escritas completas antes de reemplazar a los originales
+pub const INODE_TEMP_FILE: &str = "inode.qrfs.tmp";
+pub const DATA_TEMP_FILE: &str = "disco.qrfs.tmp";
+// Marca de que las copias nuevas están completas y deben reemplazar a los originales
+pub const COMMIT_FILE: &str = "commit.qrfs";
+
+/// Directorio donde se guardan los archivos del disco. Se abre antes de montar el sistema de archivos: como el punto
+/// de montaje es el mismo directorio, usar las rutas mientras está montado pasaría por el propio QrFS. Todas las
+/// operaciones se hacen relativas al descriptor con `openat`, `renameat` y `unlinkat`, que siguen viendo el directorio
+/// original.
+#[derive(Debug)]
+pub struct DiskDirectory {
+    directory: File
+}
+
+/// Convierte un nombre de archivo en una cadena de C.
+fn c_name(name: &str) -> io::Result<CString> {
+    CString::new(name).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "nombre de archivo inválido"))
+}
+
+/// Convierte el resultado de una llamada al sistema en un `io::Result`.
+fn check(result: c_int) -> io::Result<c_int> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+impl DiskDirectory {
+    /// Abre el directorio `path`.
+    pub fn open(path: &str) -> io::Result<DiskDirectory> {
+        let path = c_name(path)?;
+        let fd = check(unsafe { libc::open(path.as_ptr(), O_RDONLY | O_DIRECTORY | O_CLOEXEC) })?;
+
+        Ok(DiskDirectory {
+            directory: unsafe { File::from_raw_fd(fd) }
+        })
+    }
+
+    /// Abre el archivo `name` del directorio con las banderas `flags` de `open(2)`.
+    pub fn open_file(&self, name: &str, flags: c_int) -> io::Result<File> {
+        let name = c_name(name)?;
+        let fd = check(unsafe { libc::openat(self.directory.as_raw_fd(), name.as_ptr(), flags | O_CLOEXEC, 0o644) })?;
+
+        Ok(unsafe { File::from_raw_fd(fd) })
+    }
+
+    /// Indica si el archivo `name` existe en el directorio.
+    pub fn exists(&self, name: &str) -> bool {
+        match c_name(name) {
+            Ok(name) => unsafe { libc::faccessat(self.directory.as_raw_fd(), name.as_ptr(), libc::F_OK, 0) == 0 },
+            Err(_) => false
+        }
+    }
+
+    /// Lee el contenido completo del archivo `name`.
+    pub fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        let mut content = Vec::new();
+        self.open_file(name, O_RDONLY)?.read_to_end(&mut content)?;
+        Ok(content)
+    }
+
+    /// Escribe `content` en el archivo `name`, reemplazando su contenido anterior, y lo sincroniza con el
+    /// dispositivo antes de volver.
+    pub fn write(&self, name: &str, content: &[u8]) -> io::Result<()> {
+        let mut file = self.open_file(name, O_WRONLY | O_CREAT | O_TRUNC)?;
+        file.write_all(content)?;
+        file.sync_all()
+    }
+
+    /// Renombra el archivo `from` a `to` dentro del directorio, reemplazando a `to` si existe.
+    pub fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        let (from, to) = (c_name(from)?, c_name(to)?);
+        let fd = self.directory.as_raw_fd();
+        check(unsafe { libc::renameat(fd, from.as_ptr(), fd, to.as_ptr()) })?;
+        Ok(())
+    }
+
+    /// Borra el archivo `name`. No es un error que ya no exista.
+    pub fn remove(&self, name: &str) -> io::Result<()> {
+        let name = c_name(name)?;
+        match check(unsafe { libc::unlinkat(self.directory.as_raw_fd(), name.as_ptr(), 0) }) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(())
+        }
+    }
+
+    /// Sincroniza el directorio, para que los archivos creados, renombrados o borrados sobrevivan a un corte de energía.
+    pub fn sync(&self) -> io::Result<()> {
+        self.directory.sync_all()
+    }
+
+    /// Reemplaza el contenido del archivo `name` de forma atómica: escribe una copia temporal completa, la
+    /// sincroniza y la renombra sobre el original.
+    pub fn replace(&self, name: &str, content: &[u8]) -> io::Result<()> {
+        let temp_name = format!("{}.tmp", name);
+        self.write(&temp_name, content)?;
+        self.rename(&temp_name, name)?;
+        self.sync()
+    }
+
+    /// Reemplaza `inode.qrfs` y `disco.qrfs` juntos, como una sola operación: `write` escribe las copias nuevas,
+    /// que se sincronizan primero, luego se crea la marca `commit.qrfs` y recién entonces se renombran sobre los
+    /// originales. Si el proceso se interrumpe antes de la marca quedan los archivos anteriores; si se interrumpe
+    /// después, `recover_commit` termina los reemplazos al volver a cargar el disco.
+    pub fn commit<E, F>(&self, write: F) -> Result<(), E>
+    where E: From<io::Error>, F: FnOnce(&File, &File) -> Result<(), E> {
+        let inodes = self.open_file(INODE_TEMP_FILE, O_RDWR | O_CREAT | O_TRUNC)?;
+        let data = self.open_file(DATA_TEMP_FILE, O_RDWR | O_CREAT | O_TRUNC)?;
+        write(&inodes, &data)?;
+        inodes.sync_all()?;
+        data.sync_all()?;
+        self.sync()?;
+
+        self.write(COMMIT_FILE, &[])?;
+        self.sync()?;
+
+        Ok(self.finish_commit()?)
+    }
+
+    /// Termina o descarta una escritura interrumpida de `commit`. Con la marca presente, las copias nuevas están
+    /// completas y se renombran sobre los originales; sin ella, las copias pueden estar a medias y se borran.
+    /// Devuelve `true` si había una escritura completa pendiente.
+    pub fn recover_commit(&self) -> io::Result<bool> {
+        if self.exists(COMMIT_FILE) {
+            self.finish_commit()?;
+            return Ok(true);
+        }
+
+        self.remove(INODE_TEMP_FILE)?;
+        self.remove(DATA_TEMP_FILE)?;
+        Ok(false)
+    }
+
+    /// Indica si quedó una escritura de `commit` interrumpida, sin tocar los archivos: `Some(true)` si la marca está y
+    /// `recover_commit` terminaría los reemplazos, `Some(false)` si solo quedaron copias que borraría y `None` si no
+    /// quedó nada.
+    pub fn interrupted_commit(&self) -> Option<bool> {
+        if self.exists(COMMIT_FILE) {
+            return Some(true);
+        }
+
+        match self.exists(INODE_TEMP_FILE) || self.exists(DATA_TEMP_FILE) {
+            true => Some(false),
+            false => None
+        }
+    }
+
+    /// Renombra las copias nuevas que queden sobre los originales y borra la marca. Se puede repetir sin problema si
+    /// se interrumpe, porque una copia ya renombrada simplemente deja de existir.
+    fn finish_commit(&self) -> io::Result<()> {
+        for (temp_name, name) in [(INODE_TEMP_FILE, INODE_FILE), (DATA_TEMP_FILE, DATA_FILE)].iter() {
+            if self.exists(temp_name) {
+                self.rename(temp_name, name)?;
+            }
+        }
+        self.sync()?;
+
+        self.remove(COMMIT_FILE)?;
+        self.sync()
+    }
+}
@@ -4,62 +4,88 @@ extern crate serde_big_array;
 extern crate image;
 mod persistencia;
 mod serialization;
+mod cifrado;
+mod directorio;
+mod bitacora;
+mod imagen;
+mod cache;
+mod mapa;
+mod entradas;
+mod permisos;
+mod atributos;
+#[cfg(test)]
+mod pruebas;
 
 // Libreria para el manejo del file system en el user space
-use fuse::{Filesystem, Request, ReplyCreate, ReplyEmpty, ReplyAttr, ReplyEntry, ReplyOpen, ReplyData, ReplyDirectory, ReplyWrite, FileType, FileAttr};
+use fuse::{Filesystem, Request, ReplyCreate, ReplyEmpty, ReplyAttr, ReplyEntry, ReplyOpen, ReplyData, ReplyDirectory, ReplyWrite, ReplyXattr, ReplyStatfs, FileType, FileAttr};
 // Libreria para el manejo de banderas del codigo en C
-use libc::{ENOSYS, ENOENT, EIO, EISDIR, ENOSPC};
+use libc::{ENOENT, EIO, EISDIR, ENOSPC, EEXIST, ENOTDIR, ENOTEMPTY, EINVAL, EPERM, EACCES, F_OK, W_OK, ENODATA, O_TRUNC};
 // Liberia para obtener el tiempo
 use time::{Timespec};
 // Libreria para obtener el ambiente de ejecucion
 use std::env;
-// Libreria para el manejo de memoria
-use std::mem;
 // Libreria para obtener el mejor manejo de strings de nuestro sistema operativo
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
+// Libreria para pasar los destinos de los enlaces simbólicos a bytes
+use std::os::unix::ffi::OsStrExt;
 // Libreria para la implementacion de nuestra persistencia
-use crate::persistencia::{Disk, Inode};
+use crate::persistencia::{Disk, DiskError, Inode};
+// Libreria para las entradas de los directorios
+use crate::entradas::{DirectoryEntry, NAME_CAPACITY};
+// Libreria para los permisos y dueños de los archivos
+use crate::permisos::{Caller, change_mode, change_owner, check_add_entry, check_list, check_lookup, check_remove_entry, check_xattr_read, check_xattr_write, drop_set_id_on_write, has_access, lists_xattr, may_set_times, new_owner, open_mask, permissions_from_mode};
+// Libreria para los atributos extendidos
+use crate::atributos::{check_xattr_buffer, check_xattr_flags, check_xattr_size, xattr_namespace};
+// Libreria para las credenciales que abren el disco
+use crate::cifrado::{Credential, KeyShare};
+// Libreria para los atributos extendidos de los inodos nuevos
+use std::collections::BTreeMap;
 // Libreria para verificar si un archivo existe
 use std::path::Path;
-// Libreria standard para escribir y leer archivos binarios
-use std::{
-    fs::File,
-    io::{BufWriter, Write, Read,stdin,stdout},
-};
-//Libreria para el manejo del file system 
-use std::fs;
+// Libreria standard para leer la frase desde la terminal
+use std::io::{Write, stdin, stdout};
 //Libreria para el manejo de la conversion de Vectores a un formato codigo QR
-use qrcode_generator::QrCodeEcc;
+use qrcode_generator::{QrCodeEcc, QRCodeError};
 //Libreria para el manejo de la conversion del Codigo QR a imagen
 use image::{ImageFormat};
 
+// Bytes que caben en un código QR de versión 40 con corrección de errores baja
+const QR_CODE_CAPACITY: usize = 2953;
+
 struct QrFS {
     disk: Disk
 }
 
 impl QrFS {
-    /// Inicializa FS con el tamaño especificado en `memory_size` con bloques de memoria de tamaño    
-    /// `block_size`.
-    fn new(root_path: String, phrase: String) -> Self {
-        let max_files: usize = 1024;
-        let memory_size: usize = 1024 * 1024 * 1024;
-        //let memory_size: usize = 1024 * 1024 * 1024;
-        let block_size: usize = max_files * (mem::size_of::<Box<[Inode]>>() + mem::size_of::<Inode>());
-
-        let disk = Disk::new(root_path, memory_size, block_size, phrase);
+    /// Inicializa FS con el disco guardado en `root_path`, usando la geometría de su encabezado.
+    fn new(root_path: String, credential: Credential) -> Result<Self, DiskError> {
+        let disk = Disk::new(root_path, credential)?;
 
-        QrFS {
+        Ok(QrFS {
             disk
-        }
+        })
+    }
+
+    /// Abre el disco guardado en `root_path` solo para revisarlo, sin escribir en sus archivos. Devuelve también los
+    /// arreglos que haría falta guardar, que `--repair` aplica.
+    fn open_read_only(root_path: String, credential: Credential) -> Result<(Self, Vec<String>), DiskError> {
+        let (disk, repairs) = Disk::open_read_only(root_path, credential)?;
+
+        Ok((QrFS { disk }, repairs))
     }
 }
 //En caso de desmontar el file system se ejecuta esta funcion.
-#[allow(unused_must_use)]
 impl Drop for QrFS {
     fn drop(&mut self) {
+        // Un disco abierto solo para revisarlo no tiene nada que guardar
+        if self.disk.is_read_only() {
+            return;
+        }
         println!("\nsaving content...");
-        &self.disk.write_to_disk();
-        println!("success!");
+        match self.disk.write_to_disk() {
+            Ok(()) => println!("success!"),
+            Err(e) => println!("¡Error al guardar el disco! {}", e)
+        }
     }
 }
 
@@ -67,28 +93,38 @@ impl Drop for QrFS {
 impl Filesystem for QrFS {
     fn lookup(
         &mut self, 
-        _req: &Request, 
+        req: &Request, 
         parent: u64, 
         name: &OsStr, 
         reply: ReplyEntry
     ) {
         println!("lookup(parent={:?}, name={:?})", parent, name);
-        let file_name = name.to_str().unwrap();
-        let inode = self.disk.find_inode_in_references_by_name(parent, file_name);
+
+        // Buscar un nombre necesita permiso de búsqueda sobre el directorio
+        if let Err(errno) = check_lookup(&Caller::of(req), self.disk.get_inode(parent).map(|inode| &inode.attributes)) {
+            reply.error(errno);
+            return;
+        }
+
+        let inode = self.disk.find_inode_in_references_by_name(parent, name);
 
         match inode {
-            Some(inode) => {
+            Ok(Some(inode)) => {
                 let ttl = time::now().to_timespec();
                 println!("        - lookup(parent={:?}, attr={:?})", parent, inode.attributes);
                 reply.entry(&ttl, &inode.attributes, 0)
             },
-            None => reply.error(ENOENT) // “No such file or directory.”
+            Ok(None) => reply.error(ENOENT), // “No such file or directory.”
+            Err(e) => {
+                println!("¡Error al buscar la entrada! {}", e);
+                reply.error(e.errno())
+            }
         }
     }
     //Implementacion para cuando se ejecute un create en el filesystem
     fn create(
         &mut self, 
-        _req: &Request, 
+        req: &Request, 
         parent: u64, 
         name: &OsStr, 
         mode: u32, 
@@ -97,66 +133,96 @@ impl Filesystem for QrFS {
     ) {
         println!("create(name={:?}, mode={}, flags={})", name, mode, flags);
 
-        let ref_index = self.disk.find_index_of_empty_reference_in_inode(parent);
-        // Si no hay más espacio en el vector de referencias, indica que no es posible ubicar más archivos dentro de la carpeta        
-        if ref_index == None {
-            println!("¡No se pueden crear más archivos en este directorio!");
-            reply.error(EIO); // “Input/output error.”
+        match self.disk.find_inode_in_references_by_name(parent, name) {
+            Ok(Some(_)) => {
+                reply.error(EEXIST); // “File exists.”
+                return;
+            },
+            Ok(None) => (),
+            Err(e) => {
+                println!("¡Error al buscar la entrada! {}", e);
+                reply.error(e.errno());
+                return;
+            }
+        }
+
+        // Agregar una entrada necesita permisos de escritura y de búsqueda sobre el directorio
+        let caller = Caller::of(req);
+        if let Err(errno) = check_add_entry(&caller, self.disk.get_inode(parent).map(|inode| &inode.attributes)) {
+            reply.error(errno);
             return;
         }
 
         let ino_available = self.disk.find_ino_available();
-        let memory_block_index = self.disk.find_index_of_empty_memory_block();
 
-        if ino_available == None || memory_block_index == None {
+        if ino_available.is_none() {
             reply.error(ENOSPC); // “No hay mas espacio en el dispositivo.”
             return;
         }
 
         let ino_available = ino_available.unwrap();
-        let memory_block_index = memory_block_index.unwrap();
+
+        let parent_attributes = match self.disk.get_inode(parent) {
+            Some(inode) => inode.attributes,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        let (uid, gid, perm) = new_owner(&caller, &parent_attributes, FileType::RegularFile, permissions_from_mode(mode));
 
         let ts = time::now().to_timespec();
-        // Guardamos los atributos en una variable
+
         let attr = FileAttr {
             ino: ino_available,
             size: 0,
-            blocks: 1,
+            blocks: 0,
             atime: ts,
             mtime: ts,
             ctime: ts,
             crtime: ts,
             kind: FileType::RegularFile,
-            perm: 0o755,
-            nlink: 0,
-            uid: 0,
-            gid: 0,
+            perm,
+            nlink: 1,
+            uid,
+            gid,
             rdev: 0,
             flags,
         };
-        
-        let name = name.to_str().unwrap();
-        let name: Vec<char> = name.chars().collect();
-
-        let mut name_char = ['\0'; 64];
-        name_char[..name.len()].clone_from_slice(&name);
 
-        let mut inode = Inode {
-            name: name_char,
+        // Los bloques de datos se reservan a medida que se escribe en el archivo
+        let inode = Inode {
             attributes: attr,
-            references: [None; 128]
+            references: [None; 128],
+            target: None,
+            xattrs: BTreeMap::new()
         };
 
-        inode.references[0] = Some(memory_block_index);
+        // Agrega la entrada del archivo nuevo al directorio "principal", que crece si hace falta
+        let entry = DirectoryEntry { ino: ino_available, kind: FileType::RegularFile, name: name.to_os_string() };
+        if let Err(e) = self.disk.add_directory_entry(parent, entry) {
+            println!("¡No se pudo agregar el archivo al directorio! {}", e);
+            self.disk.abort_transaction();
+            reply.error(e.errno());
+            return;
+        }
 
-        self.disk.write_inode(inode);
-        let content: Box<[u8]> = Box::default();
-        self.disk.write_content_as_bytes(memory_block_index, content);
+        if let Err(e) = self.disk.write_inode(inode) {
+            println!("¡No se pudo guardar el inodo del archivo! {}", e);
+            self.disk.abort_transaction();
+            reply.error(e.errno());
+            return;
+        }
 
-        // Agregua la referencia del inodo creada en las referencias vectoriales al inodo "principal" (desde el directorio)
-        let ref_index = ref_index.unwrap();
-        self.disk.write_reference_in_inode(parent, ref_index, ino_available as usize);
+        if let Err(e) = self.disk.commit_transaction() {
+            println!("¡Error al registrar la operación en la bitácora! {}", e);
+            self.disk.abort_transaction();
+            reply.error(EIO);
+            return;
+        }
 
+        // El archivo queda abierto hasta que el núcleo lo cierre con release
+        self.disk.open_handle(ino_available);
         reply.created(&ts, &attr, 1, ino_available, flags)
     }
     // Esta funcion se encarga de sincronizar un archivo en el estado del núcleo con el dispositivo de almacenamiento
@@ -169,20 +235,45 @@ impl Filesystem for QrFS {
         reply: ReplyEmpty
     ) { 
         println!("fsync(ino={}, fh={}, datasync={})", ino, fh, datasync);
-        reply.error(ENOSYS);
+        // Los cambios del archivo ya están registrados en la bitácora, basta con sincronizarla
+        match self.disk.sync() {
+            Ok(()) => reply.ok(),
+            Err(e) => {
+                println!("¡Error al sincronizar el disco! {}", e);
+                reply.error(EIO);
+            }
+        }
+    }
+    // Esta funcion se encarga de sincronizar el contenido de un directorio con el dispositivo de almacenamiento
+    fn fsyncdir(
+        &mut self, 
+        _req: &Request, 
+        ino: u64, 
+        fh: u64, 
+        datasync: bool, 
+        reply: ReplyEmpty
+    ) { 
+        println!("fsyncdir(ino={}, fh={}, datasync={})", ino, fh, datasync);
+        match self.disk.sync() {
+            Ok(()) => reply.ok(),
+            Err(e) => {
+                println!("¡Error al sincronizar el disco! {}", e);
+                reply.error(EIO);
+            }
+        }
     }
     // Funcion se encarga de aplicar los atributos a un archivo
     fn setattr(
         &mut self, 
-        _req: &Request, 
+        req: &Request, 
         ino: u64, 
-        _mode: Option<u32>, 
+        mode: Option<u32>, 
         uid: Option<u32>, 
         gid: Option<u32>, 
         size: Option<u64>, 
         atime: Option<Timespec>, 
         mtime: Option<Timespec>, 
-        _fh: Option<u64>, 
+        fh: Option<u64>, 
         crtime: Option<Timespec>, 
         _chgtime: Option<Timespec>, 
         _bkuptime: Option<Timespec>, 
@@ -190,21 +281,90 @@ impl Filesystem for QrFS {
         reply: ReplyAttr
     ) {
         println!("setattr(ino={})", ino);
+        let mut attributes = match self.disk.get_inode(ino) {
+            Some(inode) => inode.attributes,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+
+        // Los permisos se revisan antes de cambiar nada, así un pedido rechazado no deja cambios a medias
+        let caller = Caller::of(req);
+        if let Some(mode) = mode {
+            if !change_mode(&caller, &mut attributes, mode) {
+                reply.error(EPERM); // “Operation not permitted.”
+                return;
+            }
+        }
+        if (uid.is_some() || gid.is_some()) && !change_owner(&caller, &mut attributes, uid, gid) {
+            reply.error(EPERM);
+            return;
+        }
+        if (atime.is_some() || mtime.is_some() || crtime.is_some()) && !may_set_times(&caller, &attributes) {
+            reply.error(EPERM);
+            return;
+        }
+        // Con un handle abierto el permiso de escritura ya se revisó al abrir el archivo, como en ftruncate
+        if size.is_some() && fh.is_none() && !has_access(&caller, &attributes, W_OK) {
+            reply.error(EACCES); // “Permission denied.”
+            return;
+        }
+
+        // El cambio de tamaño de un archivo regular pasa por su mapa de bloques: al achicarlo se liberan los bloques que
+        // quedan afuera y al agrandarlo la parte nueva queda como un hueco que se lee como ceros
+        if let Some(size) = size {
+            match attributes.kind {
+                FileType::RegularFile => (),
+                FileType::Directory => {
+                    reply.error(EISDIR); // “Is a directory.”
+                    return;
+                },
+                _ => {
+                    reply.error(EINVAL); // “Invalid argument.”
+                    return;
+                }
+            }
+
+            if let Err(e) = self.disk.truncate_file(ino, size) {
+                println!("¡Error al cambiar el tamaño del archivo! {}", e);
+                self.disk.abort_transaction();
+                reply.error(e.errno());
+                return;
+            }
+
+            if let Some(inode) = self.disk.get_inode(ino) {
+                attributes.size = inode.attributes.size;
+                attributes.blocks = inode.attributes.blocks;
+                attributes.mtime = inode.attributes.mtime;
+                attributes.ctime = inode.attributes.ctime;
+            }
+            drop_set_id_on_write(&caller, &mut attributes);
+        }
+
         let inode = self.disk.get_inode_as_mut(ino);
         
         match inode {
             Some(inode) => {
-                if let Some(size) = size { inode.attributes.size = size; }
-                if let Some(atime) = atime { inode.attributes.atime = atime; }
-                if let Some(mtime) = mtime { inode.attributes.mtime = mtime; }
-                if let Some(crtime) = crtime { inode.attributes.crtime = crtime; }
-                if let Some(gid) = gid { inode.attributes.gid = gid; }
-                if let Some(uid) = uid { inode.attributes.uid = uid; }
-                if let Some(flags) = flags { inode.attributes.flags = flags; }
+                if let Some(atime) = atime { attributes.atime = atime; }
+                if let Some(mtime) = mtime { attributes.mtime = mtime; }
+                if let Some(crtime) = crtime { attributes.crtime = crtime; }
+                if let Some(flags) = flags { attributes.flags = flags; }
+                if mode.is_some() || uid.is_some() || gid.is_some() {
+                    attributes.ctime = time::now().to_timespec();
+                }
+                inode.attributes = attributes;
+
+                if let Err(e) = self.disk.commit_transaction() {
+                    println!("¡Error al registrar la operación en la bitácora! {}", e);
+                    self.disk.abort_transaction();
+                    reply.error(EIO);
+                    return;
+                }
 
                 let ttl = time::now().to_timespec();
 
-                reply.attr(&ttl, &inode.attributes)
+                reply.attr(&ttl, &attributes)
             },
             None => reply.error(ENOENT)
         }
@@ -226,90 +386,197 @@ impl Filesystem for QrFS {
             None => reply.error(ENOENT)
         }
     }
+    // Esta funcion se encarga de revisar si quien pregunta tiene los permisos pedidos sobre un archivo
+    fn access(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        mask: u32,
+        reply: ReplyEmpty
+    ) {
+        println!("access(ino={}, mask={})", ino, mask);
+
+        match self.disk.get_inode(ino) {
+            Some(_) if mask as i32 == F_OK => reply.ok(),
+            Some(inode) if has_access(&Caller::of(req), &inode.attributes, mask as i32) => reply.ok(),
+            Some(_) => reply.error(EACCES), // “Permission denied.”
+            None => reply.error(ENOENT)
+        }
+    }
+    // Esta funcion se encarga de informar la ocupación del disco, como la muestra `df`
+    fn statfs(
+        &mut self,
+        _req: &Request,
+        _ino: u64,
+        reply: ReplyStatfs
+    ) {
+        println!("statfs()");
+
+        let usage = self.disk.usage();
+        reply.statfs(
+            usage.total_blocks as u64,
+            usage.free_blocks as u64,
+            usage.free_blocks as u64,
+            usage.total_inodes as u64,
+            usage.free_inodes as u64,
+            usage.block_size as u32,
+            NAME_CAPACITY as u32,
+            usage.block_size as u32
+        );
+    }
     // Esta funcion se encarga de crear un directorio nuevo
     fn mkdir(
         &mut self, 
-        _req: &Request, 
+        req: &Request, 
         parent: u64, 
         name: &OsStr, 
-        _mode: u32, 
+        mode: u32, 
         reply: ReplyEntry
     ) {
-        let reference_index = self.disk.find_index_of_empty_reference_in_inode(parent);
-        
-        match reference_index {
-            Some(reference_index) => {
-
-                let ino = self.disk.find_ino_available();
-                match ino {
-                    Some(ino) => {
-                        let ts = time::now().to_timespec();
-                        let attr = FileAttr {
-                            ino: ino as u64,
-                            size: 0,
-                            blocks: 1,
-                            atime: ts,
-                            mtime: ts,
-                            ctime: ts,
-                            crtime: ts,
-                            kind: FileType::Directory,
-                            perm: 0o755,
-                            nlink: 0,
-                            uid: 0,
-                            gid: 0,
-                            rdev: 0,
-                            flags: 0,
-                        };
-
-                        let name = name.to_str().unwrap();
-                        let name: Vec<char> = name.chars().collect();
-
-                        let mut name_char = ['\0'; 64];
-                        name_char[..name.len()].clone_from_slice(&name);
-
-                        let inode = Inode {
-                            name: name_char,
-                            attributes: attr,
-                            references: [None; 128]
-                        };
-
-                        self.disk.write_inode(inode);
-                        self.disk.write_reference_in_inode(parent, reference_index, ino as usize);
-
-                        reply.entry(&ts, &attr, 0);
-                    },
-                    None => reply.error(ENOSPC) // “No space left on device.”
+        match self.disk.find_inode_in_references_by_name(parent, name) {
+            Ok(Some(_)) => {
+                reply.error(EEXIST); // “File exists.”
+                return;
+            },
+            Ok(None) => (),
+            Err(e) => {
+                println!("¡Error al buscar la entrada! {}", e);
+                reply.error(e.errno());
+                return;
+            }
+        }
+
+        // Agregar una entrada necesita permisos de escritura y de búsqueda sobre el directorio
+        let caller = Caller::of(req);
+        if let Err(errno) = check_add_entry(&caller, self.disk.get_inode(parent).map(|inode| &inode.attributes)) {
+            reply.error(errno);
+            return;
+        }
+
+        let parent_attributes = match self.disk.get_inode(parent) {
+            Some(inode) => inode.attributes,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        let (uid, gid, perm) = new_owner(&caller, &parent_attributes, FileType::Directory, permissions_from_mode(mode));
+
+        let ino = self.disk.find_ino_available();
+        match ino {
+            Some(ino) => {
+                let ts = time::now().to_timespec();
+                let attr = FileAttr {
+                    ino,
+                    size: 0,
+                    blocks: 0,
+                    atime: ts,
+                    mtime: ts,
+                    ctime: ts,
+                    crtime: ts,
+                    kind: FileType::Directory,
+                    perm,
+                    nlink: 2,
+                    uid,
+                    gid,
+                    rdev: 0,
+                    flags: 0,
+                };
+
+                // Las entradas del directorio nuevo se guardan en sus bloques de datos, que se reservan a medida que crece
+                let inode = Inode {
+                    attributes: attr,
+                    references: [None; 128],
+                    target: None,
+                    xattrs: BTreeMap::new()
+                };
+
+                let entry = DirectoryEntry { ino, kind: FileType::Directory, name: name.to_os_string() };
+                if let Err(e) = self.disk.add_directory_entry(parent, entry) {
+                    println!("¡No se pudo agregar el directorio a su directorio padre! {}", e);
+                    self.disk.abort_transaction();
+                    reply.error(e.errno());
+                    return;
+                }
+
+                if let Err(e) = self.disk.write_inode(inode) {
+                    println!("¡No se pudo guardar el inodo del directorio! {}", e);
+                    self.disk.abort_transaction();
+                    reply.error(e.errno());
+                    return;
+                }
+                // La entrada ".." del directorio nuevo es un enlace más de su directorio padre
+                self.disk.increase_links(parent);
+
+                if let Err(e) = self.disk.commit_transaction() {
+                    println!("¡Error al registrar la operación en la bitácora! {}", e);
+                    self.disk.abort_transaction();
+                    reply.error(EIO);
+                    return;
                 }
+
+                reply.entry(&ts, &attr, 0);
             },
-            None => { println!("¡Límite de archivos dentro de la carpeta alcanzado!"); reply.error(EIO); }
+            None => reply.error(ENOSPC) // “No space left on device.”
         }
     }
     // Esta funcion se encarga de eliminar un directorio
     fn rmdir(
         &mut self, 
-        _req: &Request, 
+        req: &Request, 
         parent: u64, 
         name: &OsStr, 
         reply: ReplyEmpty
     ) {
-        let name = name.to_str().unwrap();
         let inode = self.disk.find_inode_in_references_by_name(parent, name);
-        // Eliminamos el directorio de la tabla de inodos
+        // Eliminamos el directorio de su directorio padre y de la tabla de inodos, junto con los bloques de sus entradas
         match inode {
-            Some(inode) => {
+            Ok(Some(inode)) => {
+                if inode.attributes.kind != FileType::Directory {
+                    reply.error(ENOTDIR);
+                    return;
+                }
+
+                // Quitar la entrada necesita permisos de escritura y de búsqueda sobre el directorio, y en un directorio
+                // con el bit sticky ser dueño del directorio o del archivo
+                let parent_attributes = self.disk.get_inode(parent).map(|inode| &inode.attributes);
+                if let Err(errno) = check_remove_entry(&Caller::of(req), parent_attributes, &inode.attributes) {
+                    reply.error(errno);
+                    return;
+                }
+
                 let ino = inode.attributes.ino;
-                self.disk.clear_reference_in_inode(parent, ino as usize);
-                self.disk.clear_inode(ino);
+                let result = self.disk.directory_entry_count(ino).and_then(|count| match count {
+                    0 => {
+                        self.disk.remove_directory_entry(parent, name)?;
+                        self.disk.decrease_links(parent)?;
+                        self.disk.free_inode(ino)?;
+                        self.disk.commit_transaction().map(|_| true)
+                    },
+                    _ => Ok(false)
+                });
 
-                reply.ok();
+                match result {
+                    Ok(true) => reply.ok(),
+                    Ok(false) => reply.error(ENOTEMPTY),
+                    Err(e) => {
+                        println!("¡Error al eliminar el directorio! {}", e);
+                        self.disk.abort_transaction();
+                        reply.error(e.errno());
+                    }
+                }
             },
-            None => reply.error(EIO) // "Input/output error."
+            Ok(None) => reply.error(ENOENT),
+            Err(e) => {
+                println!("¡Error al leer el disco! {}", e);
+                reply.error(e.errno())
+            }
         }
     }
     // Esta funcion se encarga de abrir un archivo o directorio
     fn open(
         &mut self,
-        _req: &Request,
+        req: &Request,
         ino: u64,
         flags: u32,
         reply: ReplyOpen
@@ -317,10 +584,58 @@ impl Filesystem for QrFS {
         println!("open(ino={}, flags={})", ino, flags);
         // Buscamos el inodo 
         let inode = self.disk.get_inode(ino);
+        let caller = Caller::of(req);
 
         match inode {
-            Some(_) => reply.opened(ino, flags),
-            None => reply.error(ENOSYS)
+            Some(inode) if !has_access(&caller, &inode.attributes, open_mask(flags)) => {
+                reply.error(EACCES); // “Permission denied.”
+            },
+            Some(inode) if flags & O_TRUNC as u32 != 0 && inode.attributes.kind == FileType::RegularFile => {
+                // Abrir con O_TRUNC deja el archivo vacío, y como toda escritura le quita los bits set-user-ID y
+                // set-group-ID
+                let truncated = self.disk.truncate_file(ino, 0).and_then(|_| {
+                    if let Some(inode) = self.disk.get_inode_as_mut(ino) {
+                        drop_set_id_on_write(&caller, &mut inode.attributes);
+                    }
+                    self.disk.commit_transaction()
+                });
+                if let Err(e) = truncated {
+                    println!("¡Error al truncar el archivo! {}", e);
+                    self.disk.abort_transaction();
+                    reply.error(e.errno());
+                    return;
+                }
+                self.disk.open_handle(ino);
+                reply.opened(ino, flags)
+            },
+            Some(_) => {
+                self.disk.open_handle(ino);
+                reply.opened(ino, flags)
+            },
+            None => reply.error(ENOENT)
+        }
+    }
+    // Esta funcion se encarga de cerrar un archivo. Un archivo borrado mientras estaba abierto se libera al cerrar su
+    // último handle
+    fn release(
+        &mut self, 
+        _req: &Request, 
+        ino: u64, 
+        fh: u64, 
+        _flags: u32, 
+        _lock_owner: u64, 
+        _flush: bool, 
+        reply: ReplyEmpty
+    ) {
+        println!("release(ino={}, fh={})", ino, fh);
+        let released = self.disk.release_handle(ino).and_then(|_| self.disk.commit_transaction());
+        match released {
+            Ok(()) => reply.ok(),
+            Err(e) => {
+                println!("¡Error al liberar el archivo! {}", e);
+                self.disk.abort_transaction();
+                reply.error(e.errno());
+            }
         }
     }
     // Esta funcion se encarga de leer un archivo 
@@ -335,17 +650,37 @@ impl Filesystem for QrFS {
     ) {
         println!("read(ino={}, fh={}, offset={}, size={})", ino, fh, offset, size);
 
-        let memory_block = self.disk.get_content_as_bytes(ino as usize);
+        let offset = offset.max(0) as u64;
+        let content = self.disk.read_file(ino, offset, size);
         
-        match memory_block {
-            Some(memory_block) => reply.data(memory_block),
-            None => reply.error(EIO)
+        match content {
+            Ok(Some(content)) => reply.data(&content),
+            Ok(None) => reply.error(ENOENT),
+            Err(e) => {
+                println!("¡Error al leer el disco! {}", e);
+                reply.error(EIO)
+            }
+        }
+    }
+    // Esta funcion se encarga de abrir un directorio para listarlo, lo que necesita permiso de lectura
+    fn opendir(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        flags: u32,
+        reply: ReplyOpen
+    ) {
+        println!("opendir(ino={}, flags={})", ino, flags);
+
+        match check_list(&Caller::of(req), self.disk.get_inode(ino).map(|inode| &inode.attributes)) {
+            Ok(()) => reply.opened(ino, flags),
+            Err(errno) => reply.error(errno)
         }
     }
     // Esta funcion se encarga de leer un directorio
     fn readdir(
         &mut self, 
-        _req: &Request, 
+        req: &Request, 
         ino: u64, 
         fh: u64, 
         offset: i64, 
@@ -353,56 +688,45 @@ impl Filesystem for QrFS {
     ) {
         println!("readdir(ino={}, fh={}, offset={})", ino, fh, offset);
 
-        // Pequeño "ajuste técnico" para mostrar el "." y ".." en la primera carpeta.
-        if ino == 1 {
-            if offset == 0 {
-                reply.add(1, 0, FileType::Directory, ".");
-                reply.add(1, 1, FileType::Directory, "..");
-            }
+        // Listar las entradas necesita permiso de lectura, como al abrir el directorio con opendir
+        if let Err(errno) = check_list(&Caller::of(req), self.disk.get_inode(ino).map(|inode| &inode.attributes)) {
+            reply.error(errno);
+            return;
         }
 
-        // Inodo "padre" (el directorio)
-        let inode: Option<&Inode> = self.disk.get_inode(ino);
+        let entries = match self.disk.directory_entries(ino) {
+            Ok(Some(entries)) => entries,
+            Ok(None) => { println!("ERROR ino={:?}", ino); reply.error(ENOENT); return; },
+            Err(e) => {
+                println!("¡Error al leer el disco! {}", e);
+                reply.error(e.errno());
+                return;
+            }
+        };
 
-        // Desplazamiento representado como el tamaño entero del Inodo, ya que todo el contenido se leerá a la vez
-        // del directorio. Si el desplazamiento es el mismo que el tamaño del inodo principal, haga clic en "Aceptar" y devuelva el contenido.
-        if mem::size_of_val(&inode) == offset as usize {
-            reply.ok();
-            return;
+        // Pequeño "ajuste técnico" para mostrar el "." y ".." en la primera carpeta.
+        let mut listing = Vec::with_capacity(entries.len() + 2);
+        if ino == 1 {
+            listing.push((1, FileType::Directory, OsString::from(".")));
+            listing.push((1, FileType::Directory, OsString::from("..")));
         }
+        listing.extend(entries.into_iter().map(|entry| (entry.ino, entry.kind, entry.name)));
 
-        match inode {
-            Some(inode) => {
-                let references = inode.references;
-                // Ciclo a través del vector de referencia del inodo padre. Cada posición indica un archivo que está presente
-                // en el directorio.
-                for ino in references.iter() {
-
-                    if let Some(ino) = ino {
-                        let inode = self.disk.get_inode(*ino as u64);
-
-                        if let Some(inode_data) = inode {
-                            if inode_data.attributes.ino == 1 {
-                                continue;
-                            }
-
-                            let name = inode_data.name.iter().collect::<String>();
-                            println!("    - readdir(ino={}, name={})", inode_data.attributes.ino, name);
-                            let offset = mem::size_of_val(&inode) as i64;
-                            reply.add(inode_data.attributes.ino, offset, inode_data.attributes.kind, name);
-                        }
-                    }
-                }
-
-                reply.ok()
-            },
-            None => { println!("ERROR ino={:?}", ino); reply.error(ENOENT) }
+        // El desplazamiento de cada entrada es su posición en la lista más uno, para que la siguiente llamada continúe
+        // después de la última entrada que entró en la respuesta
+        for (position, (entry_ino, kind, name)) in listing.iter().enumerate().skip(offset.max(0) as usize) {
+            println!("    - readdir(ino={}, name={:?})", entry_ino, name);
+            if reply.add(*entry_ino, (position + 1) as i64, *kind, name) {
+                break;
+            }
         }
+
+        reply.ok()
     }
     // Esta funcion se encarga de escribir datos
     fn write(
         &mut self, 
-        _req: &Request, 
+        req: &Request, 
         ino: u64, 
         _fh: u64, 
         offset: i64, 
@@ -411,15 +735,37 @@ impl Filesystem for QrFS {
         reply: ReplyWrite
     ) {
         println!("write(ino={}, offset={}, data={})", ino, offset, data.len());
-        let inode = self.disk.get_inode_as_mut(ino);
-        let content: Box<[u8]> = data.to_vec().into_boxed_slice();
-        // Buscamos el inode y le empezamos a escribir los bytes al disko
+        let inode = self.disk.get_inode(ino);
+        // Buscamos el inode y le escribimos los bytes al disko a partir de `offset`, bloque por bloque
         match inode {
-            Some(inode) => {
-                inode.attributes.size = data.len() as u64;
-                let index = (ino as usize) - 1;
-                self.disk.write_content_as_bytes(index, content);
-                reply.written(data.len() as u32);
+            Some(_) => {
+                let written = match self.disk.write_file(ino, offset.max(0) as u64, data) {
+                    Ok(written) => written,
+                    Err(e) => {
+                        println!("¡Error al escribir el archivo! {}", e);
+                        self.disk.abort_transaction();
+                        reply.error(e.errno());
+                        return;
+                    }
+                };
+                // Una escritura de alguien que no es root le quita al archivo los bits set-user-ID y set-group-ID
+                if written > 0 {
+                    if let Some(inode) = self.disk.get_inode_as_mut(ino) {
+                        drop_set_id_on_write(&Caller::of(req), &mut inode.attributes);
+                    }
+                }
+                if let Err(e) = self.disk.commit_transaction() {
+                    println!("¡Error al registrar la operación en la bitácora! {}", e);
+                    self.disk.abort_transaction();
+                    reply.error(EIO);
+                    return;
+                }
+
+                if written == 0 && !data.is_empty() {
+                    reply.error(ENOSPC);
+                } else {
+                    reply.written(written as u32);
+                }
             },
             None => {
                 println!("Nodo no encontrado");
@@ -430,56 +776,642 @@ impl Filesystem for QrFS {
     // Esta funcion se encarga de eliminar un archivo
     fn unlink(
         &mut self, 
-        _req: &Request, 
+        req: &Request, 
         parent: u64, 
         name: &OsStr, 
         reply: ReplyEmpty
     ) {
-        let name = name.to_str().unwrap();
         let inode = self.disk.find_inode_in_references_by_name(parent, name);
         // Buscamos el inode y lo eliminamos de la tabla, y tambien borramos los datos del disco
         match inode {
-            Some(inode) => {
+            Ok(Some(inode)) => {
                 if inode.attributes.kind == FileType::Directory {
                     reply.error(EISDIR);
-                } else {
-                    let ino = inode.attributes.ino;
-                    let memory_block_index = (ino as usize) - 1;
-                    self.disk.clear_inode(ino);
-                    self.disk.clear_memory_block(memory_block_index);
-                    self.disk.clear_reference_in_inode(parent, ino as usize);
-                    reply.ok()
+                    return;
+                }
+
+                // Quitar la entrada necesita permisos de escritura y de búsqueda sobre el directorio, y en un directorio
+                // con el bit sticky ser dueño del directorio o del archivo
+                let parent_attributes = self.disk.get_inode(parent).map(|inode| &inode.attributes);
+                if let Err(errno) = check_remove_entry(&Caller::of(req), parent_attributes, &inode.attributes) {
+                    reply.error(errno);
+                    return;
                 }
+
+                // El archivo y sus bloques se liberan cuando no le quedan enlaces ni handles abiertos
+                let ino = inode.attributes.ino;
+                let removed = self.disk.remove_directory_entry(parent, name)
+                    .and_then(|_| self.disk.decrease_links(ino));
+                if let Err(e) = removed {
+                    println!("¡Error al leer el disco! {}", e);
+                    self.disk.abort_transaction();
+                    reply.error(e.errno());
+                    return;
+                }
+
+                if let Err(e) = self.disk.commit_transaction() {
+                    println!("¡Error al registrar la operación en la bitácora! {}", e);
+                    self.disk.abort_transaction();
+                    reply.error(EIO);
+                    return;
+                }
+
+                reply.ok()
+            },
+            Ok(None) => reply.error(ENOENT),
+            Err(e) => {
+                println!("¡Error al leer el disco! {}", e);
+                reply.error(e.errno())
+            }
+        }
+    }
+    // Esta funcion se encarga de crear un enlace duro: una entrada nueva que apunta a un archivo existente
+    fn link(
+        &mut self, 
+        req: &Request, 
+        ino: u64, 
+        newparent: u64, 
+        newname: &OsStr, 
+        reply: ReplyEntry
+    ) {
+        println!("link(ino={}, newparent={}, newname={:?})", ino, newparent, newname);
+
+        let kind = match self.disk.get_inode(ino) {
+            Some(inode) => inode.attributes.kind,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+
+        // Los directorios no pueden tener enlaces duros
+        if kind == FileType::Directory {
+            reply.error(EPERM);
+            return;
+        }
+
+        match self.disk.find_inode_in_references_by_name(newparent, newname) {
+            Ok(Some(_)) => {
+                reply.error(EEXIST); // “File exists.”
+                return;
             },
-            None => reply.error(EIO)
+            Ok(None) => (),
+            Err(e) => {
+                println!("¡Error al buscar la entrada! {}", e);
+                reply.error(e.errno());
+                return;
+            }
+        }
+
+        // Agregar una entrada necesita permisos de escritura y de búsqueda sobre el directorio
+        if let Err(errno) = check_add_entry(&Caller::of(req), self.disk.get_inode(newparent).map(|inode| &inode.attributes)) {
+            reply.error(errno);
+            return;
+        }
+
+        let entry = DirectoryEntry { ino, kind, name: newname.to_os_string() };
+        if let Err(e) = self.disk.add_directory_entry(newparent, entry) {
+            println!("¡No se pudo agregar el enlace al directorio! {}", e);
+            self.disk.abort_transaction();
+            reply.error(e.errno());
+            return;
+        }
+        self.disk.increase_links(ino);
+
+        if let Err(e) = self.disk.commit_transaction() {
+            println!("¡Error al registrar la operación en la bitácora! {}", e);
+            self.disk.abort_transaction();
+            reply.error(EIO);
+            return;
+        }
+
+        match self.disk.get_inode(ino) {
+            Some(inode) => reply.entry(&time::now().to_timespec(), &inode.attributes, 0),
+            None => reply.error(ENOENT)
         }
     }
+    // Esta funcion se encarga de crear un enlace simbólico. Los destinos cortos se guardan en el inodo y los largos en
+    // sus bloques de datos
+    fn symlink(
+        &mut self, 
+        req: &Request, 
+        parent: u64, 
+        name: &OsStr, 
+        link: &Path, 
+        reply: ReplyEntry
+    ) {
+        println!("symlink(parent={}, name={:?}, link={:?})", parent, name, link);
+
+        match self.disk.find_inode_in_references_by_name(parent, name) {
+            Ok(Some(_)) => {
+                reply.error(EEXIST); // “File exists.”
+                return;
+            },
+            Ok(None) => (),
+            Err(e) => {
+                println!("¡Error al buscar la entrada! {}", e);
+                reply.error(e.errno());
+                return;
+            }
+        }
+
+        // Agregar una entrada necesita permisos de escritura y de búsqueda sobre el directorio
+        let caller = Caller::of(req);
+        if let Err(errno) = check_add_entry(&caller, self.disk.get_inode(parent).map(|inode| &inode.attributes)) {
+            reply.error(errno);
+            return;
+        }
+
+        if link.as_os_str().is_empty() {
+            reply.error(ENOENT);
+            return;
+        }
+
+        let parent_attributes = match self.disk.get_inode(parent) {
+            Some(inode) => inode.attributes,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        // Los permisos de un enlace simbólico no se usan: siempre son 0o777
+        let (uid, gid, _) = new_owner(&caller, &parent_attributes, FileType::Symlink, 0o777);
+
+        let ino = match self.disk.find_ino_available() {
+            Some(ino) => ino,
+            None => {
+                reply.error(ENOSPC); // “No space left on device.”
+                return;
+            }
+        };
+
+        let ts = time::now().to_timespec();
+        let attr = FileAttr {
+            ino,
+            size: 0,
+            blocks: 0,
+            atime: ts,
+            mtime: ts,
+            ctime: ts,
+            crtime: ts,
+            kind: FileType::Symlink,
+            perm: 0o777,
+            nlink: 1,
+            uid,
+            gid,
+            rdev: 0,
+            flags: 0,
+        };
+
+        let inode = Inode {
+            attributes: attr,
+            references: [None; 128],
+            target: None,
+            xattrs: BTreeMap::new()
+        };
+        if let Err(e) = self.disk.write_inode(inode) {
+            println!("¡No se pudo guardar el inodo del enlace simbólico! {}", e);
+            reply.error(e.errno());
+            return;
+        }
+
+        let entry = DirectoryEntry { ino, kind: FileType::Symlink, name: name.to_os_string() };
+        let added = self.disk.write_symlink_target(ino, link.as_os_str())
+            .and_then(|_| self.disk.add_directory_entry(parent, entry));
+        if let Err(e) = added {
+            println!("¡No se pudo crear el enlace simbólico! {}", e);
+            self.disk.abort_transaction();
+            reply.error(e.errno());
+            return;
+        }
+
+        if let Err(e) = self.disk.commit_transaction() {
+            println!("¡Error al registrar la operación en la bitácora! {}", e);
+            self.disk.abort_transaction();
+            reply.error(EIO);
+            return;
+        }
+
+        match self.disk.get_inode(ino) {
+            Some(inode) => reply.entry(&ts, &inode.attributes, 0),
+            None => reply.error(ENOENT)
+        }
+    }
+    // Esta funcion se encarga de leer el destino de un enlace simbólico
+    fn readlink(
+        &mut self, 
+        _req: &Request, 
+        ino: u64, 
+        reply: ReplyData
+    ) {
+        println!("readlink(ino={})", ino);
+
+        match self.disk.read_symlink_target(ino) {
+            Ok(Some(target)) => reply.data(target.as_bytes()),
+            Ok(None) => reply.error(if self.disk.get_inode(ino).is_some() { EINVAL } else { ENOENT }),
+            Err(e) => {
+                println!("¡Error al leer el disco! {}", e);
+                reply.error(e.errno())
+            }
+        }
+    }
+    // Esta funcion se encarga de crear o cambiar un atributo extendido de un archivo
+    fn setxattr(
+        &mut self, 
+        req: &Request, 
+        ino: u64, 
+        name: &OsStr, 
+        value: &[u8], 
+        flags: u32, 
+        _position: u32, 
+        reply: ReplyEmpty
+    ) {
+        println!("setxattr(ino={}, name={:?}, size={}, flags={})", ino, name, value.len(), flags);
+
+        let inode = match self.disk.get_inode(ino) {
+            Some(inode) => inode,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+
+        let allowed = xattr_namespace(name)
+            .and_then(|namespace| check_xattr_write(&Caller::of(req), &inode.attributes, namespace))
+            .and_then(|_| check_xattr_size(value.len()));
+        if let Err(errno) = allowed {
+            reply.error(errno);
+            return;
+        }
+
+        if let Err(errno) = check_xattr_flags(inode.xattrs.contains_key(name.as_bytes()), flags as i32) {
+            reply.error(errno);
+            return;
+        }
+
+        if let Err(e) = self.disk.set_xattr(ino, name, value) {
+            println!("¡No se pudo guardar el atributo! {}", e);
+            self.disk.abort_transaction();
+            reply.error(e.errno());
+            return;
+        }
+
+        if let Err(e) = self.disk.commit_transaction() {
+            println!("¡Error al registrar la operación en la bitácora! {}", e);
+            self.disk.abort_transaction();
+            reply.error(EIO);
+            return;
+        }
+
+        reply.ok()
+    }
+    // Esta funcion se encarga de leer un atributo extendido de un archivo. Con `size` 0 solo se responde su largo
+    fn getxattr(
+        &mut self, 
+        req: &Request, 
+        ino: u64, 
+        name: &OsStr, 
+        size: u32, 
+        reply: ReplyXattr
+    ) {
+        println!("getxattr(ino={}, name={:?}, size={})", ino, name, size);
+
+        let attributes = match self.disk.get_inode(ino) {
+            Some(inode) => inode.attributes,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+
+        let allowed = xattr_namespace(name)
+            .and_then(|namespace| check_xattr_read(&Caller::of(req), &attributes, namespace));
+        if let Err(errno) = allowed {
+            reply.error(errno);
+            return;
+        }
+
+        match self.disk.get_xattr(ino, name) {
+            Ok(Some(value)) => match check_xattr_buffer(value.len(), size) {
+                Ok(()) if size == 0 => reply.size(value.len() as u32),
+                Ok(()) => reply.data(&value),
+                Err(errno) => reply.error(errno)
+            },
+            Ok(None) => reply.error(ENODATA), // “No data available.”
+            Err(e) => {
+                println!("¡Error al leer el disco! {}", e);
+                reply.error(e.errno())
+            }
+        }
+    }
+    // Esta funcion se encarga de listar los atributos extendidos de un archivo, cada nombre terminado en un byte nulo
+    fn listxattr(
+        &mut self, 
+        req: &Request, 
+        ino: u64, 
+        size: u32, 
+        reply: ReplyXattr
+    ) {
+        println!("listxattr(ino={}, size={})", ino, size);
+
+        if self.disk.get_inode(ino).is_none() {
+            reply.error(ENOENT);
+            return;
+        }
+
+        let caller = Caller::of(req);
+        let mut list = Vec::new();
+        for name in self.disk.xattr_names(ino) {
+            if xattr_namespace(&name).is_ok_and(|namespace| lists_xattr(&caller, namespace)) {
+                list.extend_from_slice(name.as_bytes());
+                list.push(0);
+            }
+        }
+
+        match check_xattr_buffer(list.len(), size) {
+            Ok(()) if size == 0 => reply.size(list.len() as u32),
+            Ok(()) => reply.data(&list),
+            Err(errno) => reply.error(errno)
+        }
+    }
+    // Esta funcion se encarga de quitar un atributo extendido de un archivo
+    fn removexattr(
+        &mut self, 
+        req: &Request, 
+        ino: u64, 
+        name: &OsStr, 
+        reply: ReplyEmpty
+    ) {
+        println!("removexattr(ino={}, name={:?})", ino, name);
+
+        let attributes = match self.disk.get_inode(ino) {
+            Some(inode) => inode.attributes,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+
+        let allowed = xattr_namespace(name)
+            .and_then(|namespace| check_xattr_write(&Caller::of(req), &attributes, namespace));
+        if let Err(errno) = allowed {
+            reply.error(errno);
+            return;
+        }
+
+        if !self.disk.remove_xattr(ino, name) {
+            reply.error(ENODATA); // “No data available.”
+            return;
+        }
+
+        if let Err(e) = self.disk.commit_transaction() {
+            println!("¡Error al registrar la operación en la bitácora! {}", e);
+            self.disk.abort_transaction();
+            reply.error(EIO);
+            return;
+        }
+
+        reply.ok()
+    }
+    // Esta funcion se encarga de cambiar el nombre de un archivo o directorio, o de moverlo a otro directorio
+    fn rename(
+        &mut self, 
+        req: &Request, 
+        parent: u64, 
+        name: &OsStr, 
+        newparent: u64, 
+        newname: &OsStr, 
+        reply: ReplyEmpty
+    ) {
+        println!("rename(parent={}, name={:?}, newparent={}, newname={:?})", parent, name, newparent, newname);
+
+        let source = match self.disk.find_inode_in_references_by_name(parent, name) {
+            Ok(Some(inode)) => inode.attributes,
+            Ok(None) => {
+                reply.error(ENOENT);
+                return;
+            },
+            Err(e) => {
+                println!("¡Error al buscar la entrada! {}", e);
+                reply.error(e.errno());
+                return;
+            }
+        };
+
+        match self.disk.get_inode(newparent) {
+            Some(inode) if inode.attributes.kind == FileType::Directory => (),
+            Some(_) => {
+                reply.error(ENOTDIR);
+                return;
+            },
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        }
+
+        let target = match self.disk.find_inode_in_references_by_name(newparent, newname) {
+            Ok(inode) => inode.map(|inode| (inode.attributes.ino, inode.attributes.kind)),
+            Err(e) => {
+                println!("¡Error al buscar la entrada! {}", e);
+                reply.error(e.errno());
+                return;
+            }
+        };
+
+        let (ino, kind) = (source.ino, source.kind);
+
+        // Si el destino ya es el mismo archivo no hay nada que cambiar
+        if target.map(|(target_ino, _)| target_ino) == Some(ino) {
+            reply.ok();
+            return;
+        }
+
+        // Mover la entrada la quita de su directorio y la agrega al nuevo, que pierde la entrada que se reemplaza. Un
+        // directorio que cambia de padre además necesita permiso de escritura para cambiar su entrada ".."
+        let caller = Caller::of(req);
+        let newparent_attributes = self.disk.get_inode(newparent).map(|inode| &inode.attributes);
+        let permitted = check_remove_entry(&caller, self.disk.get_inode(parent).map(|inode| &inode.attributes), &source)
+            .and_then(|_| check_add_entry(&caller, newparent_attributes))
+            .and_then(|_| match target.and_then(|(target_ino, _)| self.disk.get_inode(target_ino)) {
+                Some(replaced) => check_remove_entry(&caller, newparent_attributes, &replaced.attributes),
+                None => Ok(())
+            })
+            .and_then(|_| match kind == FileType::Directory && parent != newparent && !has_access(&caller, &source, W_OK) {
+                true => Err(EACCES),
+                false => Ok(())
+            });
+        if let Err(errno) = permitted {
+            reply.error(errno);
+            return;
+        }
+
+        // Un directorio no se puede mover adentro de sí mismo ni de alguno de sus subdirectorios
+        if kind == FileType::Directory {
+            match self.disk.directory_contains(ino, newparent) {
+                Ok(false) => (),
+                Ok(true) => {
+                    reply.error(EINVAL);
+                    return;
+                },
+                Err(e) => {
+                    println!("¡Error al leer el disco! {}", e);
+                    reply.error(e.errno());
+                    return;
+                }
+            }
+        }
+
+        // Un directorio solo reemplaza a otro directorio, que además debe estar vacío, y un archivo no reemplaza a un
+        // directorio
+        if let Some((target_ino, target_kind)) = target {
+            let errno = match (kind == FileType::Directory, target_kind == FileType::Directory) {
+                (true, false) => Some(ENOTDIR),
+                (false, true) => Some(EISDIR),
+                (true, true) => match self.disk.directory_entry_count(target_ino) {
+                    Ok(0) => None,
+                    Ok(_) => Some(ENOTEMPTY),
+                    Err(e) => {
+                        println!("¡Error al leer el disco! {}", e);
+                        Some(e.errno())
+                    }
+                },
+                (false, false) => None
+            };
+
+            if let Some(errno) = errno {
+                reply.error(errno);
+                return;
+            }
+        }
+
+        // El destino se reemplaza en su misma entrada y todos los cambios van en una sola transacción de la bitácora,
+        // así que el destino siempre existe, con el archivo anterior o con el nuevo. El archivo reemplazado pierde un
+        // enlace y un directorio reemplazado, que está vacío, se libera junto con su entrada ".."
+        let entry = DirectoryEntry { ino, kind, name: newname.to_os_string() };
+        let moved = match target {
+            Some((target_ino, FileType::Directory)) => self.disk.replace_directory_entry(newparent, entry)
+                .and_then(|_| self.disk.free_inode(target_ino))
+                .and_then(|_| self.disk.decrease_links(newparent)),
+            Some((target_ino, _)) => self.disk.replace_directory_entry(newparent, entry)
+                .and_then(|_| self.disk.decrease_links(target_ino)),
+            None => self.disk.add_directory_entry(newparent, entry)
+        };
+
+        // La entrada ".." de un directorio que cambia de padre pasa a contar como enlace del padre nuevo
+        let moved = moved.and_then(|_| self.disk.remove_directory_entry(parent, name)).and_then(|_| {
+            if kind == FileType::Directory && parent != newparent {
+                self.disk.decrease_links(parent)?;
+                self.disk.increase_links(newparent);
+            }
+            Ok(())
+        });
+
+        // Un paso que falla deja el cambio a medias: se deshace entero antes de responder
+        if let Err(e) = moved {
+            println!("¡No se pudo mover la entrada! {}", e);
+            self.disk.abort_transaction();
+            reply.error(e.errno());
+            return;
+        }
+
+        if let Err(e) = self.disk.commit_transaction() {
+            println!("¡Error al registrar la operación en la bitácora! {}", e);
+            self.disk.abort_transaction();
+            reply.error(EIO);
+            return;
+        }
+
+        reply.ok()
+    }
+}
+/// Interpreta la opción `--shares K/N`: la llave se reparte en N partes de las que bastan K.
+fn parse_shares(value: &str) -> Option<(u8, u8)> {
+    let (threshold, total) = value.split_once('/')?;
+    let threshold: u8 = threshold.parse().ok()?;
+    let total: u8 = total.parse().ok()?;
+
+    if threshold >= 1 && threshold <= total {
+        Some((threshold, total))
+    } else {
+        None
+    }
+}
+
+/// Imágenes PNG de los códigos QR que guardan `contents`. El contenido se reparte en partes de hasta
+/// `QR_CODE_CAPACITY` bytes, una por código, que unidas en orden lo vuelven a formar.
+fn qr_code_pages(contents: &[u8]) -> Result<Vec<Vec<u8>>, QRCodeError> {
+    contents.chunks(QR_CODE_CAPACITY)
+        .map(|part| qrcode_generator::to_png_to_vec(part, QrCodeEcc::Low, 1024))
+        .collect()
 }
-// Esta funcion se encarga de obtener los bits de un archivo y ponerlos en una structura Vectorial
-fn get_file_as_byte_vec(filename: &String) -> Vec<u8> {
-    let mut f = File::open(&filename).expect("no file found");
-    let metadata = fs::metadata(&filename).expect("unable to read metadata");
-    let mut buffer = vec![0; metadata.len() as usize];
-    f.read(&mut buffer).expect("buffer overflow");
-
-    buffer
+
+/// Guarda los códigos QR de `contents` en `mountpoint` como `<name>-<parte>-de-<total>.png`. Devuelve la cantidad de
+/// códigos, o el mensaje para el usuario si alguno no se pudo generar o guardar.
+fn write_qr_codes(mountpoint: &str, name: &str, contents: &[u8]) -> Result<usize, String> {
+    let pages = qr_code_pages(contents)
+        .map_err(|e| format!("No se pudo generar el Codigo QR de {}: {}", name, e))?;
+
+    let total = pages.len();
+    for (index, page) in pages.into_iter().enumerate() {
+        if image::load_from_memory_with_format(&page, ImageFormat::Png).is_err() {
+            return Err(format!("El Codigo QR {} de {} de {} no es una imagen PNG válida", index + 1, total, name));
+        }
+        let path = format!("{}/{}-{}-de-{}.png", mountpoint, name, index + 1, total);
+        std::fs::write(&path, page).map_err(|e| format!("No se pudo guardar {}: {}", path, e))?;
+    }
+
+    Ok(total)
 }
-#[allow(unused_must_use)]
+
+/// Guarda la parte `share` de la llave maestra como una hoja `llave-i-de-n.png` con su código QR y, al lado, un
+/// `llave-i-de-n.txt` que dice qué parte es y cuántas hacen falta, porque eso no se ve en la imagen.
+fn write_key_share(mountpoint: &str, share: &KeyShare) -> Result<(), String> {
+    let page = qrcode_generator::to_png_to_vec(share.to_text(), QrCodeEcc::Medium, 512)
+        .map_err(|e| format!("No se pudo generar el Codigo QR de la llave: {}", e))?;
+
+    let path = format!("{}/llave-{}-de-{}", mountpoint, share.index, share.total);
+    std::fs::write(format!("{}.png", path), page).map_err(|e| format!("No se pudo guardar {}.png: {}", path, e))?;
+    std::fs::write(format!("{}.txt", path), format!("{}\n", share.label()))
+        .map_err(|e| format!("No se pudo guardar {}.txt: {}", path, e))
+}
+
+/// Muestra las formas de invocar fsck_qrfs.
+fn print_usage() {
+    println!("Usage: {} <MOUNTPOINT> [--key-file PATH] [--shares K/N] [--repair]", env::args().next().unwrap());
+    println!("       sin --repair el disco solo se revisa y los arreglos pendientes se informan como problemas");
+}
+
 fn main() {
     //Obtiene el pathfile donde se encuentra el file system
     let mountpoint = match env::args().nth(1) {
         Some(path) => path,
         None => {
-            println!("Usage: {} <MOUNTPOINT>", env::args().nth(0).unwrap());
+            print_usage();
             return;
         }
     };
+    let mut key_file = None;
+    let mut shares = None;
+    let mut repair = false;
+    let mut args = env::args().skip(2);
+    while let Some(option) = args.next() {
+        if option == "--repair" {
+            repair = true;
+            continue;
+        }
+        match (option.as_str(), args.next()) {
+            ("--key-file", Some(path)) => key_file = Some(path),
+            ("--shares", Some(value)) if parse_shares(&value).is_some() => shares = parse_shares(&value),
+            _ => {
+                print_usage();
+                return;
+            }
+        }
+    }
     // guardamos los pathfile defaults obtenidos con el mountpoint
     let disk_file_path = format!("{}/disco.qrfs",  mountpoint);
     let inode_table_file_path = format!("{}/inode.qrfs",  mountpoint);
-    let document_file_path = format!("{}/disco.txt",  mountpoint);
-    let qrcode_file_path = format!("{}/disco.png",mountpoint);
-    let qrcode_file_path2 = format!("{}/inode.png",mountpoint);
 
     // Pregunta si un archivo existe, si no retorna
     if !(Path::new(&disk_file_path).exists()){
@@ -489,50 +1421,156 @@ fn main() {
         println!("No se encuentra el i-node del filesytem")
         
     } else{
-        let mut s=String::new();
-        print!("Please enter your phrase: ");
-        let _=stdout().flush();
-        stdin().read_line(&mut s).expect("Did not enter a correct string");
-        if let Some('\n')=s.chars().next_back() {
-            s.pop();
-        }
-        if let Some('\r')=s.chars().next_back() {
-            s.pop();
+        // Con `--key-file` el disco se abre con un archivo de llave en lugar de pedir la frase
+        let credential = match key_file {
+            Some(path) => match Credential::from_key_file(&path) {
+                Ok(credential) => credential,
+                Err(e) => {
+                    println!("No se pudo leer el archivo de llave {}: {}", path, e);
+                    return;
+                }
+            },
+            None => {
+                let mut s=String::new();
+                print!("Please enter your phrase: ");
+                let _=stdout().flush();
+                stdin().read_line(&mut s).expect("Did not enter a correct string");
+                if let Some('\n')=s.chars().next_back() {
+                    s.pop();
+                }
+                if let Some('\r')=s.chars().next_back() {
+                    s.pop();
+                }
+                Credential::Phrase(s)
+            }
+        };
+
+        // Sin `--repair` el disco se revisa sin escribir en sus archivos: lo que la carga arreglaría (la bitácora sin
+        // aplicar, los mapas de bits dañados, los archivos sin enlaces o la migración del formato original) se informa
+        // como un problema. Con `--repair` el disco se carga como al montarlo y esos arreglos se guardan.
+        let loaded = match repair {
+            true => QrFS::new(mountpoint.clone(), credential).map(|l| (l, Vec::new())),
+            false => QrFS::open_read_only(mountpoint.clone(), credential)
+        };
+
+        // Un disco que no se puede cargar (número mágico, versión o geometría inválidos) se reporta y se termina
+        let (l, repairs) = match loaded {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                println!("QrFS Invalido: {}", e);
+                if let DiskError::NeedsRepair(_) = e {
+                    println!("Ejecute fsck_qrfs con --repair para arreglarlo.");
+                }
+                std::process::exit(1);
+            }
+        };
+
+        let mut problems = l.disk.check_consistency();
+        problems.extend(repairs.into_iter().map(|repair| format!("{} (se arregla con --repair)", repair)));
+        if !problems.is_empty() {
+            println!("QrFS Invalido! Se encontraron {} problemas:", problems.len());
+            for problem in problems.iter() {
+                println!("    - {}", problem);
+            }
+            std::process::exit(1);
         }
 
-        let l = QrFS::new(mountpoint.clone(),s.clone());
-        let write_file = File::create(document_file_path.clone()).unwrap();
-        let mut writer = BufWriter::new(&write_file);
+        // Un enlace simbólico roto no daña el disco, solo se avisa
+        for warning in l.disk.dangling_symlinks() {
+            println!("Advertencia: {}", warning);
+        }
 
         println!("QrFS Valido!");
         println!("Creando el archivo imprimible");
-        write!(&mut writer, "{:?}", l.disk);
-        let contents = get_file_as_byte_vec(&disk_file_path);
-        let contents2 = get_file_as_byte_vec(&inode_table_file_path);
-        
-        // Generamos un vector de u8 con los datos del codigo QR, utilizando los datos de cada archivo
-        let result: Vec<u8> = qrcode_generator::to_png_to_vec(contents, QrCodeEcc::Low, 1024).unwrap();
-        let result2: Vec<u8> = qrcode_generator::to_png_to_vec(contents2, QrCodeEcc::Low, 1024).unwrap();
-        
-        //println!("{:?}", result);
-        // Guardamos los vectores de u8 en archivos de formato imagen
-        match image::load_from_memory_with_format(&result, ImageFormat::Png) {
-            Ok(_img) => {
-                println!("Creando el Codigo QR para el Disco");
-                std::fs::write(qrcode_file_path, result).unwrap();
+        // Los archivos tienen una casilla fija por inodo y por bloque; solo se imprimen las que están escritas
+        let (contents2, contents) = match l.disk.image_extents() {
+            Ok(extents) => extents,
+            Err(e) => {
+                println!("No se pudo leer el disco: {}", e);
+                std::process::exit(1);
             }
-            Err(_) => {
-                println!("input is not png");
+        };
+
+        // Cada archivo se reparte en tantos códigos QR como haga falta, que se guardan como imágenes numeradas
+        println!("Creando los Codigos QR para el Disco");
+        match write_qr_codes(&mountpoint, "disco", &contents) {
+            Ok(total) => println!("Se crearon {} Codigos QR para el Disco", total),
+            Err(e) => {
+                println!("{}", e);
+                std::process::exit(1);
             }
         }
-        match image::load_from_memory_with_format(&result2, ImageFormat::Png) {
-            Ok(_img) => {
-                println!("Creando el Codigo QR para la tabla I-Node");
-                std::fs::write(qrcode_file_path2, result2).unwrap();
+        println!("Creando los Codigos QR para la tabla I-Node");
+        match write_qr_codes(&mountpoint, "inode", &contents2) {
+            Ok(total) => println!("Se crearon {} Codigos QR para la tabla I-Node", total),
+            Err(e) => {
+                println!("{}", e);
+                std::process::exit(1);
             }
-            Err(_) => {
-                println!("input is not png");
+        }
+
+        // Con `--shares` la llave maestra se reparte en hojas aparte, cada una con su propio código QR
+        if let Some((threshold, total)) = shares {
+            for share in l.disk.split_key(threshold, total) {
+                println!("Creando el Codigo QR: {}", share.label());
+                if let Err(e) = write_key_share(&mountpoint, &share) {
+                    println!("{}", e);
+                    std::process::exit(1);
+                }
             }
         }
     }   
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imagen::INODE_SLOT_SIZE;
+    use crate::pruebas::{test_credential, test_directory};
+
+    #[test]
+    fn exports_default_geometry_image() {
+        // Geometría por defecto de mkfs_qrfs: 1 GiB con bloques de 4096 bytes y 1024 inodos
+        let block_size = 4096;
+        let max_files = 1024;
+        let inode_table_size = (max_files * INODE_SLOT_SIZE).div_ceil(block_size) * block_size;
+        let block_count = (1024 * 1024 * 1024 - inode_table_size) / block_size;
+
+        let mountpoint = test_directory("fsck-geometria-por-defecto").to_string_lossy().into_owned();
+        let disk = Disk::create(mountpoint.clone(), block_size, block_count, max_files, test_credential()).unwrap();
+        assert!(disk.check_consistency().is_empty());
+
+        let (inodes, data) = disk.image_extents().unwrap();
+        let inode_codes = write_qr_codes(&mountpoint, "inode", &inodes).unwrap();
+        assert_eq!(inode_codes, inodes.len().div_ceil(QR_CODE_CAPACITY));
+        let disk_codes = write_qr_codes(&mountpoint, "disco", &data).unwrap();
+        assert!(Path::new(&format!("{}/disco-{}-de-{}.png", mountpoint, disk_codes, disk_codes)).exists());
+
+        std::fs::remove_dir_all(&mountpoint).unwrap();
+    }
+
+    #[test]
+    fn key_shares_are_labeled_next_to_their_codes() {
+        let mountpoint = test_directory("fsck-partes-de-la-llave").to_string_lossy().into_owned();
+        let disk = Disk::create(mountpoint.clone(), 512, 64, 16, test_credential()).unwrap();
+        for share in disk.split_key(2, 3) {
+            write_key_share(&mountpoint, &share).unwrap();
+        }
+
+        for index in 1..=3 {
+            assert!(Path::new(&format!("{}/llave-{}-de-3.png", mountpoint, index)).exists());
+            let label = std::fs::read_to_string(format!("{}/llave-{}-de-3.txt", mountpoint, index)).unwrap();
+            assert!(label.contains(&format!("Parte {} de 3", index)));
+            assert!(label.contains("se necesitan 2"));
+        }
+
+        std::fs::remove_dir_all(&mountpoint).unwrap();
+    }
+
+    #[test]
+    fn splits_contents_larger_than_one_code() {
+        let contents = vec![0xa5; QR_CODE_CAPACITY + 1];
+        assert_eq!(qr_code_pages(&contents).unwrap().len(), 2);
+        assert_eq!(qr_code_pages(&contents[..QR_CODE_CAPACITY]).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::BTreeSet; //Libreria para el manejo de conjuntos
+
+// Bytes de cada parte del mapa que se guarda por separado en la imagen
+pub const BITMAP_CHUNK_SIZE: usize = 4096;
+// Bits de cada palabra del mapa
+const WORD_BITS: usize = 64;
+// Palabras de cada parte del mapa
+const CHUNK_WORDS: usize = BITMAP_CHUNK_SIZE * 8 / WORD_BITS;
+
+/// Mapa de bits con las posiciones ocupadas de una tabla (los inodos o los bloques del disco). Recuerda la primera
+/// palabra que tiene algún bit libre, así que encontrar una posición libre no recorre la tabla: todas las palabras
+/// anteriores están llenas. Reservar y liberar posiciones cuesta O(1) amortizado.
+///
+/// El mapa se guarda en partes de `BITMAP_CHUNK_SIZE` bytes y recuerda qué partes cambiaron desde la última vez que se
+/// guardaron.
+#[derive(Debug)]
+pub struct Bitmap {
+    words: Vec<u64>,
+    len: usize,
+    // Primera palabra con algún bit libre; todas las anteriores están llenas
+    first_free_word: usize,
+    free: usize,
+    // Partes del mapa cambiadas desde la última vez que se guardaron
+    dirty_chunks: BTreeSet<usize>
+}
+
+impl Bitmap {
+    /// Crea un mapa de `len` posiciones, todas libres.
+    pub fn new(len: usize) -> Bitmap {
+        let mut words = vec![0u64; len.div_ceil(WORD_BITS)];
+
+        // Los bits que sobran en la última palabra quedan ocupados para que nunca se entreguen
+        if !len.is_multiple_of(WORD_BITS) {
+            if let Some(last) = words.last_mut() {
+                *last = !0u64 << (len % WORD_BITS);
+            }
+        }
+
+        Bitmap {
+            words,
+            len,
+            first_free_word: 0,
+            free: len,
+            dirty_chunks: BTreeSet::new()
+        }
+    }
+
+    /// Arma un mapa de `len` posiciones a partir de sus partes guardadas, en orden. Devuelve `None` si las partes no
+    /// tienen el tamaño que corresponde a `len`.
+    pub fn from_chunks(len: usize, chunks: &[Vec<u8>]) -> Option<Bitmap> {
+        let mut bitmap = Bitmap::new(len);
+        if chunks.len() != bitmap.chunk_count() {
+            return None;
+        }
+
+        for (chunk, bytes) in chunks.iter().enumerate() {
+            let words = bitmap.chunk_words(chunk);
+            if bytes.len() != words.len() * 8 {
+                return None;
+            }
+
+            for (position, word) in bytes.chunks(8).enumerate() {
+                let mut raw = [0u8; 8];
+                raw.copy_from_slice(word);
+                bitmap.words[words.start + position] |= u64::from_le_bytes(raw);
+            }
+        }
+
+        bitmap.free = bitmap.words.iter().map(|word| word.count_zeros() as usize).sum();
+        bitmap.first_free_word = 0;
+        bitmap.skip_full_words();
+        Some(bitmap)
+    }
+
+    /// Cantidad de posiciones del mapa.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Cantidad de posiciones libres.
+    pub fn free(&self) -> usize {
+        self.free
+    }
+
+    /// Indica si la posición `index` está ocupada.
+    pub fn is_set(&self, index: usize) -> bool {
+        index < self.len && self.words[index / WORD_BITS] & (1 << (index % WORD_BITS)) != 0
+    }
+
+    /// Marca la posición `index` como ocupada.
+    pub fn set(&mut self, index: usize) {
+        if index >= self.len || self.is_set(index) {
+            return;
+        }
+
+        self.words[index / WORD_BITS] |= 1 << (index % WORD_BITS);
+        self.free -= 1;
+        self.dirty_chunks.insert(index / WORD_BITS / CHUNK_WORDS);
+        self.skip_full_words();
+    }
+
+    /// Marca la posición `index` como libre.
+    pub fn clear(&mut self, index: usize) {
+        if !self.is_set(index) {
+            return;
+        }
+
+        self.words[index / WORD_BITS] &= !(1 << (index % WORD_BITS));
+        self.free += 1;
+        self.dirty_chunks.insert(index / WORD_BITS / CHUNK_WORDS);
+        self.first_free_word = self.first_free_word.min(index / WORD_BITS);
+    }
+
+    /// Devuelve la primera posición libre, si queda alguna, sin ocuparla.
+    pub fn first_free(&self) -> Option<usize> {
+        let word = self.words.get(self.first_free_word)?;
+        let index = self.first_free_word * WORD_BITS + (!word).trailing_zeros() as usize;
+        if index < self.len { Some(index) } else { None }
+    }
+
+    /// Avanza la primera palabra libre más allá de las palabras llenas.
+    fn skip_full_words(&mut self) {
+        while self.words.get(self.first_free_word).is_some_and(|word| *word == !0u64) {
+            self.first_free_word += 1;
+        }
+    }
+
+    /// Cantidad de partes en las que se guarda el mapa.
+    pub fn chunk_count(&self) -> usize {
+        self.words.len().div_ceil(CHUNK_WORDS)
+    }
+
+    /// Palabras que forman la parte `chunk` del mapa.
+    fn chunk_words(&self, chunk: usize) -> std::ops::Range<usize> {
+        let start = chunk * CHUNK_WORDS;
+        start..(start + CHUNK_WORDS).min(self.words.len())
+    }
+
+    /// Contenido de la parte `chunk` del mapa, para guardarla.
+    pub fn chunk(&self, chunk: usize) -> Vec<u8> {
+        self.words[self.chunk_words(chunk)].iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    /// Partes cambiadas desde la última vez que se guardaron.
+    pub fn dirty_chunks(&self) -> &BTreeSet<usize> {
+        &self.dirty_chunks
+    }
+
+    /// Da por guardadas todas las partes cambiadas.
+    pub fn clear_dirty_chunks(&mut self) {
+        self.dirty_chunks.clear();
+    }
+
+    /// Marca todas las partes como cambiadas, para que se guarde el mapa completo.
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty_chunks = (0..self.chunk_count()).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Posiciones de cada parte del mapa
+    const CHUNK_BITS: usize = BITMAP_CHUNK_SIZE * 8;
+
+    #[test]
+    fn set_and_clear_track_the_first_free_position() {
+        let mut bitmap = Bitmap::new(130);
+        assert_eq!((bitmap.len(), bitmap.free(), bitmap.first_free()), (130, 130, Some(0)));
+
+        for index in 0..70 {
+            bitmap.set(index);
+        }
+        assert_eq!((bitmap.free(), bitmap.first_free()), (60, Some(70)));
+
+        // Ocupar dos veces o fuera del mapa no cambia nada
+        bitmap.set(5);
+        bitmap.set(130);
+        bitmap.clear(100);
+        assert_eq!(bitmap.free(), 60);
+        assert!(!bitmap.is_set(130));
+
+        bitmap.clear(3);
+        assert_eq!((bitmap.free(), bitmap.first_free()), (61, Some(3)));
+        bitmap.set(3);
+
+        // Los bits que sobran en la última palabra nunca se entregan
+        for index in 70..130 {
+            bitmap.set(index);
+        }
+        assert_eq!((bitmap.free(), bitmap.first_free()), (0, None));
+    }
+
+    #[test]
+    fn chunks_round_trip_and_remember_which_changed() {
+        let len = CHUNK_BITS + 1000;
+        let mut bitmap = Bitmap::new(len);
+        assert_eq!(bitmap.chunk_count(), 2);
+        assert!(bitmap.dirty_chunks().is_empty());
+
+        bitmap.set(7);
+        bitmap.set(CHUNK_BITS + 9);
+        bitmap.clear_dirty_chunks();
+        bitmap.set(CHUNK_BITS + 500);
+        assert_eq!(bitmap.dirty_chunks().iter().copied().collect::<Vec<_>>(), vec![1]);
+
+        let chunks: Vec<Vec<u8>> = (0..bitmap.chunk_count()).map(|chunk| bitmap.chunk(chunk)).collect();
+        assert_eq!(chunks[0].len(), BITMAP_CHUNK_SIZE);
+
+        let loaded = Bitmap::from_chunks(len, &chunks).unwrap();
+        assert_eq!(loaded.free(), len - 3);
+        assert_eq!(loaded.first_free(), Some(0));
+        assert!([7, CHUNK_BITS + 9, CHUNK_BITS + 500].iter().all(|index| loaded.is_set(*index)));
+        assert!(loaded.dirty_chunks().is_empty());
+
+        // Las partes tienen que corresponder a la cantidad de posiciones
+        assert!(Bitmap::from_chunks(len, &chunks[..1]).is_none());
+        assert!(Bitmap::from_chunks(len + CHUNK_BITS, &chunks).is_none());
+        assert!(Bitmap::from_chunks(len, &[chunks[0].clone(), vec![0; 8]]).is_none());
+
+        bitmap.mark_all_dirty();
+        assert_eq!(bitmap.dirty_chunks().len(), 2);
+    }
+
+    #[test]
+    fn loading_full_words_starts_the_search_after_them() {
+        let mut full = Bitmap::new(200);
+        for index in 0..150 {
+            full.set(index);
+        }
+
+        let loaded = Bitmap::from_chunks(200, &[full.chunk(0)]).unwrap();
+        assert_eq!((loaded.free(), loaded.first_free()), (50, Some(150)));
+    }
+}
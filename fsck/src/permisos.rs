@@ -0,0 +1,428 @@
+use fuse::{FileAttr, FileType, Request}; //Libreria para el manejo del FileSytem en User Space
+use libc::{R_OK, W_OK, X_OK, O_ACCMODE, O_RDONLY, O_WRONLY, O_RDWR, O_TRUNC, EACCES, ENODATA, ENOENT, ENOTDIR, EPERM}; //Libreria para el manejo de banderas del codigo en C
+use crate::atributos::Namespace; //Libreria propietaria del proyecto para los atributos extendidos
+
+// Bits de permisos que se guardan en el inodo: lectura, escritura y ejecución del dueño, el grupo y los demás, más los
+// bits set-user-ID, set-group-ID y sticky
+const PERMISSION_BITS: u32 = 0o7777;
+// Bit set-user-ID
+const SET_UID: u16 = 0o4000;
+// Bit set-group-ID
+const SET_GID: u16 = 0o2000;
+// Bit sticky: en un directorio, solo el dueño de una entrada, el del directorio y root pueden quitarla
+const STICKY: u16 = 0o1000;
+// Bits de ejecución del dueño, el grupo y los demás
+const EXECUTE_BITS: u16 = 0o111;
+// Bit de ejecución del grupo
+const GROUP_EXECUTE: u16 = 0o010;
+// Usuario que no está sujeto a los permisos
+const ROOT_UID: u32 = 0;
+
+/// Usuario y grupos del proceso que hace una operación. El usuario y el grupo principal vienen en el `Request` de fuse;
+/// los grupos suplementarios no, y se leen de `/proc/<pid>/status`.
+#[derive(Debug, Clone)]
+pub struct Caller {
+    pub uid: u32,
+    pub gid: u32,
+    pub groups: Vec<u32>
+}
+
+impl Caller {
+    /// Usuario y grupos del proceso que hizo el pedido `req`. Si el proceso ya terminó o `/proc` no se puede leer, solo
+    /// cuenta su grupo principal.
+    pub fn of(req: &Request) -> Caller {
+        let groups = std::fs::read_to_string(format!("/proc/{}/status", req.pid()))
+            .map(|status| supplementary_groups(&status))
+            .unwrap_or_default();
+        Caller { uid: req.uid(), gid: req.gid(), groups }
+    }
+
+    fn is_root(&self) -> bool {
+        self.uid == ROOT_UID
+    }
+
+    fn owns(&self, attributes: &FileAttr) -> bool {
+        self.uid == attributes.uid
+    }
+
+    fn in_group(&self, gid: u32) -> bool {
+        self.gid == gid || self.groups.contains(&gid)
+    }
+}
+
+/// Grupos suplementarios de la línea `Groups:` del contenido `status` de `/proc/<pid>/status`.
+fn supplementary_groups(status: &str) -> Vec<u32> {
+    status.lines()
+        .find_map(|line| line.strip_prefix("Groups:"))
+        .map(|groups| groups.split_whitespace().filter_map(|gid| gid.parse().ok()).collect())
+        .unwrap_or_default()
+}
+
+/// Permisos de un archivo nuevo a partir del `mode` que llega en `create` o `mkdir`. El núcleo ya le quitó los bits
+/// de la umask del proceso, así que solo se quitan los bits del tipo de archivo.
+pub fn permissions_from_mode(mode: u32) -> u16 {
+    (mode & PERMISSION_BITS) as u16
+}
+
+/// Dueño, grupo y permisos de un archivo nuevo de tipo `kind`, creado por `caller` con los permisos `perm` dentro del
+/// directorio `parent`. Como en los sistemas BSD y Linux, si el directorio tiene el bit set-group-ID el archivo nuevo
+/// toma su grupo, y los directorios nuevos heredan el bit.
+pub fn new_owner(caller: &Caller, parent: &FileAttr, kind: FileType, perm: u16) -> (u32, u32, u16) {
+    if parent.perm & SET_GID == 0 {
+        return (caller.uid, caller.gid, perm);
+    }
+
+    match kind {
+        FileType::Directory => (caller.uid, parent.gid, perm | SET_GID),
+        _ => (caller.uid, parent.gid, perm)
+    }
+}
+
+/// Indica si `caller` tiene sobre el archivo los permisos de `mask`, una combinación de `R_OK`, `W_OK` y `X_OK` como
+/// en `access(2)`. Se usan los bits del dueño si `caller` es el dueño, los del grupo si el grupo del archivo es su
+/// grupo principal o uno de sus grupos suplementarios y si no los de los demás. Root puede leer y escribir todo, y
+/// ejecutar lo que tenga algún bit de ejecución o sea un directorio.
+pub fn has_access(caller: &Caller, attributes: &FileAttr, mask: i32) -> bool {
+    if caller.is_root() {
+        let executable = attributes.kind == FileType::Directory || attributes.perm & EXECUTE_BITS != 0;
+        return mask & X_OK == 0 || executable;
+    }
+
+    let bits = if caller.owns(attributes) {
+        attributes.perm >> 6
+    } else if caller.in_group(attributes.gid) {
+        attributes.perm >> 3
+    } else {
+        attributes.perm
+    };
+
+    let granted = (if bits & 0o4 != 0 { R_OK } else { 0 })
+        | (if bits & 0o2 != 0 { W_OK } else { 0 })
+        | (if bits & 0o1 != 0 { X_OK } else { 0 });
+    mask & !granted & (R_OK | W_OK | X_OK) == 0
+}
+
+/// Revisa que `caller` pueda buscar nombres en el directorio `directory`, para lo que necesita permiso de ejecución.
+/// Devuelve el código de error de `open(2)`.
+pub fn check_lookup(caller: &Caller, directory: Option<&FileAttr>) -> Result<(), i32> {
+    match has_access(caller, existing_directory(directory)?, X_OK) {
+        true => Ok(()),
+        false => Err(EACCES)
+    }
+}
+
+/// Revisa que `caller` pueda listar las entradas del directorio `directory`, para lo que necesita permiso de lectura.
+/// Buscar cada nombre además necesita permiso de ejecución, que revisa `check_lookup`. Devuelve el código de error de
+/// `opendir(3)`.
+pub fn check_list(caller: &Caller, directory: Option<&FileAttr>) -> Result<(), i32> {
+    match has_access(caller, existing_directory(directory)?, R_OK) {
+        true => Ok(()),
+        false => Err(EACCES)
+    }
+}
+
+/// Revisa que `caller` pueda agregar una entrada al directorio `directory`, para lo que necesita permisos de escritura y
+/// de ejecución. Devuelve el código de error de `mkdir(2)`.
+pub fn check_add_entry(caller: &Caller, directory: Option<&FileAttr>) -> Result<(), i32> {
+    match has_access(caller, existing_directory(directory)?, W_OK | X_OK) {
+        true => Ok(()),
+        false => Err(EACCES)
+    }
+}
+
+/// Revisa que `caller` pueda quitar del directorio `directory` la entrada del archivo `file`. Hacen falta los mismos
+/// permisos que para agregarla y, si el directorio tiene el bit sticky, ser root o dueño del directorio o del archivo.
+/// Devuelve el código de error de `unlink(2)`.
+pub fn check_remove_entry(caller: &Caller, directory: Option<&FileAttr>, file: &FileAttr) -> Result<(), i32> {
+    check_add_entry(caller, directory)?;
+
+    let directory = existing_directory(directory)?;
+    if directory.perm & STICKY != 0 && !caller.is_root() && !caller.owns(directory) && !caller.owns(file) {
+        return Err(EPERM);
+    }
+    Ok(())
+}
+
+/// Permisos que hacen falta para abrir un archivo con las banderas `flags` de `open(2)`.
+pub fn open_mask(flags: u32) -> i32 {
+    let flags = flags as i32;
+    let mask = match flags & O_ACCMODE {
+        O_RDONLY => R_OK,
+        O_WRONLY => W_OK,
+        O_RDWR => R_OK | W_OK,
+        _ => R_OK | W_OK
+    };
+
+    match flags & O_TRUNC {
+        0 => mask,
+        _ => mask | W_OK
+    }
+}
+
+/// Cambia los permisos del archivo a los de `mode`, como `chmod(2)`. Solo pueden hacerlo su dueño y root. Si quien lo
+/// cambia no es root ni del grupo del archivo, el bit set-group-ID se quita. Devuelve `false` si no tiene permiso.
+pub fn change_mode(caller: &Caller, attributes: &mut FileAttr, mode: u32) -> bool {
+    if !caller.is_root() && !caller.owns(attributes) {
+        return false;
+    }
+
+    let mut perm = permissions_from_mode(mode);
+    if !caller.is_root() && !caller.in_group(attributes.gid) {
+        perm &= !SET_GID;
+    }
+    attributes.perm = perm;
+    true
+}
+
+/// Cambia el dueño y el grupo del archivo, como `chown(2)`. Solo root puede cambiar el dueño; el dueño puede cambiar
+/// el grupo a uno al que pertenece. Cambiar el dueño o el grupo de algo que no es un directorio quita los bits
+/// set-user-ID y set-group-ID. Devuelve `false` si no tiene permiso.
+pub fn change_owner(caller: &Caller, attributes: &mut FileAttr, uid: Option<u32>, gid: Option<u32>) -> bool {
+    let uid_changes = uid.is_some_and(|uid| uid != attributes.uid);
+    let gid_changes = gid.is_some_and(|gid| gid != attributes.gid);
+
+    if !caller.is_root() {
+        if uid_changes || (gid.is_some() && !caller.owns(attributes)) {
+            return false;
+        }
+        if gid_changes && !gid.is_some_and(|gid| caller.in_group(gid)) {
+            return false;
+        }
+    }
+
+    if let Some(uid) = uid { attributes.uid = uid; }
+    if let Some(gid) = gid { attributes.gid = gid; }
+    if (uid_changes || gid_changes) && attributes.kind != FileType::Directory {
+        attributes.perm &= !(SET_UID | SET_GID);
+    }
+    true
+}
+
+/// Quita los bits set-user-ID y set-group-ID de un archivo que `caller` escribe o trunca, como hace Linux: así nadie
+/// que no sea root puede dejar un ejecutable privilegiado con contenido propio. El bit set-group-ID sin permiso de
+/// ejecución del grupo marca el bloqueo obligatorio y se conserva. Devuelve `true` si cambió algún bit.
+pub fn drop_set_id_on_write(caller: &Caller, attributes: &mut FileAttr) -> bool {
+    if caller.is_root() {
+        return false;
+    }
+
+    let mut perm = attributes.perm & !SET_UID;
+    if perm & GROUP_EXECUTE != 0 {
+        perm &= !SET_GID;
+    }
+
+    let changed = perm != attributes.perm;
+    attributes.perm = perm;
+    changed
+}
+
+/// Indica si `caller` puede poner en el archivo tiempos de acceso o modificación cualesquiera. Como fuse no distingue
+/// entre fijar los tiempos y ponerlos en la hora actual, se permite al dueño, a root y a quien puede escribir el
+/// archivo.
+pub fn may_set_times(caller: &Caller, attributes: &FileAttr) -> bool {
+    caller.owns(attributes) || has_access(caller, attributes, W_OK)
+}
+
+/// Revisa que `caller` pueda leer un atributo extendido del espacio de nombres `namespace` del archivo. Los atributos
+/// `user.` solo existen en archivos regulares y directorios y se leen con permiso de lectura; los `trusted.` solo los
+/// ve root. Devuelve el código de error de `getxattr(2)`.
+pub fn check_xattr_read(caller: &Caller, attributes: &FileAttr, namespace: Namespace) -> Result<(), i32> {
+    match namespace {
+        Namespace::User if !supports_user_xattrs(attributes) => Err(ENODATA),
+        Namespace::User if !has_access(caller, attributes, R_OK) => Err(EACCES),
+        Namespace::Trusted if !caller.is_root() => Err(ENODATA),
+        _ => Ok(())
+    }
+}
+
+/// Revisa que `caller` pueda crear, cambiar o quitar un atributo extendido del espacio de nombres `namespace` del
+/// archivo. Los atributos `user.` se escriben con permiso de escritura, los `trusted.` solo los escribe root y los
+/// `security.` el dueño y root. Devuelve el código de error de `setxattr(2)`.
+pub fn check_xattr_write(caller: &Caller, attributes: &FileAttr, namespace: Namespace) -> Result<(), i32> {
+    match namespace {
+        Namespace::User if !supports_user_xattrs(attributes) => Err(EPERM),
+        Namespace::User if !has_access(caller, attributes, W_OK) => Err(EACCES),
+        Namespace::Trusted if !caller.is_root() => Err(EPERM),
+        Namespace::Security if !caller.is_root() && !caller.owns(attributes) => Err(EPERM),
+        _ => Ok(())
+    }
+}
+
+/// Indica si `listxattr(2)` le muestra a `caller` los atributos del espacio de nombres `namespace`.
+pub fn lists_xattr(caller: &Caller, namespace: Namespace) -> bool {
+    namespace != Namespace::Trusted || caller.is_root()
+}
+
+fn existing_directory(attributes: Option<&FileAttr>) -> Result<&FileAttr, i32> {
+    match attributes {
+        Some(attributes) if attributes.kind == FileType::Directory => Ok(attributes),
+        Some(_) => Err(ENOTDIR),
+        None => Err(ENOENT)
+    }
+}
+
+fn supports_user_xattrs(attributes: &FileAttr) -> bool {
+    attributes.kind == FileType::RegularFile || attributes.kind == FileType::Directory
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::Timespec;
+
+    const OWNER: Caller = Caller { uid: 1000, gid: 100, groups: Vec::new() };
+    const GROUP_MEMBER: Caller = Caller { uid: 2000, gid: 100, groups: Vec::new() };
+    const OTHER: Caller = Caller { uid: 3000, gid: 300, groups: Vec::new() };
+    const ROOT: Caller = Caller { uid: 0, gid: 0, groups: Vec::new() };
+
+    /// Atributos de un archivo de tipo `kind` con los permisos `perm`, del dueño `OWNER` y el grupo 100.
+    fn attributes(kind: FileType, perm: u16) -> FileAttr {
+        let ts = Timespec::new(0, 0);
+        FileAttr {
+            ino: 2, size: 0, blocks: 0, atime: ts, mtime: ts, ctime: ts, crtime: ts, kind, perm,
+            nlink: 1, uid: OWNER.uid, gid: OWNER.gid, rdev: 0, flags: 0
+        }
+    }
+
+    #[test]
+    fn uses_the_owner_group_or_other_bits() {
+        let file = attributes(FileType::RegularFile, 0o640);
+        assert!(has_access(&OWNER, &file, R_OK | W_OK));
+        assert!(!has_access(&OWNER, &file, X_OK));
+        assert!(has_access(&GROUP_MEMBER, &file, R_OK));
+        assert!(!has_access(&GROUP_MEMBER, &file, W_OK));
+        assert!(!has_access(&OTHER, &file, R_OK));
+
+        // Solo cuentan los bits de la clase de quien pide, aunque otra clase tenga más permisos
+        let file = attributes(FileType::RegularFile, 0o077);
+        assert!(!has_access(&OWNER, &file, R_OK));
+        assert!(has_access(&OTHER, &file, R_OK | W_OK | X_OK));
+    }
+
+    #[test]
+    fn root_reads_and_writes_anything_but_only_executes_executables() {
+        let file = attributes(FileType::RegularFile, 0o000);
+        assert!(has_access(&ROOT, &file, R_OK | W_OK));
+        assert!(!has_access(&ROOT, &file, X_OK));
+        assert!(has_access(&ROOT, &attributes(FileType::RegularFile, 0o001), X_OK));
+        assert!(has_access(&ROOT, &attributes(FileType::Directory, 0o000), X_OK));
+    }
+
+    #[test]
+    fn open_mask_follows_the_access_mode_and_truncation() {
+        assert_eq!(open_mask(O_RDONLY as u32), R_OK);
+        assert_eq!(open_mask(O_WRONLY as u32), W_OK);
+        assert_eq!(open_mask(O_RDWR as u32), R_OK | W_OK);
+        assert_eq!(open_mask((O_RDONLY | O_TRUNC) as u32), R_OK | W_OK);
+    }
+
+    #[test]
+    fn setgid_directories_pass_on_their_group() {
+        let plain = attributes(FileType::Directory, 0o775);
+        assert_eq!(new_owner(&OTHER, &plain, FileType::RegularFile, 0o644), (OTHER.uid, OTHER.gid, 0o644));
+
+        let setgid = attributes(FileType::Directory, 0o2775);
+        assert_eq!(new_owner(&OTHER, &setgid, FileType::RegularFile, 0o644), (OTHER.uid, OWNER.gid, 0o644));
+        assert_eq!(new_owner(&OTHER, &setgid, FileType::Directory, 0o755), (OTHER.uid, OWNER.gid, 0o2755));
+    }
+
+    #[test]
+    fn changing_mode_or_owner_drops_set_id_bits() {
+        // El dueño que no es del grupo del archivo no puede ponerle el bit set-group-ID
+        let mut file = attributes(FileType::RegularFile, 0o644);
+        let outsider = Caller { uid: OWNER.uid, gid: 300, groups: Vec::new() };
+        assert!(change_mode(&outsider, &mut file, 0o2755));
+        assert_eq!(file.perm, 0o755);
+        assert!(!change_mode(&OTHER, &mut file, 0o777));
+
+        let mut file = attributes(FileType::RegularFile, 0o6755);
+        assert!(change_owner(&ROOT, &mut file, Some(OTHER.uid), None));
+        assert_eq!((file.uid, file.perm), (OTHER.uid, 0o755));
+
+        let mut directory = attributes(FileType::Directory, 0o2755);
+        assert!(change_owner(&ROOT, &mut directory, None, Some(300)));
+        assert_eq!(directory.perm, 0o2755);
+
+        // Solo root cambia el dueño, y el dueño solo puede pasar el archivo a su propio grupo
+        let mut file = attributes(FileType::RegularFile, 0o644);
+        assert!(!change_owner(&OWNER, &mut file, Some(OTHER.uid), None));
+        assert!(!change_owner(&OWNER, &mut file, None, Some(300)));
+        assert!(change_owner(&outsider, &mut file, None, Some(300)));
+    }
+
+    #[test]
+    fn entries_need_write_and_search_permission_on_the_directory() {
+        let directory = attributes(FileType::Directory, 0o755);
+        assert_eq!(check_lookup(&OTHER, Some(&directory)), Ok(()));
+        assert_eq!(check_add_entry(&OWNER, Some(&directory)), Ok(()));
+        assert_eq!(check_add_entry(&OTHER, Some(&directory)), Err(EACCES));
+
+        let private = attributes(FileType::Directory, 0o700);
+        assert_eq!(check_lookup(&OTHER, Some(&private)), Err(EACCES));
+        assert_eq!(check_add_entry(&ROOT, Some(&private)), Ok(()));
+
+        assert_eq!(check_lookup(&OWNER, Some(&attributes(FileType::RegularFile, 0o755))), Err(ENOTDIR));
+        assert_eq!(check_add_entry(&OWNER, None), Err(ENOENT));
+    }
+
+    #[test]
+    fn sticky_directories_only_let_owners_remove_entries() {
+        let shared = attributes(FileType::Directory, 0o1777);
+        let mut file = attributes(FileType::RegularFile, 0o644);
+        file.uid = GROUP_MEMBER.uid;
+
+        assert_eq!(check_remove_entry(&OTHER, Some(&shared), &file), Err(EPERM));
+        assert_eq!(check_remove_entry(&GROUP_MEMBER, Some(&shared), &file), Ok(()));
+        assert_eq!(check_remove_entry(&OWNER, Some(&shared), &file), Ok(()));
+        assert_eq!(check_remove_entry(&ROOT, Some(&shared), &file), Ok(()));
+
+        // Sin el bit sticky alcanza con poder escribir en el directorio
+        let open = attributes(FileType::Directory, 0o777);
+        assert_eq!(check_remove_entry(&OTHER, Some(&open), &file), Ok(()));
+        let closed = attributes(FileType::Directory, 0o1755);
+        assert_eq!(check_remove_entry(&GROUP_MEMBER, Some(&closed), &file), Err(EACCES));
+    }
+
+    #[test]
+    fn supplementary_groups_count_as_the_file_group() {
+        let status = "Name:\tcat\nUid:\t3000\t3000\t3000\t3000\nGroups:\t100 27 \nNgid:\t0\n";
+        let member = Caller { groups: supplementary_groups(status), ..OTHER };
+        assert_eq!(member.groups, vec![100, 27]);
+        assert!(supplementary_groups("Name:\tcat\nGroups:\t\n").is_empty());
+
+        let file = attributes(FileType::RegularFile, 0o640);
+        assert!(has_access(&member, &file, R_OK));
+        assert!(!has_access(&OTHER, &file, R_OK));
+
+        // El dueño puede pasar el archivo a uno de sus grupos suplementarios
+        let mut file = attributes(FileType::RegularFile, 0o644);
+        let owner = Caller { groups: vec![27], ..OWNER };
+        assert!(change_owner(&owner, &mut file, None, Some(27)));
+        assert!(!change_owner(&owner, &mut file, None, Some(300)));
+    }
+
+    #[test]
+    fn writes_by_other_users_drop_set_id_bits() {
+        let mut file = attributes(FileType::RegularFile, 0o6755);
+        assert!(!drop_set_id_on_write(&ROOT, &mut file));
+        assert_eq!(file.perm, 0o6755);
+        assert!(drop_set_id_on_write(&GROUP_MEMBER, &mut file));
+        assert_eq!(file.perm, 0o755);
+        assert!(!drop_set_id_on_write(&GROUP_MEMBER, &mut file));
+
+        // Sin ejecución del grupo el bit set-group-ID se conserva
+        let mut file = attributes(FileType::RegularFile, 0o6644);
+        assert!(drop_set_id_on_write(&OWNER, &mut file));
+        assert_eq!(file.perm, 0o2644);
+    }
+
+    #[test]
+    fn listing_a_directory_needs_read_permission() {
+        let directory = attributes(FileType::Directory, 0o711);
+        assert_eq!(check_list(&OWNER, Some(&directory)), Ok(()));
+        assert_eq!(check_list(&OTHER, Some(&directory)), Err(EACCES));
+        assert_eq!(check_lookup(&OTHER, Some(&directory)), Ok(()));
+        assert_eq!(check_list(&ROOT, Some(&attributes(FileType::Directory, 0o000))), Ok(()));
+        assert_eq!(check_list(&OWNER, Some(&attributes(FileType::RegularFile, 0o644))), Err(ENOTDIR));
+    }
+}
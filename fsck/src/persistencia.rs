@@ -1,129 +1,598 @@
 use fuse::{FileAttr}; //Libreria para el manejo del FileSytem en User Space
 use std::str; // Libreria estandar de string
-use std::mem; //Libreria estandar para el manejo de memoria
-use std::fs::File; // Liberia para el manejo de archivos
-use std::io::prelude::*; //Libreria para el manejo de entradas y salidas 
-use std::path::Path; // Libreria para obtener el current path del proyecto
-use std::fs::OpenOptions; //Libreria asistente para el manejo de los archivos.
 use serde::{Serialize, Deserialize}; //Libreria para el manejo de serealizacion
-use crate::serialization::FileAttrDef; //Libreria propietaria del proyecto
-use bincode::{serialize, deserialize}; //Libreria para encodificar y codificar en binario
+use crate::serialization::{FileAttrDef, TimespecDef}; //Libreria propietaria del proyecto
+use bincode::{serialize, serialized_size, deserialize, deserialize_from}; //Libreria para encodificar y codificar en binario
 use fuse::{FileType};//Libreria para el manejo del FileSytem en User Space
+use time::Timespec; //Libreria para el manejo del tiempo
+use std::fmt; //Libreria para dar formato a los mensajes de error
+use std::io::{self, Cursor}; //Libreria para leer los archivos por partes
+use std::collections::{BTreeMap, BTreeSet, HashMap}; //Libreria para el manejo de diccionarios y conjuntos
+use std::cell::RefCell; //Libreria para llenar la caché de bloques desde las lecturas
+use std::rc::Rc; //Libreria para compartir el contenido de los bloques sin copiarlo
+use std::time::Duration; //Libreria para el intervalo de sincronización
+use std::ffi::{OsStr, OsString}; //Libreria para los nombres de las entradas de los directorios
+use std::os::unix::ffi::{OsStrExt, OsStringExt}; //Libreria para pasar los nombres a bytes sin convertirlos
+use crate::directorio::{DiskDirectory, INODE_FILE, DATA_FILE}; //Libreria propietaria del proyecto para los archivos del disco
+use crate::imagen::{BitmapKind, ImageFiles, ImageLayout, HEADER_REGION_SIZE}; //Libreria propietaria del proyecto para las casillas de la imagen
+use crate::mapa::Bitmap; //Libreria propietaria del proyecto para los mapas de bits
+use crate::entradas::{check_name, DirectoryEntry, DirectoryIndex, DIRECTORY_ENTRY_SIZE, NAME_CAPACITY}; //Libreria propietaria del proyecto para las entradas de los directorios
+use crate::atributos::{XattrValue, INLINE_XATTR_CAPACITY, XATTR_INODE_CAPACITY}; //Libreria propietaria del proyecto para los atributos extendidos
+use crate::cache::{BlockCache, LruCache, DEFAULT_CACHE_SIZE, DIRECTORY_INDEX_CACHE_SIZE}; //Libreria propietaria del proyecto para la caché de bloques
+use crate::bitacora::{Journal, JournalRecord}; //Libreria propietaria del proyecto para la bitácora
+use crate::cifrado::{Credential, EncryptionHeader, Key, KeyShare, KeySlot}; //Libreria propietaria del proyecto para el cifrado del disco
 
 big_array! { BigArray; }
-// Estructura para el disco virtual
+
+// Distribución del vector `references` de un archivo regular: las primeras posiciones son referencias
+// directas a bloques de datos, la penúltima apunta a un bloque indirecto simple y la última a uno doble.
+// Los directorios usan el mismo mapa de bloques para los bloques de datos donde guardan sus entradas.
+pub const DIRECT_REFERENCES: usize = 126;
+const SINGLE_INDIRECT_REFERENCE: usize = 126;
+const DOUBLE_INDIRECT_REFERENCE: usize = 127;
+// Tamaño en bytes de cada entrada de un bloque indirecto
+const INDIRECT_ENTRY_SIZE: usize = 8;
+// Largo máximo del destino de un enlace simbólico guardado dentro de su inodo; los más largos van en bloques de datos
+const INLINE_TARGET_CAPACITY: usize = 60;
+// Largo máximo del destino de un enlace simbólico, como `PATH_MAX` sin el byte nulo del final
+pub const SYMLINK_MAX: usize = 4095;
+// Cantidad de enlaces simbólicos que se siguen al resolver un destino antes de considerarlo un ciclo, como `ELOOP`
+const MAX_SYMLINK_DEPTH: usize = 40;
+
+// Número mágico con el que empiezan `inode.qrfs` (encabezado del superbloque) y `disco.qrfs`
+pub const MAGIC: [u8; 4] = *b"QRFS";
+const DATA_MAGIC: [u8; 4] = *b"QRDT";
+// Versión del formato en disco: una imagen cifrada con casillas fijas para cada inodo y cada bloque, mapas de bits y
+// bitácora. Los discos del formato original, sin encabezado, se migran a esta versión al cargarlos.
+pub const FORMAT_VERSION: u32 = 1;
+// Banderas de características del encabezado
+pub const FEATURE_ENCRYPTED: u32 = 0x1;
+
+// Geometría fija con la que se creaban los discos antes de que existiera el encabezado
+const LEGACY_BLOCK_SIZE: usize = 2_490_368;
+const LEGACY_BLOCK_COUNT: usize = 430;
+const LEGACY_MAX_FILES: usize = 1024;
+
+// Unidad en la que se reporta `st_blocks`, sin importar el tamaño de bloque del disco
+const SECTOR_SIZE: usize = 512;
+
+// Tamaño de la bitácora a partir del cual sus cambios se escriben en la imagen y se vacía
+const JOURNAL_CHECKPOINT_SIZE: u64 = 16 * 1024 * 1024;
+
+// Ubicación de un bloque lógico de un archivo dentro de su mapa de bloques
+enum BlockPath {
+    Direct(usize),
+    SingleIndirect(usize),
+    DoubleIndirect(usize, usize)
+}
+
+// Errores al cargar o guardar el disco virtual
 #[derive(Debug)]
+pub enum DiskError {
+    Io(io::Error),
+    Serialization(bincode::Error),
+    InvalidMagic,
+    UnsupportedVersion(u32),
+    GeometryMismatch(String),
+    WrongPhrase,
+    Corrupted(String),
+    Inconsistent(String),
+    KeySlot(String),
+    NoSpace,
+    NameTooLong(OsString),
+    InvalidName(OsString),
+    TargetTooLong(usize),
+    FileTooLarge(u64),
+    NotADirectory(u64),
+    InodeOutOfRange(u64),
+    InodeNotFound(u64),
+    BlockOutOfRange(usize),
+    BlockOverflow(usize),
+    NeedsRepair(String),
+    ReadOnly
+}
+
+impl fmt::Display for DiskError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DiskError::Io(e) => write!(f, "error de entrada/salida: {}", e),
+            DiskError::Serialization(e) => write!(f, "¡Error al leer el disco persistido! {}", e),
+            DiskError::InvalidMagic => write!(f, "los archivos no tienen el número mágico de QrFS"),
+            DiskError::UnsupportedVersion(version) => write!(f, "versión de formato {} no soportada (la más reciente es {})", version, FORMAT_VERSION),
+            DiskError::GeometryMismatch(detail) => write!(f, "la geometría del disco no coincide con su encabezado: {}", detail),
+            DiskError::WrongPhrase => write!(f, "la frase o el archivo de llave no abre ninguna ranura del disco"),
+            DiskError::Corrupted(detail) => write!(f, "no se pudo descifrar {}: el contenido fue modificado o está dañado", detail),
+            DiskError::Inconsistent(detail) => write!(f, "el disco es inconsistente: {}", detail),
+            DiskError::KeySlot(detail) => write!(f, "{}", detail),
+            DiskError::NoSpace => write!(f, "no quedan bloques libres en el disco"),
+            DiskError::NameTooLong(name) => write!(f, "el nombre {:?} excede los {} bytes permitidos", name, NAME_CAPACITY),
+            DiskError::InvalidName(name) => write!(f, "el nombre {:?} no es válido", name),
+            DiskError::FileTooLarge(size) => write!(f, "un archivo de {} bytes no cabe en el mapa de bloques de un inodo", size),
+            DiskError::TargetTooLong(length) => write!(f, "el destino del enlace simbólico tiene {} bytes, más de los {} permitidos", length, SYMLINK_MAX),
+            DiskError::NotADirectory(ino) => write!(f, "el inodo {} no es un directorio", ino),
+            DiskError::InodeOutOfRange(ino) => write!(f, "el número de inodo {} está fuera de la tabla de inodos", ino),
+            DiskError::InodeNotFound(ino) => write!(f, "el inodo {} no existe", ino),
+            DiskError::BlockOutOfRange(index) => write!(f, "el bloque {} está fuera del disco", index),
+            DiskError::BlockOverflow(length) => write!(f, "un contenido de {} bytes no cabe en un bloque de memoria", length),
+            DiskError::NeedsRepair(detail) => write!(f, "el disco se tiene que arreglar antes de revisarlo: {}", detail),
+            DiskError::ReadOnly => write!(f, "el disco se abrió solo para leer")
+        }
+    }
+}
+
+impl DiskError {
+    /// Código de `errno` con el que se responde a una operación del sistema de archivos que falló con este error.
+    #[allow(dead_code)]
+    pub fn errno(&self) -> i32 {
+        match self {
+            DiskError::NoSpace => libc::ENOSPC,
+            DiskError::NameTooLong(_) | DiskError::TargetTooLong(_) => libc::ENAMETOOLONG,
+            DiskError::InvalidName(_) => libc::EINVAL,
+            DiskError::InodeNotFound(_) => libc::ENOENT,
+            DiskError::ReadOnly => libc::EROFS,
+            DiskError::NotADirectory(_) => libc::ENOTDIR,
+            DiskError::FileTooLarge(_) => libc::EFBIG,
+            _ => libc::EIO
+        }
+    }
+}
+
+impl From<io::Error> for DiskError {
+    fn from(e: io::Error) -> Self {
+        DiskError::Io(e)
+    }
+}
+
+impl From<bincode::Error> for DiskError {
+    fn from(e: bincode::Error) -> Self {
+        DiskError::Serialization(e)
+    }
+}
+
+// Encabezado del superbloque, guardado al inicio de `inode.qrfs`. Describe la geometría del disco.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SuperBlockHeader {
+    pub magic: [u8; 4],
+    pub version: u32,
+    pub block_size: u64,
+    pub block_count: u64,
+    pub max_files: u64,
+    #[serde(with = "TimespecDef")]
+    pub created: Timespec,
+    // Banderas de características opcionales del formato (`FEATURE_*`)
+    pub features: u32,
+    // Ranuras con la llave maestra envuelta por cada credencial
+    pub encryption: EncryptionHeader
+}
+
+impl SuperBlockHeader {
+    fn new(block_size: usize, block_count: usize, max_files: usize, encryption: EncryptionHeader) -> Self {
+        SuperBlockHeader {
+            magic: MAGIC,
+            version: FORMAT_VERSION,
+            block_size: block_size as u64,
+            block_count: block_count as u64,
+            max_files: max_files as u64,
+            created: time::now().to_timespec(),
+            features: FEATURE_ENCRYPTED,
+            encryption
+        }
+    }
+
+    /// Lee el encabezado al inicio de `inode.qrfs` y prueba `credential` contra sus ranuras de llave. Devuelve la llave
+    /// maestra junto con el número de la ranura que abrió.
+    fn read(reader: &mut Cursor<&Vec<u8>>, credential: &Credential) -> Result<(SuperBlockHeader, (usize, Key)), DiskError> {
+        let header = SuperBlockHeader::read_unlocked(reader)?;
+        let unlocked = header.encryption.unlock(credential).ok_or(DiskError::WrongPhrase)?;
+        Ok((header, unlocked))
+    }
+
+    /// Lee el encabezado al inicio de `inode.qrfs` sin abrir ninguna ranura de llave.
+    fn read_unlocked(reader: &mut Cursor<&Vec<u8>>) -> Result<SuperBlockHeader, DiskError> {
+        let (_, version): ([u8; 4], u32) = deserialize_from(&mut *reader)?;
+        if version != FORMAT_VERSION {
+            return Err(DiskError::UnsupportedVersion(version));
+        }
+
+        reader.set_position(0);
+        Ok(deserialize_from(reader)?)
+    }
+}
+
+/// Contexto autenticado junto con el contenido cifrado del bloque `index`.
+fn block_context(index: usize) -> Vec<u8> {
+    format!("qrfs-bloque-{}", index).into_bytes()
+}
+
+/// Contexto autenticado junto con el contenido cifrado del inodo guardado en la posición `index`.
+fn inode_context(index: usize) -> Vec<u8> {
+    format!("qrfs-inodo-{}", index).into_bytes()
+}
+
+/// Contexto autenticado junto con el contenido cifrado de la parte `chunk` del mapa de bits `kind`.
+fn bitmap_context(kind: BitmapKind, chunk: usize) -> Vec<u8> {
+    match kind {
+        BitmapKind::Inodes => format!("qrfs-mapa-inodos-{}", chunk).into_bytes(),
+        BitmapKind::Blocks => format!("qrfs-mapa-bloques-{}", chunk).into_bytes()
+    }
+}
+
+// Estructura para el disco virtual
 #[allow(dead_code)]
 pub struct Disk {
-    pub super_block: Box<[Option<Inode>]>,
-    pub memory_blocks: Box<[MemoryBlock]>,
-    pub max_files: usize,
+    header: SuperBlockHeader,
+    super_block: Box<[Option<Inode>]>,
+    block_count: usize,
+    // Mapas de bits de los inodos y bloques en uso, guardados en la imagen junto con sus casillas
+    inode_map: Bitmap,
+    block_map: Bitmap,
+    // Índices de las entradas de los directorios, por número `ino` del directorio, que se arman a medida que se usan.
+    // Se guardan solo los de los directorios usados hace menos tiempo: los demás se vuelven a armar de su contenido.
+    directory_index: RefCell<LruCache<u64, DirectoryIndex>>,
+    // Bloques cambiados desde la última vez que se escribieron en la imagen, con su contenido nuevo
+    modified_blocks: BTreeMap<usize, Option<Rc<[u8]>>>,
+    // Bloques leídos de la imagen, que se cargan a medida que se usan
+    cache: RefCell<BlockCache>,
+    max_files: usize,
+    block_size: usize,
+    root_path: String,
+    directory: DiskDirectory,
+    key: Key,
+    // Ranura de llave con la que se abrió el disco
+    unlocked_slot: usize,
+    // Posiciones de los inodos y bloques en los archivos de la imagen
+    layout: ImageLayout,
+    // Archivos de la imagen abiertos para escribir las casillas cambiadas. `None` mientras los archivos guardados no
+    // tengan el formato actual y se deban escribir completos.
+    image: Option<ImageFiles>,
+    // Bitácora con las operaciones hechas desde la última vez que sus cambios se escribieron en la imagen
+    journal: Journal,
+    // Inodos y bloques modificados por la operación en curso, que todavía no se registraron en la bitácora
+    pending_inodes: BTreeSet<usize>,
+    pending_blocks: BTreeSet<usize>,
+    // Estado de los inodos y bloques antes de que la operación en curso los cambiara, para deshacerla si falla a mitad
+    // de camino: el inodo, o si el bloque estaba en uso junto con su entrada entre los bloques cambiados
+    undo_inodes: BTreeMap<usize, Option<Inode>>,
+    undo_blocks: BTreeMap<usize, SavedBlock>,
+    // Directorios cuyas entradas cambió la operación en curso, cuyos índices se descartan si se deshace
+    undo_directories: BTreeSet<u64>,
+    // Inodos registrados en la bitácora que todavía no se escribieron en sus casillas de la imagen
+    dirty_inodes: BTreeSet<usize>,
+    // Cantidad de handles abiertos de cada archivo, por número `ino`. Un archivo sin enlaces no se libera mientras
+    // tenga alguno.
+    open_handles: HashMap<u64, u32>,
+    // Cantidad de operaciones sin sincronizar a partir de la cual se sincroniza la bitácora, si se configuró
+    sync_after_operations: Option<u64>,
+    // Si el disco se abrió con `open_read_only`: no registra ni guarda cambios
+    read_only: bool
+}
+
+// El formato de depuración solo muestra la geometría del disco: los inodos, los bloques y la llave están descifrados en
+// memoria y no deben terminar en un registro o un archivo
+impl fmt::Debug for Disk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Disk")
+            .field("header", &self.header)
+            .field("block_count", &self.block_count)
+            .field("max_files", &self.max_files)
+            .field("block_size", &self.block_size)
+            .field("root_path", &self.root_path)
+            .field("unlocked_slot", &self.unlocked_slot)
+            .finish_non_exhaustive()
+    }
+}
+
+// Estado de un bloque antes de que la operación en curso lo cambiara: si estaba en uso y su entrada entre los bloques
+// cambiados, que falta si el bloque se lee de la imagen
+struct SavedBlock {
+    in_use: bool,
+    modified: Option<Option<Rc<[u8]>>>
+}
+
+/// Ocupación del disco según sus mapas de bits, para `statfs`.
+#[derive(Debug, Clone, Copy)]
+pub struct DiskUsage {
     pub block_size: usize,
-    pub root_path: String,
-    phrase: String
+    pub total_blocks: usize,
+    pub free_blocks: usize,
+    pub total_inodes: usize,
+    pub free_inodes: usize
 }
 // Estructura de los i-nodes
-#[derive(Debug)]
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Inode {
-    #[serde(with = "BigArray")]
-    pub name: [char; 64],
     #[serde(with = "FileAttrDef")]
     pub attributes: FileAttr,
     #[serde(with = "BigArray")]
-    pub references: [Option<usize>; 128]
+    pub references: [Option<usize>; 128],
+    // Destino de un enlace simbólico de hasta `INLINE_TARGET_CAPACITY` bytes. Los destinos más largos se guardan en
+    // los bloques de datos del enlace, como el contenido de un archivo.
+    pub target: Option<Vec<u8>>,
+    // Atributos extendidos, por nombre
+    pub xattrs: BTreeMap<Vec<u8>, XattrValue>
 }
-#[derive(Debug)]
-#[derive(Serialize, Deserialize)]
-pub struct MemoryBlock {
+
+// Inodo del formato original, que guardaba su nombre en 64 caracteres. Solo se lee, para migrar el disco.
+#[derive(Deserialize)]
+struct LegacyInode {
+    #[serde(with = "BigArray")]
+    name: [char; 64],
+    #[serde(with = "FileAttrDef")]
+    attributes: FileAttr,
+    #[serde(with = "BigArray")]
+    references: [Option<usize>; 128]
+}
+
+impl LegacyInode {
+    /// Pasa al formato actual una tabla de inodos del formato original, dejando el nombre de cada inodo en `names`, por
+    /// número `ino`, para armar las entradas de los directorios.
+    fn into_inodes(inodes: Vec<Option<LegacyInode>>, names: &mut HashMap<u64, OsString>) -> Vec<Option<Inode>> {
+        inodes.into_iter().map(|inode| inode.map(|inode| {
+            let name: String = inode.name.iter().take_while(|c| **c != '\0').collect();
+            names.insert(inode.attributes.ino, OsString::from(name));
+            Inode {
+                attributes: inode.attributes,
+                references: inode.references,
+                target: None,
+                xattrs: BTreeMap::new()
+            }
+        })).collect()
+    }
+}
+
+// Bloque del formato original. Solo se lee, para migrar el disco.
+#[derive(Deserialize)]
+struct MemoryBlock {
     data: Option<Box<[u8]>>
 }
 
 impl Disk {
 
-    /// Inicializa un disco virtual con el tamaño total especificado en `memory_size_in_bytes` y cada bloque contiene un tamaño fijo definido en `block_size`.
-    /// El número de bloques asignados se define mediante la expresión `memory_size_in_bytes / block_size`.
-    pub fn new(
+    /// Crea un disco virtual nuevo de `block_count` bloques de `block_size` bytes con espacio para `max_files` inodos.
+    /// La geometría queda guardada en el encabezado del superbloque, de donde la leen mount_qrfs y fsck_qrfs.
+    #[allow(dead_code)]
+    pub fn create(
         root_path: String,
-        memory_size_in_bytes: usize,
         block_size: usize,
-        phrase: String
-    ) -> Disk {
-        // Número de bloques de memoria
-        // El -1 se refiere al "superbloque", que tiene el mismo tamaño que un MemoryBlock
-        let memory_block_quantity: usize = (memory_size_in_bytes / block_size) - 1;
-        // Se está considerando el tamaño del puntero Box además del tamaño de la estructura Inode
-        let inode_size = mem::size_of::<Box<[Inode]>>() + mem::size_of::<Inode>();
-        let max_files = block_size / inode_size;
+        block_count: usize,
+        max_files: usize,
+        credential: Credential
+    ) -> Result<Disk, DiskError> {
+        let (encryption, key) = EncryptionHeader::create(&credential);
+        let header = SuperBlockHeader::new(block_size, block_count, max_files, encryption);
 
-        let disk_file_path = format!("{}/disco.qrfs", &root_path);
-        let inode_table_file_path = format!("{}/inode.qrfs", &root_path);
+        let ts = time::now().to_timespec();
+        let attr = FileAttr {
+            ino: 1,
+            size: 0,
+            blocks: 0,
+            atime: ts,
+            mtime: ts,
+            ctime: ts,
+            crtime: ts,
+            kind: FileType::Directory,
+            perm: 0o755,
+            nlink: 2,
+            // La raíz es de quien crea el disco, que es quien lo va a montar
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+            rdev: 0,
+            flags: 0,
+        };
 
-        // Intente leer el archivo del disco, si no existe, crea uno nuevo
-        let mut memory_blocks: Vec<MemoryBlock>;
-        let mut super_block: Vec<Option<Inode>>;
+        let initial_inode = Inode {
+            attributes: attr,
+            references: [None; 128],
+            target: None,
+            xattrs: BTreeMap::new()
+        };
 
-        if Path::new(&disk_file_path).exists() && Path::new(&inode_table_file_path).exists() {
-            println!("¡Disco existente encontrado! Cargando...");
+        let directory = DiskDirectory::open(&root_path)?;
+        directory.recover_commit()?;
+        let journal = Journal::open(&directory)?;
 
-            let mut ser_inodes: Vec<u8> = Vec::new();
-            let mut ser_disk: Vec<u8> = Vec::new();
+        let mut disk = Disk::from_parts(header, vec![Some(initial_inode)], Vec::new(), root_path, directory, journal, key);
+        disk.write_to_disk()?;
 
-            File::open(&inode_table_file_path).unwrap().read_to_end(&mut ser_inodes).unwrap();
-            File::open(&disk_file_path).unwrap().read_to_end(&mut ser_disk).unwrap();
+        Ok(disk)
+    }
 
-            super_block = if &ser_inodes.len() > &0 {
-                deserialize(&ser_inodes).expect("¡Error al leer el disco persistido!")
-             } else {
-                Vec::new()
-            };
+    /// Carga el disco virtual guardado en `root_path`. La geometría (tamaño de bloque, cantidad de bloques y número
+    /// máximo de archivos) se toma del encabezado del superbloque. Los discos del formato original, sin encabezado, se
+    /// migran con `migrate_legacy`.
+    ///
+    /// La tabla de inodos y los bloques se descifran con la llave maestra guardada en la ranura que abra `credential`;
+    /// si no abre ninguna se devuelve `DiskError::WrongPhrase`.
+    ///
+    /// Las transacciones completas de la bitácora se vuelven a aplicar sobre la imagen, que se guarda antes de devolver
+    /// el disco.
+    pub fn new(root_path: String, credential: Credential) -> Result<Disk, DiskError> {
+        println!("¡Disco existente encontrado! Cargando...");
+        Disk::load(root_path, credential, false).map(|(disk, _)| disk)
+    }
 
-            memory_blocks = if &ser_disk.len() > &0 {
-                deserialize(&ser_disk).expect("¡Error al leer el disco persistido!")
-            } else {
-                Vec::new()
-            };
+    /// Carga el disco guardado en `root_path` como `new`, pero sin escribir nada en sus archivos, para revisarlo. Los
+    /// arreglos que `new` haría al cargarlo no se guardan: se devuelven descritos junto con el disco, que tiene las
+    /// transacciones de la bitácora aplicadas solo en memoria y no puede registrar ni guardar cambios.
+    ///
+    /// Un disco que no se puede leer sin arreglarlo antes, porque quedó un guardado interrumpido o tiene el formato
+    /// original, devuelve `DiskError::NeedsRepair`.
+    #[allow(dead_code)]
+    pub fn open_read_only(root_path: String, credential: Credential) -> Result<(Disk, Vec<String>), DiskError> {
+        Disk::load(root_path, credential, true)
+    }
 
-            // Si la cantidad de bloques en MemoryBlockel disco existente es mayor que la del disco a crear, la ejecución finaliza
-            if memory_block_quantity < memory_blocks.len() {
-                panic!("¡El disco existente es más grande que el disco actual! ¡Intenta arrancar con un disco de mayor tamaño!");
+    /// Carga el disco guardado en `root_path` y devuelve los arreglos que hicieron falta. Con `read_only` los arreglos
+    /// solo se describen y no se escribe nada en los archivos del disco.
+    fn load(root_path: String, credential: Credential, read_only: bool) -> Result<(Disk, Vec<String>), DiskError> {
+        let mut repairs = Vec::new();
+
+        // Una escritura interrumpida se termina o se descarta antes de leer los archivos
+        let directory = DiskDirectory::open(&root_path)?;
+        match (read_only, directory.interrupted_commit()) {
+            (true, Some(true)) => return Err(DiskError::NeedsRepair(String::from("quedó un guardado interrumpido sin terminar"))),
+            (true, Some(false)) => repairs.push(String::from("Quedaron copias a medio escribir de un guardado interrumpido, que se tienen que borrar")),
+            (true, None) => (),
+            (false, _) => {
+                if directory.recover_commit()? {
+                    println!("Se completó un guardado que había quedado interrumpido.");
+                }
             }
-        } else {
-            File::create(&disk_file_path).expect("¡Error al crear archivos para la persistencia!");
-            File::create(&inode_table_file_path).expect("¡Error al crear archivos para la persistencia!");
+        }
 
-            super_block = Vec::with_capacity(1);
-            memory_blocks = Vec::new();
+        let ser_inodes = directory.read(INODE_FILE)?;
+        if !ser_inodes.starts_with(&MAGIC) {
+            if read_only {
+                return Err(DiskError::NeedsRepair(format!("tiene el formato original, que se tiene que migrar al formato {}", FORMAT_VERSION)));
+            }
+            return Disk::migrate_legacy(root_path, directory, &ser_inodes, &credential).map(|disk| (disk, repairs));
+        }
 
-            let ts = time::now().to_timespec();
-            let attr = FileAttr {
-                ino: 1,
-                size: 0,
-                blocks: 0,
-                atime: ts,
-                mtime: ts,
-                ctime: ts,
-                crtime: ts,
-                kind: FileType::Directory,
-                perm: 0o755,
-                nlink: 0,
-                uid: 0,
-                gid: 0,
-                rdev: 0,
-                flags: 0,
-            };
+        let (header, (slot, key)) = SuperBlockHeader::read(&mut Cursor::new(&ser_inodes), &credential)?;
 
-            let mut name = ['\0'; 64];
-            name[0] = '.';
+        // Las transacciones de la bitácora se leen antes que la imagen, que puede tener casillas a medio escribir
+        // que ellas reparan
+        let transactions = Journal::read_transactions(&directory, &key)?;
 
-            let initial_inode = Inode {
-                name,
-                attributes: attr,
-                references: [None; 128]
-            };
+        // Los bloques se leen de sus casillas recién cuando se usan
+        let files = match read_only {
+            true => ImageFiles::open_read_only(&directory, Disk::layout_of(&header))?,
+            false => ImageFiles::open(&directory, Disk::layout_of(&header))?
+        };
+        let super_block = Disk::read_inodes(&files, &header, &key, &transactions)?;
+        let maps = Disk::read_bitmaps(&files, &header, &key)?;
+
+        if super_block.first().is_none_or(|root| root.is_none()) {
+            return Err(DiskError::GeometryMismatch(String::from("no existe el inodo raíz")));
+        }
+
+        let journal = match read_only {
+            true => Journal::open_read_only(&directory)?,
+            false => Journal::open(&directory)?
+        };
+        let mut disk = Disk::from_parts(header, super_block, Vec::new(), root_path, directory, journal, key);
+        disk.unlocked_slot = slot;
+        disk.image = Some(files);
+
+        let rebuild_maps = maps.is_none();
+        if let Some((inode_map, block_map)) = maps {
+            disk.inode_map = inode_map;
+            disk.block_map = block_map;
+        }
 
-            super_block.push(Some(initial_inode));
+        if !transactions.is_empty() {
+            repairs.push(format!("La bitácora tiene {} operaciones que no se escribieron en la imagen", transactions.len()));
+            println!("Aplicando {} operaciones de la bitácora...", transactions.len());
+            for record in transactions.into_iter().flatten() {
+                disk.apply_journal_record(record)?;
+            }
+        }
+
+        // Sin mapas de bits que se puedan leer, los bloques en uso salen de los mapas de bloques de los archivos y el
+        // mapa de inodos de la tabla de inodos, armado con el disco
+        if rebuild_maps {
+            repairs.push(String::from("Un mapa de bits de la imagen falta o está dañado y se tiene que volver a armar"));
+            println!("Un mapa de bits de la imagen falta o está dañado, se vuelve a armar.");
+            disk.rebuild_block_map()?;
+            disk.inode_map.mark_all_dirty();
+            disk.block_map.mark_all_dirty();
+        }
+
+        // Los archivos que quedan sin enlaces, porque estaban abiertos cuando se interrumpió el montaje anterior, ya no
+        // tienen handles y se liberan. Como todo cambio, pasan por la bitácora antes de escribirse en la imagen.
+        let unlinked = disk.unlinked_inodes();
+        if !unlinked.is_empty() {
+            repairs.push(format!("{} archivos ya no tienen enlaces y se tienen que liberar", unlinked.len()));
+            if !read_only {
+                for ino in unlinked.iter() {
+                    disk.free_inode(*ino)?;
+                }
+                println!("Se liberaron {} archivos que ya no tenían enlaces.", unlinked.len());
+            }
+        }
+        disk.forget_undo();
+
+        if read_only {
+            disk.read_only = true;
+            return Ok((disk, repairs));
+        }
+
+        let changed = !disk.pending_inodes.is_empty() || !disk.pending_blocks.is_empty();
+        if disk.journal.size() > 0 || rebuild_maps || changed {
+            disk.write_to_disk()?;
+        }
+
+        Ok((disk, repairs))
+    }
+
+    /// Carga un disco del formato original, sin encabezado, con la geometría fija de aquel entonces y lo migra al
+    /// formato actual: lo cifra con `credential`, pasa el contenido de cada archivo a su mapa de bloques y las entradas
+    /// de los directorios a sus bloques de datos, cuenta los enlaces y escribe la imagen completa.
+    fn migrate_legacy(root_path: String, directory: DiskDirectory, ser_inodes: &[u8], credential: &Credential) -> Result<Disk, DiskError> {
+        let ser_disk = directory.read(DATA_FILE)?;
+
+        // Nombres de los inodos, que hacen falta para armar las entradas de sus directorios
+        let mut names = HashMap::new();
+        let super_block = match ser_inodes.is_empty() {
+            false => LegacyInode::into_inodes(deserialize(ser_inodes)?, &mut names),
+            true => Vec::new()
         };
+        let memory_blocks: Vec<MemoryBlock> = if !ser_disk.is_empty() { deserialize(&ser_disk)? } else { Vec::new() };
+
+        // Si el disco persistido tiene más inodos o bloques de los que permitía la geometría fija, no se puede cargar
+        if super_block.len() > LEGACY_MAX_FILES {
+            return Err(DiskError::GeometryMismatch(format!("{} inodos guardados para un máximo de {}", super_block.len(), LEGACY_MAX_FILES)));
+        }
+
+        if memory_blocks.len() > LEGACY_BLOCK_COUNT {
+            return Err(DiskError::GeometryMismatch(format!("{} bloques guardados para un disco de {} bloques", memory_blocks.len(), LEGACY_BLOCK_COUNT)));
+        }
+
+        if super_block.first().is_none_or(|root| root.is_none()) {
+            return Err(DiskError::GeometryMismatch(String::from("no existe el inodo raíz")));
+        }
+
+        println!("El disco no estaba cifrado, se cifrará con la {} ingresada.", credential.kind());
+        let (encryption, key) = EncryptionHeader::create(credential);
+        let header = SuperBlockHeader::new(LEGACY_BLOCK_SIZE, LEGACY_BLOCK_COUNT, LEGACY_MAX_FILES, encryption);
+        let journal = Journal::open(&directory)?;
+        let mut disk = Disk::from_parts(header, super_block, memory_blocks, root_path, directory, journal, key);
+
+        println!("Disco con el formato original, migrando al formato {}...", FORMAT_VERSION);
+        disk.migrate_legacy_layout()?;
+        let migrated_directories = disk.migrate_directory_entries(&names)?;
+        if migrated_directories > 0 {
+            println!("Se pasaron las entradas de {} directorios a sus bloques de datos.", migrated_directories);
+        }
+
+        // El formato original no contaba los enlaces
+        disk.recount_links()?;
+        disk.free_unlinked_inodes()?;
+
+        // Sin una imagen guardada todavía, el disco se escribe completo y no pasa por la bitácora
+        disk.pending_inodes.clear();
+        disk.pending_blocks.clear();
+        disk.forget_undo();
+        disk.write_to_disk()?;
+
+        Ok(disk)
+    }
+
+    /// Arma el disco a partir del encabezado y del contenido leído, instanciando en blanco el resto de posiciones.
+    /// Los bloques de `memory_blocks`, leídos completos del formato original, quedan como cambiados para que se
+    /// escriban en la imagen nueva.
+    fn from_parts(
+        header: SuperBlockHeader,
+        mut super_block: Vec<Option<Inode>>,
+        memory_blocks: Vec<MemoryBlock>,
+        root_path: String,
+        directory: DiskDirectory,
+        journal: Journal,
+        key: Key
+    ) -> Disk {
+        let max_files = header.max_files as usize;
+        let block_size = header.block_size as usize;
+        let memory_block_quantity = header.block_count as usize;
 
         // Instanciando en blanco otras posiciones posibles para una mayor velocidad
         for _ in super_block.len()..max_files {
@@ -131,255 +600,2509 @@ impl Disk {
             super_block.push(value);
         }
 
-        for _ in memory_blocks.len()..memory_block_quantity {
-            let value: MemoryBlock = MemoryBlock { data: Option::None };
-            memory_blocks.push(value);
+        let mut inode_map = Bitmap::new(max_files);
+        for (index, inode) in super_block.iter().enumerate() {
+            if inode.is_some() {
+                inode_map.set(index);
+            }
+        }
+
+        let mut block_map = Bitmap::new(memory_block_quantity);
+        let mut modified_blocks = BTreeMap::new();
+        for (index, block) in memory_blocks.into_iter().enumerate() {
+            if let Some(data) = block.data {
+                block_map.set(index);
+                modified_blocks.insert(index, Some(Rc::from(data)));
+            }
         }
 
-        println!("\nTamaño del disco: {} KB", memory_size_in_bytes / 1024);
-        println!("Tamaño del bloque de memoria {} KB", block_size / 1024);
-        println!("Número máximo de archivos (Inode {} bytes): {}", inode_size, max_files);
-        //println!("{:?}",memory_blocks);
+        println!("\nTamaño del disco: {} KB", memory_block_quantity * block_size / 1024);
+        println!("Tamaño del bloque de memoria {} bytes", block_size);
+        println!("Número máximo de archivos: {}", max_files);
+
         Disk {
-            memory_blocks: memory_blocks.into_boxed_slice(),
+            layout: Disk::layout_of(&header),
+            header,
             super_block: super_block.into_boxed_slice(),
+            block_count: memory_block_quantity,
+            inode_map,
+            block_map,
+            directory_index: RefCell::new(LruCache::new(DIRECTORY_INDEX_CACHE_SIZE)),
+            modified_blocks,
+            cache: RefCell::new(BlockCache::new(DEFAULT_CACHE_SIZE / block_size)),
             max_files,
             block_size,
             root_path,
-            phrase
+            directory,
+            key,
+            unlocked_slot: 0,
+            image: None,
+            journal,
+            pending_inodes: BTreeSet::new(),
+            pending_blocks: BTreeSet::new(),
+            undo_inodes: BTreeMap::new(),
+            undo_blocks: BTreeMap::new(),
+            undo_directories: BTreeSet::new(),
+            dirty_inodes: BTreeSet::new(),
+            open_handles: HashMap::new(),
+            sync_after_operations: None,
+            read_only: false
         }
     }
 
-    /// Busca el vector `super_block` para un espacio de memoria vacío (con `None`) y devuelve el número `ino` disponible, si lo hay.
-    /// Por convención, el número de inodo `ino` es el número del índice que ocupa en el vector `super_block` + 1.
-    pub fn find_ino_available(&self) -> Option<u64> {
-        for index in 0..self.super_block.len() - 1 {
-            if let Option::None = self.super_block[index] {
-                let ino = (index as u64) + 1;
-                return Option::Some(ino);
-            }
+    /// Posiciones de las casillas de la imagen para la geometría de `header`.
+    fn layout_of(header: &SuperBlockHeader) -> ImageLayout {
+        ImageLayout::new(header.block_size as usize, header.block_count as usize, header.max_files as usize)
+    }
+
+    /// Lee y descifra con `key` las casillas de los inodos de la imagen. Una casilla que no se puede descifrar pero que
+    /// aparece en `transactions` quedó a medio escribir al guardar los cambios de la bitácora: se deja vacía, porque al
+    /// aplicarla se vuelve a escribir.
+    fn read_inodes(
+        files: &ImageFiles,
+        header: &SuperBlockHeader,
+        key: &Key,
+        transactions: &[Vec<JournalRecord>]
+    ) -> Result<Vec<Option<Inode>>, DiskError> {
+        if !files.read_data_header()?.starts_with(&DATA_MAGIC) {
+            return Err(DiskError::InvalidMagic);
+        }
+
+        let journaled: BTreeSet<usize> = transactions.iter().flatten().filter_map(JournalRecord::inode_index).collect();
+
+        let mut super_block = Vec::with_capacity(header.max_files as usize);
+        for index in 0..header.max_files as usize {
+            let inode = match files.read_inode(index)? {
+                Some(sealed) => match key.decrypt(&sealed, &inode_context(index)) {
+                    Some(inode) => Some(deserialize(&inode)?),
+                    None if !journaled.contains(&index) => {
+                        return Err(DiskError::Corrupted(format!("el inodo {}", index + 1)));
+                    },
+                    None => None
+                },
+                None => None
+            };
+            super_block.push(inode);
         }
 
-        Option::None
+        Ok(super_block)
     }
 
-    /// Busca en la matriz `memory_blocks` un espacio de memoria vacío (con `None`) y devuelve el índice de bloque, si lo hay.
-    pub fn find_index_of_empty_memory_block(&self) -> Option<usize> {
-        for index in 0..self.memory_blocks.len() - 1 {
-            if let Option::None = self.memory_blocks[index].data {
-                return Option::Some(index);
+    /// Lee y descifra con `key` los mapas de bits de inodos y bloques de la imagen. Si alguna parte
+    /// no se puede descifrar, porque quedó a medio escribir, devuelve `None` para que los mapas se vuelvan a armar.
+    fn read_bitmaps(files: &ImageFiles, header: &SuperBlockHeader, key: &Key) -> Result<Option<(Bitmap, Bitmap)>, DiskError> {
+        let layout = Disk::layout_of(header);
+        let mut maps = Vec::with_capacity(2);
+
+        for (kind, len) in [(BitmapKind::Inodes, header.max_files), (BitmapKind::Blocks, header.block_count)].iter() {
+            let mut chunks = Vec::with_capacity(layout.bitmap_chunks(*kind));
+            for chunk in 0..layout.bitmap_chunks(*kind) {
+                match files.read_bitmap(*kind, chunk)?.and_then(|sealed| key.decrypt(&sealed, &bitmap_context(*kind, chunk))) {
+                    Some(content) => chunks.push(content),
+                    None => return Ok(None)
+                }
+            }
+
+            match Bitmap::from_chunks(*len as usize, &chunks) {
+                Some(map) => maps.push(map),
+                None => return Err(DiskError::Corrupted(String::from("los mapas de bits")))
             }
         }
 
-        Option::None
+        let block_map = maps.pop().expect("fn read_bitmaps: falta el mapa de bloques");
+        let inode_map = maps.pop().expect("fn read_bitmaps: falta el mapa de inodos");
+        Ok(Some((inode_map, block_map)))
     }
 
-    /// Funcion que busca el vector `referencias` de un inodo identificado por su número `ino` el primer espacio vacío y devuelve su índice.
-    pub fn find_index_of_empty_reference_in_inode(&self, ino: u64) -> Option<usize> {
-        let index = (ino as usize) - 1;
-        match &self.super_block[index] {
-            Some(inode) => inode.references.iter().position(|r| r == &None),
-            None => panic!("Intento de accesso a memoria inválido")
+    /// Bloques e inodos totales y libres del disco, incluidos los cambios de la operación en curso.
+    pub fn usage(&self) -> DiskUsage {
+        DiskUsage {
+            block_size: self.block_size,
+            total_blocks: self.block_map.len(),
+            free_blocks: self.block_map.free(),
+            total_inodes: self.inode_map.len(),
+            free_inodes: self.inode_map.free()
         }
     }
 
+    /// Devuelve el número `ino` del primer inodo libre según el mapa de inodos, si lo hay.
+    /// Por convención, el número de inodo `ino` es el número del índice que ocupa en el vector `super_block` + 1.
+    pub fn find_ino_available(&self) -> Option<u64> {
+        self.inode_map.first_free().map(|index| (index as u64) + 1)
+    }
+
+    /// Devuelve el índice del primer bloque de memoria libre según el mapa de bloques, si lo hay.
+    pub fn find_index_of_empty_memory_block(&self) -> Option<usize> {
+        self.block_map.first_free()
+    }
+
     /// Funcion que guarda el `inodo` en el vector `super_block`. Si el número de Inodo `ino` ya existe, los datos se sobrescriben.
-    pub fn write_inode(&mut self, inode: Inode) {
-        if mem::size_of_val(&inode) > self.block_size {
-            println!("No se puede guardar el inodo: tamaño mayor que el tamaño del bloque de memoria");
-            return;
+    pub fn write_inode(&mut self, inode: Inode) -> Result<(), DiskError> {
+        if inode.attributes.ino == 0 || inode.attributes.ino as usize > self.max_files {
+            return Err(DiskError::InodeOutOfRange(inode.attributes.ino));
         }
 
         let index = (inode.attributes.ino - 1) as usize;
+        self.save_inode_for_undo(index);
         self.super_block[index] = Some(inode);
+        self.inode_map.set(index);
+        self.pending_inodes.insert(index);
+        Ok(())
     }
 
     pub fn clear_memory_block(&mut self, index: usize) {
-        self.memory_blocks[index] = MemoryBlock { data: None };
+        self.set_block(index, None);
+        self.block_map.clear(index);
+    }
+
+    /// Cambia el contenido del bloque `index`. El bloque queda entre los cambios de la operación en curso hasta que se
+    /// escribe en la imagen.
+    fn set_block(&mut self, index: usize, data: Option<Rc<[u8]>>) {
+        if !self.undo_blocks.contains_key(&index) {
+            let saved = SavedBlock { in_use: self.block_map.is_set(index), modified: self.modified_blocks.get(&index).cloned() };
+            self.undo_blocks.insert(index, saved);
+        }
+        self.cache.get_mut().remove(index);
+        self.modified_blocks.insert(index, data);
+        self.pending_blocks.insert(index);
     }
 
     pub fn clear_inode(&mut self, ino: u64) {
-        let index = (ino - 1) as usize;
+        let index = match self.inode_index(ino) {
+            Some(index) => index,
+            None => return
+        };
+        self.directory_index.get_mut().remove(ino);
+        self.save_inode_for_undo(index);
         self.super_block[index] = None;
+        self.inode_map.clear(index);
+        self.pending_inodes.insert(index);
     }
 
-    /// Funcion que elimina la referencia del vector de referencias de un Inodo
-    pub fn clear_reference_in_inode(&mut self, ino: u64, ref_value: usize) {
-        let index = (ino - 1) as usize;
-        let inode: &mut Option<Inode> = &mut self.super_block[index];
-        
-        match inode {
-            Some(inode) => {
-                let reference_index: Option<usize> = inode.references.iter().position(|r| match r {
-                    Some(reference) => *reference == ref_value,
-                    None => false
-                });
-
-                match reference_index {
-                    Some(reference_index) => inode.references[reference_index] = None,
-                    None => panic!("fn clear_reference_in_inode: Referencia no encontrada en Inode.")
-                }
-            },
-            None => panic!("fn clear_reference_in_inode: Intente desreferenciar un Inodo vacío.")
+    /// Libera el inodo `ino` junto con todos sus bloques de datos y los de sus atributos extendidos.
+    pub fn free_inode(&mut self, ino: u64) -> Result<(), DiskError> {
+        self.free_data_blocks_from(ino, 0)?;
+        let xattr_blocks: Vec<usize> = match self.get_inode(ino) {
+            Some(inode) => inode.xattrs.values().flat_map(|value| value.blocks().to_vec()).collect(),
+            None => Vec::new()
+        };
+        xattr_blocks.iter().for_each(|block| self.clear_memory_block(*block));
+        self.clear_inode(ino);
+        Ok(())
+    }
+
+    /// Suma un enlace al inodo `ino`: una entrada más que lo nombra o, si es un directorio, un subdirectorio más.
+    pub fn increase_links(&mut self, ino: u64) {
+        if let Some(inode) = self.get_inode_as_mut(ino) {
+            inode.attributes.nlink += 1;
+            inode.attributes.ctime = time::now().to_timespec();
         }
     }
 
-    /// Funcion que devuelve la referencia de memoria mutable de `Inode`.
-    pub fn get_inode_as_mut(&mut self, ino: u64) -> Option<&mut Inode> {
-        let index = (ino as usize) - 1;
-        match &mut self.super_block[index] {
-            Some(inode) => Some(inode),
-            None => None
+    /// Resta un enlace al inodo `ino`. Un archivo que se queda sin enlaces se libera, salvo que esté abierto: entonces
+    /// se libera al cerrar su último handle con `release_handle`. Los directorios se liberan con `free_inode` al
+    /// quitarlos.
+    pub fn decrease_links(&mut self, ino: u64) -> Result<(), DiskError> {
+        if let Some(inode) = self.get_inode_as_mut(ino) {
+            inode.attributes.nlink = inode.attributes.nlink.saturating_sub(1);
+            inode.attributes.ctime = time::now().to_timespec();
         }
+        self.free_if_unlinked(ino)
     }
 
-    /// Funcion que devuelve el `Inodo` especificado por su número `ino`.
-    pub fn get_inode(&self, ino: u64) -> Option<&Inode> {
-        let index = (ino as usize) - 1;
-        match &self.super_block[index] {
-            Some(inode) => Some(inode),
-            None => None
+    /// Libera el archivo `ino` si no tiene enlaces ni handles abiertos.
+    fn free_if_unlinked(&mut self, ino: u64) -> Result<(), DiskError> {
+        let unlinked = self.get_inode(ino)
+            .is_some_and(|inode| inode.attributes.nlink == 0 && inode.attributes.kind != FileType::Directory);
+        if unlinked && !self.open_handles.contains_key(&ino) {
+            self.free_inode(ino)?;
         }
+        Ok(())
     }
-    
-    /// Funcion que busca el Inodo por nombre dentro de una matriz de referencias de Inodos principales.
-    pub fn find_inode_in_references_by_name(&self, parent_inode_ino: u64, name: &str) -> Option<&Inode> {
-        let index = (parent_inode_ino as usize) - 1;
-        let parent_inode = &self.super_block[index];
-
-        match parent_inode {
-            Some(parent_inode) => {
-                // Buscar la matriz de referencia de Inode
-                for ino_ref in parent_inode.references.iter() {
-                    // Si hay algún dato dentro de ino_ref, ingrese el bloque y obtenga ese contenido
-                    if let Some(ino) = ino_ref {
-                        let index: usize = (ino.clone() as usize) - 1;
-                        let inode_ref = &self.super_block[index];
-
-                        match inode_ref {
-                            Some(inode) => {
-                                let name_from_inode: String = inode.name.iter().collect::<String>();
-                                let name_from_inode: &str = name_from_inode.as_str().trim_matches(char::from(0)); // Eliminación de caracteres '\0'
-                                let name = name.trim();
-                                println!("    - access(name={:?}, name_from_inode={:?}, equals={})", name, name_from_inode, name_from_inode == name);
-                                
-                                if name_from_inode == name {
-                                    return Some(inode);
-                                }
-                            },
-                            None => panic!("fn get_inode_by_name: referencia a inodo no encontrado!")
-                        }
-                    }
+
+    /// Registra un handle abierto del archivo `ino`.
+    pub fn open_handle(&mut self, ino: u64) {
+        *self.open_handles.entry(ino).or_insert(0) += 1;
+    }
+
+    /// Cierra un handle del archivo `ino`. Si era el último y el archivo ya no tiene enlaces, se libera.
+    pub fn release_handle(&mut self, ino: u64) -> Result<(), DiskError> {
+        if let Some(handles) = self.open_handles.get_mut(&ino) {
+            *handles -= 1;
+            if *handles == 0 {
+                self.open_handles.remove(&ino);
+            }
+        }
+        self.free_if_unlinked(ino)
+    }
+
+    /// Cuenta los enlaces de cada inodo a partir de las entradas de los directorios: las entradas que lo nombran y, en
+    /// un directorio, su propia entrada "." y la ".." de cada subdirectorio. La raíz es su propio directorio padre.
+    fn count_links(&self) -> Result<HashMap<u64, u32>, DiskError> {
+        let mut links = HashMap::new();
+        for inode in self.super_block.iter().flatten() {
+            if inode.attributes.kind != FileType::Directory {
+                continue;
+            }
+
+            let ino = inode.attributes.ino;
+            *links.entry(ino).or_insert(0) += if ino == 1 { 2 } else { 1 };
+            for entry in self.directory_entries(ino)?.unwrap_or_default() {
+                *links.entry(entry.ino).or_insert(0) += 1;
+                if entry.kind == FileType::Directory {
+                    *links.entry(ino).or_insert(0) += 1;
                 }
-            },
-            None => panic!("fn get_inode_by_name: inodo padre no encontrado!")
+            }
         }
 
-        return None;
+        Ok(links)
     }
 
-    /// Funcion que retorna un vector de refencia
-    #[allow(dead_code)]
-    pub fn get_references_from_inode(&self, ino: u64) -> &[Option<usize>; 128] {
-        let index = (ino as usize) - 1;
-        match &self.super_block[index] {
-            Some(inode) => &inode.references,
-            None => panic!("fn get_references_from_inode: inodo no encontrado!")
+    /// Cambia la cantidad de enlaces de cada inodo por la que surge de las entradas de los directorios.
+    fn recount_links(&mut self) -> Result<(), DiskError> {
+        let links = self.count_links()?;
+        for index in 0..self.max_files {
+            let (ino, nlink) = match &self.super_block[index] {
+                Some(inode) => (inode.attributes.ino, inode.attributes.nlink),
+                None => continue
+            };
+
+            let counted = links.get(&ino).copied().unwrap_or(0);
+            if nlink != counted {
+                if let Some(inode) = self.get_inode_as_mut(ino) {
+                    inode.attributes.nlink = counted;
+                }
+            }
         }
+
+        Ok(())
     }
 
-    /// Funcion que recupera el contenido de un bloque de memoria convertido a `str`
-    #[allow(dead_code)]
-    pub fn get_content(&self, block_index: usize) -> Option<&str> {
-        let data = self.get_content_as_bytes(block_index);
-        
-        match &data {
-            Some(data) => {
-                Option::Some(str::from_utf8(&data).unwrap())
-            },
-            None => None
+    /// Archivos sin enlaces. Al cargar el disco no hay handles abiertos, así que son archivos borrados mientras estaban
+    /// abiertos en un montaje que se interrumpió.
+    fn unlinked_inodes(&self) -> Vec<u64> {
+        self.super_block.iter().flatten()
+            .filter(|inode| inode.attributes.nlink == 0 && inode.attributes.kind != FileType::Directory)
+            .map(|inode| inode.attributes.ino)
+            .collect()
+    }
+
+    /// Libera los archivos sin enlaces de `unlinked_inodes`. Devuelve la cantidad de archivos liberados.
+    fn free_unlinked_inodes(&mut self) -> Result<usize, DiskError> {
+        let unlinked = self.unlinked_inodes();
+        for ino in unlinked.iter() {
+            self.free_inode(*ino)?;
         }
+
+        Ok(unlinked.len())
     }
 
-    /// Recupera una matriz de bytes prestados de un bloque especificado.
-    ///
-    /// # Ejemplos
-    ///
-    /// ```.
-    /// let disk = disk::new(argumentos);
-    /// let content: [u8] = disk.get_content_as_bytes(1);
-    /// ```
-    pub fn get_content_as_bytes(&self, block_index: usize) -> &Option<Box<[u8]>> {
-        let memory_block = &self.memory_blocks[block_index];
-        return &memory_block.data;
+    /// Funcion que devuelve la referencia de memoria mutable de `Inode`. El inodo queda entre los cambios de la
+    /// operación en curso.
+    pub fn get_inode_as_mut(&mut self, ino: u64) -> Option<&mut Inode> {
+        let index = self.inode_index(ino)?;
+        self.save_inode_for_undo(index);
+        self.pending_inodes.insert(index);
+        self.super_block[index].as_mut()
     }
 
-    /// Escribir datos en bytes en un bloque de memoria
-    ///
-    /// # Ejemplos
-    ///
-    /// ```
-    /// let content: Box<[u8]> = Box::from(content.as_bytes());
-    /// let disk: disk = disk::new(argumentos);
-    /// disk.write_content_as_bytes(1, contenido);
-    /// ```
-    ///
-    /// Solo se escribe si es una ubicación de memoria válida
-    pub fn write_content_as_bytes(&mut self, block_index: usize, content: Box<[u8]>) {
-        if content.len() > self.block_size {
-            panic!("No se puede guardar el contenido del archivo porque excede el tamaño del bloque de memoria {}", self.block_size);
+    /// Funcion que devuelve el `Inodo` especificado por su número `ino`. Un número fuera de la tabla de inodos, como el
+    /// de una entrada dañada, se trata como un inodo que no existe.
+    pub fn get_inode(&self, ino: u64) -> Option<&Inode> {
+        (ino as usize).checked_sub(1).and_then(|index| self.super_block.get(index)).and_then(|inode| inode.as_ref())
+    }
+
+    /// Posición en `super_block` del inodo número `ino`, o `None` si el número está fuera de la tabla de inodos.
+    fn inode_index(&self, ino: u64) -> Option<usize> {
+        (ino as usize).checked_sub(1).filter(|index| *index < self.super_block.len())
+    }
+    
+    /// Busca en el directorio `parent_inode_ino` la entrada llamada `name` y devuelve su inodo. Devuelve
+    /// `DiskError::NameTooLong` o `DiskError::InvalidName` si `name` no puede ser el nombre de una entrada.
+    pub fn find_inode_in_references_by_name(&self, parent_inode_ino: u64, name: &OsStr) -> Result<Option<&Inode>, DiskError> {
+        check_name(name)?;
+        let ino = self.with_directory_index(parent_inode_ino, |index| index.get(name))?;
+        Ok(ino.flatten().and_then(|ino| self.get_inode(ino)))
+    }
+
+    /// Aplica `action` al índice de entradas del directorio `ino`, armándolo a partir de su contenido si todavía no
+    /// estaba en memoria. Devuelve `None` si `ino` no es un directorio.
+    fn with_directory_index<T, F>(&self, ino: u64, action: F) -> Result<Option<T>, DiskError>
+    where F: FnOnce(&mut DirectoryIndex) -> T {
+        match self.get_inode(ino) {
+            Some(inode) if inode.attributes.kind == FileType::Directory => (),
+            _ => return Ok(None)
+        }
+
+        let cached = self.directory_index.borrow_mut().get(ino).is_some();
+        if !cached {
+            let content = self.read_file(ino, 0, u32::MAX)?.unwrap_or_default();
+            let index = DirectoryIndex::build(&content)?;
+            self.directory_index.borrow_mut().insert(ino, index);
         }
 
-        let memory_block = MemoryBlock { data: Some(content) };
-        self.memory_blocks[block_index] = memory_block;
+        let mut directory_index = self.directory_index.borrow_mut();
+        Ok(directory_index.get(ino).map(action))
     }
 
-    /// Escribe una referencia al vector de referencia de un Inodo ino-numerado
-    pub fn write_reference_in_inode(&mut self, ino: u64, ref_index: usize, ref_content: usize) {
-        let index = (ino as usize) - 1;
-        match &mut self.super_block[index] {
-            Some(inode) => {
-                inode.references[ref_index] = Some(ref_content);
-            },
-            None => panic!("fn write_reference_in_inode: inodo no encontrado!")
+    /// Entradas del directorio `ino`, en el orden en que están guardadas, o `None` si no es un directorio.
+    pub fn directory_entries(&self, ino: u64) -> Result<Option<Vec<DirectoryEntry>>, DiskError> {
+        match self.get_inode(ino) {
+            Some(inode) if inode.attributes.kind == FileType::Directory => (),
+            _ => return Ok(None)
         }
+
+        let content = self.read_file(ino, 0, u32::MAX)?.unwrap_or_default();
+        let mut entries = Vec::new();
+        for bytes in content.chunks(DIRECTORY_ENTRY_SIZE) {
+            entries.extend(DirectoryEntry::decode(bytes)?);
+        }
+
+        Ok(Some(entries))
     }
 
-    pub fn write_to_disk(&mut self) {
-        match serialize(&self.super_block) {
-            Err(e) => {
-                print!("¡Error al intentar escribir en el archivo inodes! {}", e);
-                return;
-            },
-            Ok(v) => {
-                let inode_file = format!("{}/inode.qrfs", &self.root_path);
-                let mut inode_file = OpenOptions::new().write(true).open(inode_file).unwrap();
-                match inode_file.write(&v) {
-                    Err(e) => {
-                        print!("¡Error al intentar escribir en el archivo inodes! {}", e);
-                        return;
-                    },
-                    Ok(v) => v,
-                };
-            },
-        };
+    /// Cantidad de entradas del directorio `ino`.
+    pub fn directory_entry_count(&self, ino: u64) -> Result<usize, DiskError> {
+        Ok(self.with_directory_index(ino, |index| index.len())?.unwrap_or(0))
+    }
 
-        match serialize(&self.memory_blocks) {
-            Err(e) => {
-                print!("¡Error al intentar escribir en el archivo de disco! {}", e);
-                return;
-            },
-            Ok(v) => {
-                let disk_file = format!("{}/disco.qrfs", &self.root_path);
-                let mut disk_file = OpenOptions::new().write(true).open(disk_file).unwrap();
-                match disk_file.write(&v) {
-                    Err(e) => {
-                        print!("¡Error al intentar escribir en el archivo inodes! {}", e);
-                        return;
-                    },
-                    Ok(v) => v,
-                };
-            },
-        };
+    /// Indica si `ino` es el directorio `directory` o está dentro de él, en cualquiera de sus subdirectorios.
+    pub fn directory_contains(&self, directory: u64, ino: u64) -> Result<bool, DiskError> {
+        let mut pending = vec![directory];
+        let mut visited = BTreeSet::new();
+        while let Some(current) = pending.pop() {
+            if current == ino {
+                return Ok(true);
+            }
+
+            // Un subdirectorio que no existe o que aparece dos veces, que no puede tener enlaces duros, es una entrada
+            // dañada
+            if !visited.insert(current) {
+                return Err(DiskError::Inconsistent(format!("el directorio {} aparece en más de una entrada", current)));
+            }
+            let entries = self.directory_entries(current)?
+                .ok_or_else(|| DiskError::Inconsistent(format!("una entrada apunta al directorio {}, que no existe", current)))?;
+            for entry in entries {
+                if entry.kind == FileType::Directory {
+                    pending.push(entry.ino);
+                }
+            }
+        }
+
+        Ok(false)
     }
-}
\ No newline at end of file
+
+    /// Agrega `entry` al directorio `parent`, en la primera posición libre o al final de su contenido. No revisa que
+    /// el nombre esté libre: eso se hace antes con `find_inode_in_references_by_name`. Devuelve
+    /// `DiskError::NoSpace` si no quedan bloques para hacer crecer el directorio.
+    pub fn add_directory_entry(&mut self, parent: u64, entry: DirectoryEntry) -> Result<(), DiskError> {
+        check_name(&entry.name)?;
+        let bytes = entry.encode().ok_or_else(|| DiskError::NameTooLong(entry.name.clone()))?;
+        let slot = self.with_directory_index(parent, |index| index.next_slot())?.ok_or(DiskError::NotADirectory(parent))?;
+        self.undo_directories.insert(parent);
+
+        let offset = (slot * DIRECTORY_ENTRY_SIZE) as u64;
+        let size = self.get_inode(parent).map_or(0, |inode| inode.attributes.size);
+        if self.write_file(parent, offset, &bytes)? < bytes.len() {
+            // Lo que se llegó a escribir de la entrada se descarta
+            self.set_file_size(parent, size)?;
+            return Err(DiskError::NoSpace);
+        }
+
+        self.with_directory_index(parent, |index| index.insert(entry.name, entry.ino, slot))?;
+        Ok(())
+    }
+
+    /// Hace que la entrada `entry.name` del directorio `parent` apunte a `entry.ino`, escribiéndola en la misma
+    /// posición, así que no hace falta espacio nuevo. Devuelve el número de inodo al que apuntaba, o `None` si el
+    /// directorio no tiene una entrada con ese nombre.
+    pub fn replace_directory_entry(&mut self, parent: u64, entry: DirectoryEntry) -> Result<Option<u64>, DiskError> {
+        let bytes = entry.encode().ok_or_else(|| DiskError::NameTooLong(entry.name.clone()))?;
+        self.undo_directories.insert(parent);
+        let (previous, slot) = match self.with_directory_index(parent, |index| index.replace(&entry.name, entry.ino))?.flatten() {
+            Some(replaced) => replaced,
+            None => return Ok(None)
+        };
+
+        self.write_file(parent, (slot * DIRECTORY_ENTRY_SIZE) as u64, &bytes)?;
+        Ok(Some(previous))
+    }
+
+    /// Quita del directorio `parent` la entrada llamada `name` y devuelve el número de inodo al que apuntaba. Su
+    /// posición queda libre; las posiciones libres del final se descartan, liberando los bloques que sobren.
+    pub fn remove_directory_entry(&mut self, parent: u64, name: &OsStr) -> Result<Option<u64>, DiskError> {
+        self.undo_directories.insert(parent);
+        let removed = self.with_directory_index(parent, |index| {
+            let ino = index.get(name)?;
+            let slot = index.remove(name)?;
+            Some((ino, slot, index.trim()))
+        })?.flatten();
+
+        let (ino, slot, slots) = match removed {
+            Some(removed) => removed,
+            None => return Ok(None)
+        };
+
+        if slot < slots {
+            self.write_file(parent, (slot * DIRECTORY_ENTRY_SIZE) as u64, &[0; DIRECTORY_ENTRY_SIZE])?;
+        } else {
+            self.set_file_size(parent, (slots * DIRECTORY_ENTRY_SIZE) as u64)?;
+        }
+
+        Ok(Some(ino))
+    }
+
+    /// Guarda `target` como destino del enlace simbólico `ino`: dentro del inodo si no pasa de `INLINE_TARGET_CAPACITY`
+    /// bytes y en sus bloques de datos si es más largo. El tamaño del enlace es el largo del destino.
+    pub fn write_symlink_target(&mut self, ino: u64, target: &OsStr) -> Result<(), DiskError> {
+        let target = target.as_bytes();
+        if target.len() > SYMLINK_MAX {
+            return Err(DiskError::TargetTooLong(target.len()));
+        }
+
+        if target.len() <= INLINE_TARGET_CAPACITY {
+            if let Some(inode) = self.get_inode_as_mut(ino) {
+                inode.target = Some(target.to_vec());
+                inode.attributes.size = target.len() as u64;
+            }
+            return Ok(());
+        }
+
+        if self.write_file(ino, 0, target)? < target.len() {
+            self.set_file_size(ino, 0)?;
+            return Err(DiskError::NoSpace);
+        }
+        Ok(())
+    }
+
+    /// Destino del enlace simbólico `ino`, o `None` si no es un enlace simbólico.
+    pub fn read_symlink_target(&self, ino: u64) -> Result<Option<OsString>, DiskError> {
+        let inode = match self.get_inode(ino) {
+            Some(inode) if inode.attributes.kind == FileType::Symlink => inode,
+            _ => return Ok(None)
+        };
+
+        let target = match &inode.target {
+            Some(target) => target.clone(),
+            None => self.read_file(ino, 0, inode.attributes.size as u32)?.unwrap_or_default()
+        };
+        Ok(Some(OsString::from_vec(target)))
+    }
+
+    /// Sigue el enlace simbólico `ino`, guardado en el directorio `directory`, y devuelve el inodo al que lleva, o
+    /// `None` si su destino no existe dentro del disco. `parents` tiene el directorio padre de cada directorio. Los
+    /// destinos absolutos dependen de dónde se monte el disco y se dan por existentes.
+    fn resolve_symlink(&self, directory: u64, ino: u64, parents: &HashMap<u64, u64>, depth: usize) -> Option<u64> {
+        if depth >= MAX_SYMLINK_DEPTH {
+            return None;
+        }
+
+        let target = self.read_symlink_target(ino).ok().flatten()?;
+        if target.as_bytes().starts_with(b"/") {
+            return Some(ino);
+        }
+
+        let mut current = directory;
+        for component in target.as_bytes().split(|byte| *byte == b'/').filter(|component| !component.is_empty()) {
+            if self.get_inode(current)?.attributes.kind != FileType::Directory {
+                return None;
+            }
+
+            current = match component {
+                b"." => current,
+                b".." => parents.get(&current).copied().unwrap_or(current),
+                name => {
+                    let inode = self.find_inode_in_references_by_name(current, OsStr::from_bytes(name)).ok().flatten()?;
+                    match inode.attributes.kind {
+                        FileType::Symlink => self.resolve_symlink(current, inode.attributes.ino, parents, depth + 1)?,
+                        _ => inode.attributes.ino
+                    }
+                }
+            };
+        }
+
+        Some(current)
+    }
+
+    /// Enlaces simbólicos cuyo destino no existe dentro del disco o forma un ciclo. No son un error del disco, pero
+    /// fsck_qrfs los avisa.
+    #[allow(dead_code)]
+    pub fn dangling_symlinks(&self) -> Vec<String> {
+        let mut parents = HashMap::new();
+        let mut symlinks = Vec::new();
+        for inode in self.super_block.iter().flatten() {
+            let ino = inode.attributes.ino;
+            for entry in self.directory_entries(ino).ok().flatten().unwrap_or_default() {
+                match entry.kind {
+                    FileType::Directory => {
+                        parents.insert(entry.ino, ino);
+                    },
+                    FileType::Symlink => symlinks.push((ino, entry)),
+                    _ => ()
+                }
+            }
+        }
+
+        let mut dangling = Vec::new();
+        for (directory, entry) in symlinks {
+            if self.resolve_symlink(directory, entry.ino, &parents, 0).is_none() {
+                let target = self.read_symlink_target(entry.ino).ok().flatten().unwrap_or_default();
+                dangling.push(format!("El enlace simbólico {:?} del directorio {} apunta a {:?}, que no existe dentro del disco", entry.name, directory, target));
+            }
+        }
+
+        dangling
+    }
+
+    /// Valor del atributo extendido `name` del inodo `ino`, o `None` si no lo tiene.
+    pub fn get_xattr(&self, ino: u64, name: &OsStr) -> Result<Option<Vec<u8>>, DiskError> {
+        let value = match self.get_inode(ino).and_then(|inode| inode.xattrs.get(name.as_bytes())) {
+            Some(value) => value,
+            None => return Ok(None)
+        };
+
+        let (length, blocks) = match value {
+            XattrValue::Inline(value) => return Ok(Some(value.clone())),
+            XattrValue::Blocks { length, blocks } => (*length, blocks)
+        };
+
+        let mut content = Vec::with_capacity(length);
+        for block in blocks {
+            let data = self.get_content_as_bytes(*block)?
+                .ok_or_else(|| DiskError::Corrupted(format!("el bloque {} del atributo {:?} del inodo {}", block, name, ino)))?;
+            content.extend_from_slice(&data);
+        }
+        content.resize(length, 0);
+        Ok(Some(content))
+    }
+
+    /// Nombres de los atributos extendidos del inodo `ino`, en orden.
+    pub fn xattr_names(&self, ino: u64) -> Vec<OsString> {
+        match self.get_inode(ino) {
+            Some(inode) => inode.xattrs.keys().map(|name| OsString::from_vec(name.clone())).collect(),
+            None => Vec::new()
+        }
+    }
+
+    /// Guarda `value` como valor del atributo extendido `name` del inodo `ino`, reemplazando el anterior. Los valores
+    /// de hasta `INLINE_XATTR_CAPACITY` bytes quedan en el inodo y los más largos en bloques de datos nuevos; los
+    /// bloques del valor anterior se liberan. Devuelve `NoSpace` si no quedan bloques libres o si los atributos ya no
+    /// caben en el inodo, e `InodeNotFound` si el inodo `ino` no existe.
+    pub fn set_xattr(&mut self, ino: u64, name: &OsStr, value: &[u8]) -> Result<(), DiskError> {
+        let mut xattrs = match self.get_inode(ino) {
+            Some(inode) => inode.xattrs.clone(),
+            None => return Err(DiskError::InodeNotFound(ino))
+        };
+
+        let stored = if value.len() <= INLINE_XATTR_CAPACITY {
+            XattrValue::Inline(value.to_vec())
+        } else {
+            let mut blocks = Vec::new();
+            for chunk in value.chunks(self.block_size) {
+                match self.allocate_memory_block()? {
+                    Some(block) => {
+                        self.write_content_as_bytes(block, Box::from(chunk))?;
+                        blocks.push(block);
+                    },
+                    None => {
+                        blocks.iter().for_each(|block| self.clear_memory_block(*block));
+                        return Err(DiskError::NoSpace);
+                    }
+                }
+            }
+            XattrValue::Blocks { length: value.len(), blocks }
+        };
+
+        let new_blocks = stored.blocks().to_vec();
+        let previous = xattrs.insert(name.as_bytes().to_vec(), stored);
+        if serialized_size(&xattrs)? > XATTR_INODE_CAPACITY {
+            new_blocks.iter().for_each(|block| self.clear_memory_block(*block));
+            return Err(DiskError::NoSpace);
+        }
+
+        for block in previous.iter().flat_map(|previous| previous.blocks()) {
+            self.clear_memory_block(*block);
+        }
+        if let Some(inode) = self.get_inode_as_mut(ino) {
+            inode.xattrs = xattrs;
+            inode.attributes.ctime = time::now().to_timespec();
+        }
+        Ok(())
+    }
+
+    /// Quita el atributo extendido `name` del inodo `ino` y libera sus bloques. Devuelve `false` si no lo tenía.
+    pub fn remove_xattr(&mut self, ino: u64, name: &OsStr) -> bool {
+        let previous = match self.get_inode(ino) {
+            Some(inode) if inode.xattrs.contains_key(name.as_bytes()) => {
+                let inode = self.get_inode_as_mut(ino).unwrap();
+                inode.attributes.ctime = time::now().to_timespec();
+                inode.xattrs.remove(name.as_bytes())
+            },
+            _ => None
+        };
+
+        match previous {
+            Some(previous) => {
+                previous.blocks().iter().for_each(|block| self.clear_memory_block(*block));
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Indica si el directorio `inode` todavía guarda sus entradas como en el formato original: los números de inodo de
+    /// sus archivos en el vector `references`, con los nombres en cada inodo. Esos directorios siempre tienen tamaño 0,
+    /// mientras que uno con entradas en bloques de datos tiene el tamaño de sus entradas.
+    fn has_legacy_entries(inode: &Inode) -> bool {
+        inode.attributes.kind == FileType::Directory && inode.attributes.size == 0 && inode.references.iter().any(Option::is_some)
+    }
+
+    /// Pasa las entradas de los directorios del formato original a sus bloques de datos, con los nombres `names` que
+    /// tenían sus inodos. Devuelve la cantidad de directorios migrados.
+    fn migrate_directory_entries(&mut self, names: &HashMap<u64, OsString>) -> Result<usize, DiskError> {
+        let directories: Vec<u64> = self.super_block.iter().flatten()
+            .filter(|inode| Disk::has_legacy_entries(inode))
+            .map(|inode| inode.attributes.ino)
+            .collect();
+
+        for ino in directories.iter() {
+            let children: Vec<usize> = self.get_references_from_inode(*ino)?.iter().flatten().copied().collect();
+            // Los bloques de las entradas se cuentan a medida que se agregan, como en un directorio nuevo
+            if let Some(inode) = self.get_inode_as_mut(*ino) {
+                inode.references = [None; 128];
+                inode.attributes.blocks = 0;
+            }
+            self.directory_index.get_mut().remove(*ino);
+
+            for child in children {
+                let (child, kind) = match child.checked_sub(1).and_then(|index| self.super_block.get(index)) {
+                    Some(Some(inode)) if inode.attributes.ino != *ino => (inode.attributes.ino, inode.attributes.kind),
+                    _ => continue
+                };
+
+                // Un inodo sin nombre se llama como su número, y uno de 64 caracteres que no entra en `NAME_CAPACITY`
+                // bytes se corta
+                let mut name = names.get(&child).map(|name| name.as_bytes().to_vec())
+                    .filter(|name| !name.is_empty())
+                    .unwrap_or_else(|| child.to_string().into_bytes());
+                name.truncate(NAME_CAPACITY);
+
+                // Un nombre repetido de una versión anterior se conserva con el número de inodo al final
+                if self.find_inode_in_references_by_name(*ino, OsStr::from_bytes(&name))?.is_some() {
+                    let suffix = format!("~{}", child);
+                    name.truncate(NAME_CAPACITY - suffix.len());
+                    name.extend_from_slice(suffix.as_bytes());
+                }
+                self.add_directory_entry(*ino, DirectoryEntry { ino: child, kind, name: OsString::from_vec(name) })?;
+            }
+        }
+
+        Ok(directories.len())
+    }
+
+    /// Funcion que retorna un vector de refencia. Devuelve `InodeNotFound` si el inodo `ino` no existe.
+    #[allow(dead_code)]
+    pub fn get_references_from_inode(&self, ino: u64) -> Result<&[Option<usize>; 128], DiskError> {
+        match self.get_inode(ino) {
+            Some(inode) => Ok(&inode.references),
+            None => Err(DiskError::InodeNotFound(ino))
+        }
+    }
+
+    /// Funcion que recupera el contenido de un bloque de memoria convertido a `String`
+    #[allow(dead_code)]
+    pub fn get_content(&self, block_index: usize) -> Result<Option<String>, DiskError> {
+        let data = self.get_content_as_bytes(block_index)?;
+        
+        match &data {
+            Some(data) => {
+                Ok(Option::Some(str::from_utf8(data).unwrap().to_string()))
+            },
+            None => Ok(None)
+        }
+    }
+
+    /// Recupera el contenido de un bloque especificado. Un bloque que no cambió desde la última vez que se guardó se
+    /// lee de su casilla en `disco.qrfs` la primera vez que se usa y queda en la caché de bloques.
+    ///
+    /// # Ejemplos
+    ///
+    /// ```.
+    /// let disk = disk::new(argumentos);
+    /// let content: Option<Rc<[u8]>> = disk.get_content_as_bytes(1)?;
+    /// ```
+    pub fn get_content_as_bytes(&self, block_index: usize) -> Result<Option<Rc<[u8]>>, DiskError> {
+        if block_index >= self.block_count {
+            return Err(DiskError::GeometryMismatch(format!("el bloque {} está fuera del disco de {} bloques", block_index, self.block_count)));
+        }
+
+        if let Some(data) = self.modified_blocks.get(&block_index) {
+            return Ok(data.clone());
+        }
+
+        if let Some(data) = self.cache.borrow_mut().get(block_index) {
+            return Ok(Some(Rc::clone(data)));
+        }
+
+        let sealed = match &self.image {
+            Some(image) => image.read_block(block_index)?,
+            None => None
+        };
+
+        match sealed {
+            Some(sealed) => {
+                let data: Rc<[u8]> = self.key.decrypt(&sealed, &block_context(block_index))
+                    .ok_or_else(|| DiskError::Corrupted(format!("el bloque {}", block_index)))?
+                    .into();
+                self.cache.borrow_mut().insert(block_index, Rc::clone(&data));
+                Ok(Some(data))
+            },
+            None => Ok(None)
+        }
+    }
+
+    /// Escribir datos en bytes en un bloque de memoria
+    ///
+    /// # Ejemplos
+    ///
+    /// ```
+    /// let content: Box<[u8]> = Box::from(content.as_bytes());
+    /// let disk: disk = disk::new(argumentos);
+    /// disk.write_content_as_bytes(1, contenido)?;
+    /// ```
+    ///
+    /// Solo se escribe si es una ubicación de memoria válida: devuelve `BlockOutOfRange` si el bloque no existe y
+    /// `BlockOverflow` si el contenido excede el tamaño del bloque.
+    pub fn write_content_as_bytes(&mut self, block_index: usize, content: Box<[u8]>) -> Result<(), DiskError> {
+        if block_index >= self.block_count {
+            return Err(DiskError::BlockOutOfRange(block_index));
+        }
+        if content.len() > self.block_size {
+            return Err(DiskError::BlockOverflow(content.len()));
+        }
+
+        self.set_block(block_index, Some(Rc::from(content)));
+        self.block_map.set(block_index);
+        Ok(())
+    }
+
+    /// Escribe una referencia al vector de referencia de un Inodo ino-numerado. Devuelve `InodeNotFound` si el inodo
+    /// no existe.
+    pub fn write_reference_in_inode(&mut self, ino: u64, ref_index: usize, ref_content: usize) -> Result<(), DiskError> {
+        match self.get_inode_as_mut(ino) {
+            Some(inode) => {
+                inode.references[ref_index] = Some(ref_content);
+                Ok(())
+            },
+            None => Err(DiskError::InodeNotFound(ino))
+        }
+    }
+
+    /// Cantidad de referencias que caben en un bloque indirecto.
+    fn references_per_block(&self) -> usize {
+        self.block_size / INDIRECT_ENTRY_SIZE
+    }
+
+    /// Suma `added` bloques y resta `freed` bloques a los que ocupa el archivo `ino`. Como `st_blocks`, la cuenta se
+    /// lleva en unidades de 512 bytes e incluye tanto los bloques de datos como los indirectos.
+    fn charge_blocks(&mut self, ino: u64, added: u64, freed: u64) {
+        let sectors = (self.block_size / SECTOR_SIZE) as u64;
+        if let Some(inode) = self.get_inode_as_mut(ino) {
+            inode.attributes.blocks = (inode.attributes.blocks + added * sectors).saturating_sub(freed * sectors);
+        }
+    }
+
+    /// Traduce la posición lógica `position` de un bloque de archivo a su ubicación en el mapa de bloques.
+    /// Devuelve `None` si la posición excede lo que puede direccionar el bloque indirecto doble.
+    fn block_path(&self, position: usize) -> Option<BlockPath> {
+        let per_block = self.references_per_block();
+
+        if position < DIRECT_REFERENCES {
+            return Some(BlockPath::Direct(position));
+        }
+
+        let position = position - DIRECT_REFERENCES;
+        if position < per_block {
+            return Some(BlockPath::SingleIndirect(position));
+        }
+
+        let position = position - per_block;
+        if position < per_block * per_block {
+            return Some(BlockPath::DoubleIndirect(position / per_block, position % per_block));
+        }
+
+        None
+    }
+
+    /// Lee la entrada `position` de un bloque indirecto.
+    /// Las entradas se guardan como `u64` little-endian con el índice del bloque + 1, así un 0 (o una entrada
+    /// más allá del contenido del bloque) significa que no hay bloque asignado.
+    fn read_indirect_entry(&self, block_index: usize, position: usize) -> Result<Option<usize>, DiskError> {
+        let data = match self.get_content_as_bytes(block_index)? {
+            Some(data) => data,
+            None => return Ok(None)
+        };
+        let start = position * INDIRECT_ENTRY_SIZE;
+
+        if start + INDIRECT_ENTRY_SIZE > data.len() {
+            return Ok(None);
+        }
+
+        let mut bytes = [0u8; INDIRECT_ENTRY_SIZE];
+        bytes.copy_from_slice(&data[start..start + INDIRECT_ENTRY_SIZE]);
+
+        match u64::from_le_bytes(bytes) {
+            0 => Ok(None),
+            value => Ok(Some((value - 1) as usize))
+        }
+    }
+
+    /// Escribe la entrada `position` de un bloque indirecto. Las entradas vacías del final se recortan para que
+    /// el bloque ocupe solo lo necesario.
+    fn write_indirect_entry(&mut self, block_index: usize, position: usize, value: Option<usize>) -> Result<(), DiskError> {
+        let mut data = self.get_content_as_bytes(block_index)?.map(|data| data.to_vec()).unwrap_or_default();
+        let start = position * INDIRECT_ENTRY_SIZE;
+
+        if data.len() < start + INDIRECT_ENTRY_SIZE {
+            data.resize(start + INDIRECT_ENTRY_SIZE, 0);
+        }
+
+        let raw = match value {
+            Some(index) => (index as u64) + 1,
+            None => 0
+        };
+        data[start..start + INDIRECT_ENTRY_SIZE].copy_from_slice(&raw.to_le_bytes());
+
+        while data.len() >= INDIRECT_ENTRY_SIZE && data[data.len() - INDIRECT_ENTRY_SIZE..].iter().all(|b| *b == 0) {
+            data.truncate(data.len() - INDIRECT_ENTRY_SIZE);
+        }
+
+        self.write_content_as_bytes(block_index, data.into_boxed_slice())
+    }
+
+    /// Cantidad de entradas (ocupadas o no) que contiene un bloque indirecto.
+    fn indirect_entry_count(&self, block_index: usize) -> Result<usize, DiskError> {
+        match self.get_content_as_bytes(block_index)? {
+            Some(data) => Ok(data.len() / INDIRECT_ENTRY_SIZE),
+            None => Ok(0)
+        }
+    }
+
+    /// Reserva un bloque de memoria vacío y lo marca como ocupado. Devuelve `None` si no quedan bloques libres.
+    fn allocate_memory_block(&mut self) -> Result<Option<usize>, DiskError> {
+        let index = match self.find_index_of_empty_memory_block() {
+            Some(index) => index,
+            None => return Ok(None)
+        };
+        self.write_content_as_bytes(index, Box::default())?;
+        Ok(Some(index))
+    }
+
+    /// Devuelve el bloque indirecto guardado en la referencia `reference` del inodo, reservándolo si no existe.
+    fn get_or_allocate_indirect_block(&mut self, ino: u64, reference: usize) -> Result<Option<usize>, DiskError> {
+        if let Some(index) = self.get_references_from_inode(ino)?[reference] {
+            return Ok(Some(index));
+        }
+
+        let index = match self.allocate_memory_block()? {
+            Some(index) => index,
+            None => return Ok(None)
+        };
+        self.write_reference_in_inode(ino, reference, index)?;
+        self.charge_blocks(ino, 1, 0);
+        Ok(Some(index))
+    }
+
+    /// Devuelve el índice del bloque de memoria que guarda el bloque lógico `position` del archivo `ino`.
+    pub fn get_data_block_index(&self, ino: u64, position: usize) -> Result<Option<usize>, DiskError> {
+        let (inode, path) = match (self.get_inode(ino), self.block_path(position)) {
+            (Some(inode), Some(path)) => (inode, path),
+            _ => return Ok(None)
+        };
+
+        match path {
+            BlockPath::Direct(index) => Ok(inode.references[index]),
+            BlockPath::SingleIndirect(index) => match inode.references[SINGLE_INDIRECT_REFERENCE] {
+                Some(indirect) => self.read_indirect_entry(indirect, index),
+                None => Ok(None)
+            },
+            BlockPath::DoubleIndirect(first, second) => match inode.references[DOUBLE_INDIRECT_REFERENCE] {
+                Some(indirect) => match self.read_indirect_entry(indirect, first)? {
+                    Some(indirect) => self.read_indirect_entry(indirect, second),
+                    None => Ok(None)
+                },
+                None => Ok(None)
+            }
+        }
+    }
+
+    /// Devuelve el bloque de memoria del bloque lógico `position` del archivo `ino`, reservando el bloque de datos
+    /// y los bloques indirectos que hagan falta. Devuelve `None` si ya no queda espacio en el disco.
+    pub fn allocate_data_block(&mut self, ino: u64, position: usize) -> Result<Option<usize>, DiskError> {
+        if let Some(index) = self.get_data_block_index(ino, position)? {
+            return Ok(Some(index));
+        }
+
+        let path = match self.block_path(position) {
+            Some(path) => path,
+            None => return Ok(None)
+        };
+        let index = match path {
+            BlockPath::Direct(reference) => {
+                let index = match self.allocate_memory_block()? {
+                    Some(index) => index,
+                    None => return Ok(None)
+                };
+                self.write_reference_in_inode(ino, reference, index)?;
+                index
+            },
+            BlockPath::SingleIndirect(entry) => {
+                let indirect = match self.get_or_allocate_indirect_block(ino, SINGLE_INDIRECT_REFERENCE)? {
+                    Some(indirect) => indirect,
+                    None => return Ok(None)
+                };
+                let index = match self.allocate_memory_block()? {
+                    Some(index) => index,
+                    None => return Ok(None)
+                };
+                self.write_indirect_entry(indirect, entry, Some(index))?;
+                index
+            },
+            BlockPath::DoubleIndirect(first, second) => {
+                let indirect = match self.get_or_allocate_indirect_block(ino, DOUBLE_INDIRECT_REFERENCE)? {
+                    Some(indirect) => indirect,
+                    None => return Ok(None)
+                };
+                let indirect = match self.read_indirect_entry(indirect, first)? {
+                    Some(second_level) => second_level,
+                    None => {
+                        let second_level = match self.allocate_memory_block()? {
+                            Some(second_level) => second_level,
+                            None => return Ok(None)
+                        };
+                        self.write_indirect_entry(indirect, first, Some(second_level))?;
+                        self.charge_blocks(ino, 1, 0);
+                        second_level
+                    }
+                };
+                let index = match self.allocate_memory_block()? {
+                    Some(index) => index,
+                    None => return Ok(None)
+                };
+                self.write_indirect_entry(indirect, second, Some(index))?;
+                index
+            }
+        };
+
+        self.charge_blocks(ino, 1, 0);
+
+        Ok(Some(index))
+    }
+
+    /// Libera las entradas de un bloque indirecto a partir de `first` y devuelve cuántos bloques liberó, contando
+    /// los indirectos de segundo nivel. Si `levels` es 2 cada entrada es a su vez un bloque indirecto.
+    fn free_indirect_entries_from(&mut self, indirect: usize, first: usize, levels: usize) -> Result<u64, DiskError> {
+        let per_block = self.references_per_block();
+        let mut freed = 0;
+
+        for entry in (0..self.indirect_entry_count(indirect)?).rev() {
+            let child = match self.read_indirect_entry(indirect, entry)? {
+                Some(child) => child,
+                None => continue
+            };
+
+            if levels == 1 {
+                if entry < first {
+                    break;
+                }
+                self.clear_memory_block(child);
+                freed += 1;
+            } else {
+                if (entry + 1) * per_block <= first {
+                    break;
+                }
+                let child_first = first.saturating_sub(entry * per_block);
+                freed += self.free_indirect_entries_from(child, child_first, levels - 1)?;
+                if self.indirect_entry_count(child)? > 0 {
+                    continue;
+                }
+                self.clear_memory_block(child);
+                freed += 1;
+            }
+
+            self.write_indirect_entry(indirect, entry, None)?;
+        }
+
+        Ok(freed)
+    }
+
+    /// Libera todos los bloques de datos del archivo `ino` desde el bloque lógico `first` en adelante, junto con
+    /// los bloques indirectos que queden vacíos.
+    pub fn free_data_blocks_from(&mut self, ino: u64, first: usize) -> Result<(), DiskError> {
+        let references = match self.get_inode(ino) {
+            Some(inode) => inode.references,
+            None => return Ok(())
+        };
+        let per_block = self.references_per_block();
+        let mut freed = 0;
+
+        for (reference, block) in references.iter().enumerate().take(DIRECT_REFERENCES).skip(first) {
+            if let Some(block) = block {
+                self.clear_memory_block(*block);
+                self.get_inode_as_mut(ino).unwrap().references[reference] = None;
+                freed += 1;
+            }
+        }
+
+        let levels = [(SINGLE_INDIRECT_REFERENCE, 1, DIRECT_REFERENCES), (DOUBLE_INDIRECT_REFERENCE, 2, DIRECT_REFERENCES + per_block)];
+        for (reference, level, start) in levels.iter() {
+            if let Some(indirect) = references[*reference] {
+                freed += self.free_indirect_entries_from(indirect, first.saturating_sub(*start), *level)?;
+                if self.indirect_entry_count(indirect)? == 0 {
+                    self.clear_memory_block(indirect);
+                    self.get_inode_as_mut(ino).unwrap().references[*reference] = None;
+                    freed += 1;
+                }
+            }
+        }
+
+        self.charge_blocks(ino, 0, freed);
+
+        Ok(())
+    }
+
+    /// Lee hasta `size` bytes del archivo `ino` a partir de la posición `offset`, sin pasar del fin del archivo.
+    /// Las partes del archivo sin bloque asignado (huecos) se devuelven como ceros. Devuelve `None` si el archivo no
+    /// existe.
+    pub fn read_file(&self, ino: u64, offset: u64, size: u32) -> Result<Option<Vec<u8>>, DiskError> {
+        let file_size = match self.get_inode(ino) {
+            Some(inode) => inode.attributes.size,
+            None => return Ok(None)
+        };
+
+        if offset >= file_size {
+            return Ok(Some(Vec::new()));
+        }
+
+        let end = file_size.min(offset + size as u64);
+        let mut content = vec![0u8; (end - offset) as usize];
+        let mut done = 0;
+
+        while done < content.len() {
+            let file_position = offset as usize + done;
+            let start = file_position % self.block_size;
+            let length = (self.block_size - start).min(content.len() - done);
+
+            if let Some(index) = self.get_data_block_index(ino, file_position / self.block_size)? {
+                if let Some(data) = self.get_content_as_bytes(index)? {
+                    if start < data.len() {
+                        let available = (data.len() - start).min(length);
+                        content[done..done + available].copy_from_slice(&data[start..start + available]);
+                    }
+                }
+            }
+
+            done += length;
+        }
+
+        Ok(Some(content))
+    }
+
+    /// Escribe `data` en el archivo `ino` a partir de la posición `offset`, mezclándolo con el contenido que ya
+    /// tienen los bloques y reservando los bloques nuevos que hagan falta. Si `offset` está más allá del fin del
+    /// archivo, el hueco intermedio queda en ceros. Devuelve la cantidad de bytes escritos, que es menor que
+    /// `data.len()` si el disco se queda sin espacio o si el archivo llega al tamaño máximo. Devuelve `FileTooLarge`
+    /// si `offset` ya está en el tamaño máximo o más allá.
+    pub fn write_file(&mut self, ino: u64, offset: u64, data: &[u8]) -> Result<usize, DiskError> {
+        let file_size = match self.get_inode(ino) {
+            Some(inode) => inode.attributes.size,
+            None => return Ok(0)
+        };
+
+        // Una escritura vacía no cambia el tamaño del archivo, aunque `offset` esté más allá del final
+        if data.is_empty() {
+            return Ok(0);
+        }
+
+        let max_file_size = self.max_file_size();
+        if offset >= max_file_size {
+            return Err(DiskError::FileTooLarge(offset.saturating_add(data.len() as u64)));
+        }
+        let room = max_file_size - offset;
+        let data = if data.len() as u64 > room { &data[..room as usize] } else { data };
+
+        if offset > file_size {
+            self.clear_file_tail(ino, file_size)?;
+        }
+
+        let mut written = 0;
+        while written < data.len() {
+            let file_position = offset as usize + written;
+            let start = file_position % self.block_size;
+            let length = (self.block_size - start).min(data.len() - written);
+
+            let index = match self.allocate_data_block(ino, file_position / self.block_size)? {
+                Some(index) => index,
+                None => break
+            };
+
+            let mut content = self.get_content_as_bytes(index)?.map(|data| data.to_vec()).unwrap_or_default();
+            if content.len() < start + length {
+                content.resize(start + length, 0);
+            }
+            content[start..start + length].copy_from_slice(&data[written..written + length]);
+            self.write_content_as_bytes(index, content.into_boxed_slice())?;
+
+            written += length;
+        }
+
+        // Solo una escritura que guardó datos cambia el contenido del archivo
+        let end = offset + written as u64;
+        if written > 0 {
+            if let Some(inode) = self.get_inode_as_mut(ino) {
+                if end > inode.attributes.size {
+                    inode.attributes.size = end;
+                }
+                let ts = time::now().to_timespec();
+                inode.attributes.mtime = ts;
+                inode.attributes.ctime = ts;
+            }
+        }
+
+        Ok(written)
+    }
+
+    /// Descarta los bytes que el bloque del fin de archivo guarda más allá de `size`, para que al extender el
+    /// archivo esa zona se lea como ceros y no como datos viejos.
+    fn clear_file_tail(&mut self, ino: u64, size: u64) -> Result<(), DiskError> {
+        let keep = (size % self.block_size as u64) as usize;
+        let position = (size / self.block_size as u64) as usize;
+
+        if let Some(index) = self.get_data_block_index(ino, position)? {
+            if let Some(data) = self.get_content_as_bytes(index)? {
+                if data.len() > keep {
+                    self.write_content_as_bytes(index, Box::from(&data[..keep]))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Cambia el tamaño del archivo `ino` a `size`, liberando los bloques que quedan fuera del nuevo tamaño. El bloque
+    /// del fin de archivo descarta los bytes que quedan afuera, así que al achicar y después agrandar el archivo, o al
+    /// agrandarlo, la parte nueva se lee como ceros. La parte nueva no reserva bloques: queda como hueco.
+    pub fn set_file_size(&mut self, ino: u64, size: u64) -> Result<(), DiskError> {
+        let old_size = match self.get_inode(ino) {
+            Some(inode) => inode.attributes.size,
+            None => return Ok(())
+        };
+
+        let block_size = self.block_size as u64;
+        let needed_blocks = size.div_ceil(block_size);
+        self.free_data_blocks_from(ino, needed_blocks as usize)?;
+        self.clear_file_tail(ino, size.min(old_size))?;
+
+        if let Some(inode) = self.get_inode_as_mut(ino) {
+            inode.attributes.size = size;
+        }
+
+        Ok(())
+    }
+
+    /// Trunca o extiende el archivo regular `ino` a `size` bytes, como `truncate(2)`, y actualiza sus tiempos de
+    /// modificación y de cambio. Devuelve `FileTooLarge` si el mapa de bloques no alcanza para ese tamaño.
+    pub fn truncate_file(&mut self, ino: u64, size: u64) -> Result<(), DiskError> {
+        if size > self.max_file_size() {
+            return Err(DiskError::FileTooLarge(size));
+        }
+
+        self.set_file_size(ino, size)?;
+        if let Some(inode) = self.get_inode_as_mut(ino) {
+            let ts = time::now().to_timespec();
+            inode.attributes.mtime = ts;
+            inode.attributes.ctime = ts;
+        }
+
+        Ok(())
+    }
+
+    /// Tamaño máximo de un archivo: los bloques que alcanzan las referencias directas, la indirecta y la doble
+    /// indirecta del inodo.
+    #[allow(dead_code)]
+    pub fn max_file_size(&self) -> u64 {
+        Disk::max_file_size_for(self.block_size)
+    }
+
+    /// Tamaño máximo de un archivo en un disco con bloques de `block_size` bytes. Con bloques grandes los bloques que
+    /// alcanza el inodo suman más de lo que cabe en un `u64`: la cuenta se hace en 128 bits y se limita a `u64::MAX`,
+    /// que igual deja alcanzar cualquier posición de un archivo.
+    fn max_file_size_for(block_size: usize) -> u64 {
+        let per_block = (block_size / INDIRECT_ENTRY_SIZE) as u128;
+        let size = (DIRECT_REFERENCES as u128 + per_block + per_block * per_block) * block_size as u128;
+        size.min(u64::MAX as u128) as u64
+    }
+
+    /// Migra un disco escrito con la convención anterior, en la que `write` guardaba el contenido de cada archivo
+    /// en el bloque `ino - 1` sin importar la referencia guardada en el inodo. El contenido se recupera de ese
+    /// bloque y se vuelve a escribir a través del mapa de bloques de cada archivo.
+    /// Devuelve la cantidad de archivos migrados.
+    pub fn migrate_legacy_layout(&mut self) -> Result<usize, DiskError> {
+        let mut contents: Vec<(u64, Vec<u8>)> = Vec::new();
+
+        for inode in self.super_block.iter().flatten() {
+            if inode.attributes.kind != FileType::RegularFile {
+                continue;
+            }
+
+            let ino = inode.attributes.ino;
+            let legacy_block = (ino as usize).checked_sub(1).and_then(|legacy_index| self.modified_blocks.get(&legacy_index));
+            let mut content = match legacy_block {
+                Some(Some(data)) => data.to_vec(),
+                _ => Vec::new()
+            };
+            content.resize(inode.attributes.size as usize, 0);
+            contents.push((ino, content));
+        }
+
+        // En la convención anterior solo los archivos regulares ocupaban bloques, así que se pueden liberar todos
+        for index in 0..self.block_count {
+            self.clear_memory_block(index);
+        }
+
+        for (ino, content) in contents.iter() {
+            if let Some(inode) = self.get_inode_as_mut(*ino) {
+                inode.references = [None; 128];
+                inode.attributes.size = 0;
+                inode.attributes.blocks = 0;
+            }
+            self.write_file(*ino, 0, content)?;
+        }
+
+        Ok(contents.len())
+    }
+
+    /// Devuelve todos los bloques de memoria que ocupa el archivo `ino`: los de datos, los indirectos y los de los
+    /// valores de sus atributos extendidos.
+    #[allow(dead_code)]
+    pub fn get_file_blocks(&self, ino: u64) -> Result<Vec<usize>, DiskError> {
+        let mut blocks = Vec::new();
+        let inode = match self.get_inode(ino) {
+            Some(inode) => inode,
+            None => return Ok(blocks)
+        };
+
+        blocks.extend(inode.references.iter().take(DIRECT_REFERENCES).flatten());
+
+        let levels = [(SINGLE_INDIRECT_REFERENCE, 1), (DOUBLE_INDIRECT_REFERENCE, 2)];
+        for (reference, level) in levels.iter() {
+            if let Some(indirect) = inode.references[*reference] {
+                self.collect_indirect_blocks(indirect, *level, &mut blocks)?;
+            }
+        }
+
+        blocks.extend(inode.xattrs.values().flat_map(|value| value.blocks().iter().copied()));
+
+        Ok(blocks)
+    }
+
+    fn collect_indirect_blocks(&self, indirect: usize, levels: usize, blocks: &mut Vec<usize>) -> Result<(), DiskError> {
+        blocks.push(indirect);
+
+        // Un índice fuera del disco se reporta, pero no se recorre
+        if indirect >= self.block_count {
+            return Ok(());
+        }
+
+        for entry in 0..self.indirect_entry_count(indirect)? {
+            if let Some(child) = self.read_indirect_entry(indirect, entry)? {
+                if levels == 1 {
+                    blocks.push(child);
+                } else {
+                    self.collect_indirect_blocks(child, levels - 1, blocks)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Vuelve a armar el mapa de bits de bloques en uso recorriendo los mapas de bloques de todos los archivos. Solo se
+    /// leen los bloques indirectos.
+    fn rebuild_block_map(&mut self) -> Result<(), DiskError> {
+        let mut block_map = Bitmap::new(self.block_count);
+
+        for inode in self.super_block.iter().flatten() {
+            for block in self.get_file_blocks(inode.attributes.ino)? {
+                block_map.set(block);
+            }
+        }
+
+        self.block_map = block_map;
+        Ok(())
+    }
+
+    /// Revisa que el contenido del disco respete la geometría de su encabezado y devuelve una descripción de cada
+    /// problema encontrado. Una lista vacía indica que el disco es consistente.
+    #[allow(dead_code)]
+    pub fn check_consistency(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut block_owners: HashMap<usize, u64> = HashMap::new();
+        let mut referenced_blocks = vec![false; self.block_count];
+        // Sin todos los mapas de bloques no se sabe qué bloques están sin referenciar
+        let mut all_files_read = true;
+
+        if self.block_size != self.header.block_size as usize || self.max_files != self.super_block.len() {
+            problems.push(String::from("La geometría cargada no coincide con el encabezado del superbloque"));
+        }
+
+        for (index, inode) in self.super_block.iter().enumerate() {
+            let inode = match inode {
+                Some(inode) => inode,
+                None => continue
+            };
+            let ino = inode.attributes.ino;
+
+            if ino != (index as u64) + 1 {
+                problems.push(format!("El inodo en la posición {} tiene el número {}", index, ino));
+                continue;
+            }
+
+            // Las entradas de un directorio deben apuntar a inodos existentes del mismo tipo y tener nombres válidos y
+            // distintos
+            match self.directory_entries(ino) {
+                Ok(Some(entries)) => {
+                    let mut names = BTreeSet::new();
+                    for entry in entries {
+                        if check_name(&entry.name).is_err() {
+                            problems.push(format!("La entrada {:?} del directorio {} no tiene un nombre válido", entry.name, ino));
+                        }
+
+                        match entry.ino.checked_sub(1).and_then(|index| self.super_block.get(index as usize)) {
+                            Some(Some(child)) if child.attributes.kind != entry.kind => {
+                                problems.push(format!("La entrada {:?} del directorio {} no tiene el tipo del inodo {}", entry.name, ino, entry.ino));
+                            },
+                            Some(Some(_)) => (),
+                            _ => problems.push(format!("La entrada {:?} del directorio {} apunta al inodo {}, que no existe", entry.name, ino, entry.ino))
+                        }
+
+                        if !names.insert(entry.name.clone()) {
+                            problems.push(format!("El directorio {} tiene más de una entrada llamada {:?}", ino, entry.name));
+                        }
+                    }
+                },
+                Ok(None) => (),
+                Err(e) => problems.push(format!("No se pudieron leer las entradas del directorio {}: {}", ino, e))
+            }
+
+            // El destino de un enlace simbólico no puede pasar de `SYMLINK_MAX` bytes y, si se guarda en el inodo,
+            // debe medir lo mismo que el enlace
+            if inode.attributes.kind == FileType::Symlink {
+                if inode.attributes.size > SYMLINK_MAX as u64 {
+                    problems.push(format!("El enlace simbólico {} tiene un destino de {} bytes, más de los {} permitidos", ino, inode.attributes.size, SYMLINK_MAX));
+                }
+
+                if let Some(target) = &inode.target {
+                    if target.len() as u64 != inode.attributes.size {
+                        problems.push(format!("El enlace simbólico {} mide {} bytes pero su destino tiene {}", ino, inode.attributes.size, target.len()));
+                    }
+                }
+            }
+
+            // Los atributos extendidos deben caber en el inodo y cada valor en bloques debe tener los bloques de su largo
+            if !serialized_size(&inode.xattrs).is_ok_and(|size| size <= XATTR_INODE_CAPACITY) {
+                problems.push(format!("Los atributos extendidos del inodo {} no caben en el inodo", ino));
+            }
+            for (name, value) in inode.xattrs.iter() {
+                if let XattrValue::Blocks { length, blocks } = value {
+                    if blocks.len() != length.div_ceil(self.block_size) {
+                        problems.push(format!("El atributo {:?} del inodo {} mide {} bytes pero tiene {} bloques", OsStr::from_bytes(name), ino, length, blocks.len()));
+                    }
+                }
+            }
+
+            let blocks = match self.get_file_blocks(ino) {
+                Ok(blocks) => blocks,
+                Err(e) => {
+                    problems.push(format!("No se pudo leer el mapa de bloques del archivo {}: {}", ino, e));
+                    all_files_read = false;
+                    continue;
+                }
+            };
+
+            for block in blocks {
+                if block >= self.block_count {
+                    problems.push(format!("El archivo {} referencia al bloque {}, fuera del disco de {} bloques", ino, block, self.block_count));
+                    continue;
+                }
+                referenced_blocks[block] = true;
+
+                match self.get_content_as_bytes(block) {
+                    Ok(Some(_)) => {
+                        if let Some(owner) = block_owners.insert(block, ino) {
+                            problems.push(format!("El bloque {} está referenciado por los archivos {} y {}", block, owner, ino));
+                        }
+                    },
+                    Ok(None) => problems.push(format!("El archivo {} referencia al bloque {}, que está vacío", ino, block)),
+                    Err(e) => problems.push(format!("No se pudo leer el bloque {} del archivo {}: {}", block, ino, e))
+                }
+            }
+        }
+
+        // Los mapas de bits deben coincidir con los inodos existentes y con los bloques referenciados
+        for (index, inode) in self.super_block.iter().enumerate() {
+            match (inode.is_some(), self.inode_map.is_set(index)) {
+                (true, false) => problems.push(format!("El inodo {} existe pero el mapa de inodos lo marca como libre", index + 1)),
+                (false, true) => problems.push(format!("El inodo {} no existe pero el mapa de inodos lo marca como ocupado", index + 1)),
+                _ => ()
+            }
+        }
+
+        if self.inode_map.len() != self.max_files || self.block_map.len() != self.block_count {
+            problems.push(String::from("El tamaño de los mapas de bits no coincide con el encabezado del superbloque"));
+        }
+
+        for (block, referenced) in referenced_blocks.iter().enumerate() {
+            match (*referenced, self.block_map.is_set(block)) {
+                (true, false) => problems.push(format!("El bloque {} está referenciado pero el mapa de bloques lo marca como libre", block)),
+                (false, true) if all_files_read => {
+                    problems.push(format!("El bloque {} está marcado como ocupado pero ningún archivo lo referencia", block));
+                },
+                _ => ()
+            }
+        }
+
+        // La cantidad de enlaces de cada inodo debe coincidir con las entradas de los directorios
+        if let Ok(links) = self.count_links() {
+            for inode in self.super_block.iter().flatten() {
+                let counted = links.get(&inode.attributes.ino).copied().unwrap_or(0);
+                if inode.attributes.nlink != counted {
+                    problems.push(format!("El inodo {} tiene {} enlaces, pero las entradas de los directorios cuentan {}", inode.attributes.ino, inode.attributes.nlink, counted));
+                }
+            }
+        }
+
+        problems
+    }
+
+    /// Guarda en la imagen los inodos y bloques que cambiaron desde la última vez y vacía la bitácora.
+    ///
+    /// `inode.qrfs` tiene el encabezado del superbloque seguido de una casilla de tamaño fijo por inodo y `disco.qrfs`
+    /// su número mágico y versión seguidos de una casilla por bloque, cada una cifrada por separado. Normalmente solo
+    /// se escriben en su lugar las casillas cambiadas, así que el tiempo depende de lo que cambió y no del tamaño del
+    /// disco: los cambios ya están en la bitácora, que se sincroniza antes y se vacía después, así que una
+    /// interrupción a mitad de camino se repara al volver a aplicarla. Un disco nuevo o migrado del formato original se
+    /// escribe completo, reemplazando los dos archivos juntos y de forma atómica.
+    pub fn write_to_disk(&mut self) -> Result<(), DiskError> {
+        if self.read_only {
+            return Err(DiskError::ReadOnly);
+        }
+
+        if self.image.is_some() {
+            self.write_changes()?;
+        } else {
+            self.write_image()?;
+        }
+
+        // La imagen ya contiene todas las operaciones, incluida la que esté en curso. Los bloques escritos pasan a la
+        // caché, que ya puede descartarlos.
+        self.journal.reset()?;
+        self.pending_inodes.clear();
+        self.pending_blocks.clear();
+        self.forget_undo();
+        self.dirty_inodes.clear();
+        self.inode_map.clear_dirty_chunks();
+        self.block_map.clear_dirty_chunks();
+        let cache = self.cache.get_mut();
+        for (index, data) in std::mem::take(&mut self.modified_blocks) {
+            if let Some(data) = data {
+                cache.insert(index, data);
+            }
+        }
+        Ok(())
+    }
+
+    /// Escribe en su lugar las casillas de los inodos y bloques cambiados. Los cambios de la operación en curso se
+    /// registran antes en la bitácora, como los demás, para que ninguna casilla se escriba sin estar en ella.
+    fn write_changes(&mut self) -> Result<(), DiskError> {
+        self.append_pending()?;
+        self.journal.sync()?;
+
+        let image = self.image.as_ref().expect("fn write_changes: imagen sin abrir");
+        for index in self.dirty_inodes.iter() {
+            image.write_inode(*index, self.seal_inode(*index)?.as_deref())?;
+        }
+        for (index, data) in self.modified_blocks.iter() {
+            image.write_block(*index, self.seal_block(*index, data).as_deref())?;
+        }
+        self.write_bitmaps(image, false)?;
+        image.sync()?;
+
+        Ok(())
+    }
+
+    /// Escribe la imagen completa en archivos nuevos, que reemplazan a los anteriores con `DiskDirectory::commit`.
+    fn write_image(&mut self) -> Result<(), DiskError> {
+        let header = serialize(&self.header)?;
+        let data_header = serialize(&(DATA_MAGIC, FORMAT_VERSION))?;
+        let layout = self.layout;
+
+        self.directory.commit(|inodes, data| -> Result<(), DiskError> {
+            let image = ImageFiles::create(inodes.try_clone()?, data.try_clone()?, layout)?;
+            image.write_header(&header)?;
+            image.write_data_header(&data_header)?;
+
+            for index in 0..self.super_block.len() {
+                if let Some(sealed) = self.seal_inode(index)? {
+                    image.write_inode(index, Some(&sealed))?;
+                }
+            }
+            // Sin una imagen del formato actual no hay bloques que leer: todos están entre los cambiados
+            for (index, data) in self.modified_blocks.iter() {
+                if let Some(sealed) = self.seal_block(*index, data) {
+                    image.write_block(*index, Some(&sealed))?;
+                }
+            }
+
+            self.write_bitmaps(&image, true)
+        })?;
+
+        self.image = Some(ImageFiles::open(&self.directory, layout)?);
+        Ok(())
+    }
+
+    /// Escribe en `image` las partes de los mapas de bits que cambiaron desde la última vez, o todas con `all`.
+    fn write_bitmaps(&self, image: &ImageFiles, all: bool) -> Result<(), DiskError> {
+        for (kind, map) in [(BitmapKind::Inodes, &self.inode_map), (BitmapKind::Blocks, &self.block_map)].iter() {
+            let chunks: Vec<usize> = match all {
+                true => (0..map.chunk_count()).collect(),
+                false => map.dirty_chunks().iter().copied().collect()
+            };
+
+            for chunk in chunks {
+                image.write_bitmap(*kind, chunk, &self.key.encrypt(&map.chunk(chunk), &bitmap_context(*kind, chunk)))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Contenido cifrado de la casilla del inodo en la posición `index`, o `None` si está vacía.
+    fn seal_inode(&self, index: usize) -> Result<Option<Vec<u8>>, DiskError> {
+        match &self.super_block[index] {
+            Some(inode) => Ok(Some(self.key.encrypt(&serialize(inode)?, &inode_context(index)))),
+            None => Ok(None)
+        }
+    }
+
+    /// Contenido cifrado de la casilla del bloque `index` con el contenido `data`, o `None` si queda vacía.
+    fn seal_block(&self, index: usize, data: &Option<Rc<[u8]>>) -> Option<Vec<u8>> {
+        data.as_ref().map(|data| self.key.encrypt(data, &block_context(index)))
+    }
+
+    /// Registra en la bitácora, como una sola transacción, los inodos y bloques que cambió la operación en curso. Desde
+    /// ese momento quedan pendientes de escribirse en la imagen.
+    fn append_pending(&mut self) -> Result<(), DiskError> {
+        if self.pending_inodes.is_empty() && self.pending_blocks.is_empty() {
+            return Ok(());
+        }
+        if self.read_only {
+            return Err(DiskError::ReadOnly);
+        }
+
+        let mut records = Vec::with_capacity(self.pending_inodes.len() + self.pending_blocks.len());
+        for index in self.pending_inodes.iter() {
+            records.push(JournalRecord::Inode { index: *index, inode: self.super_block[*index].clone().map(Box::new) });
+        }
+        for index in self.pending_blocks.iter() {
+            let data = self.modified_blocks.get(index).cloned().flatten();
+            records.push(JournalRecord::Block { index: *index, data: data.map(|data| Box::from(&*data)) });
+        }
+
+        // Los bloques siguen entre los cambiados hasta que se escriben en la imagen
+        self.journal.append(&self.key, &records)?;
+        self.dirty_inodes.append(&mut self.pending_inodes);
+        self.pending_blocks.clear();
+        self.forget_undo();
+        Ok(())
+    }
+
+    /// Guarda el inodo en la posición `index` antes de que la operación en curso lo cambie por primera vez.
+    fn save_inode_for_undo(&mut self, index: usize) {
+        if !self.undo_inodes.contains_key(&index) {
+            self.undo_inodes.insert(index, self.super_block[index].clone());
+        }
+    }
+
+    /// Descarta el estado guardado para deshacer la operación en curso, una vez que sus cambios quedaron registrados.
+    fn forget_undo(&mut self) {
+        self.undo_inodes.clear();
+        self.undo_blocks.clear();
+        self.undo_directories.clear();
+    }
+
+    /// Deshace los cambios de la operación en curso, que falló a mitad de camino: los inodos, los bloques y los mapas
+    /// de bits vuelven a como estaban después de la última operación registrada, así que los cambios a medias no
+    /// llegan a la bitácora con la operación siguiente.
+    pub fn abort_transaction(&mut self) {
+        for (index, inode) in std::mem::take(&mut self.undo_inodes) {
+            if self.inode_map.is_set(index) != inode.is_some() {
+                match inode.is_some() {
+                    true => self.inode_map.set(index),
+                    false => self.inode_map.clear(index)
+                }
+            }
+            self.super_block[index] = inode;
+        }
+
+        for (index, saved) in std::mem::take(&mut self.undo_blocks) {
+            if self.block_map.is_set(index) != saved.in_use {
+                match saved.in_use {
+                    true => self.block_map.set(index),
+                    false => self.block_map.clear(index)
+                }
+            }
+            self.cache.get_mut().remove(index);
+            match saved.modified {
+                Some(data) => self.modified_blocks.insert(index, data),
+                None => self.modified_blocks.remove(&index)
+            };
+        }
+
+        // Los índices de los directorios que cambió la operación se vuelven a armar a partir de su contenido restaurado
+        let directory_index = self.directory_index.get_mut();
+        for ino in std::mem::take(&mut self.undo_directories) {
+            directory_index.remove(ino);
+        }
+        self.pending_inodes.clear();
+        self.pending_blocks.clear();
+    }
+
+    /// Registra en la bitácora, como una sola transacción, los inodos y bloques que cambió la operación que termina.
+    /// Cuando la bitácora crece más allá de `JOURNAL_CHECKPOINT_SIZE` sus cambios se escriben en la imagen y se vacía.
+    pub fn commit_transaction(&mut self) -> Result<(), DiskError> {
+        if self.pending_inodes.is_empty() && self.pending_blocks.is_empty() {
+            return Ok(());
+        }
+
+        self.append_pending()?;
+
+        if self.journal.size() > JOURNAL_CHECKPOINT_SIZE {
+            self.write_to_disk()?;
+        } else if self.sync_after_operations.is_some_and(|limit| self.journal.unsynced() >= limit) {
+            self.journal.sync()?;
+        }
+
+        Ok(())
+    }
+
+    /// Hace durables todas las operaciones terminadas: registra los cambios pendientes y sincroniza la bitácora con
+    /// el dispositivo. Es lo que necesita `fsync` para cualquier inodo, porque sus cambios están en la bitácora.
+    #[allow(dead_code)]
+    pub fn sync(&mut self) -> Result<(), DiskError> {
+        self.commit_transaction()?;
+        self.journal.sync()?;
+        Ok(())
+    }
+
+    /// Configura cada cuánto se sincroniza la bitácora sin que nadie lo pida: cada `interval`, desde un hilo aparte,
+    /// y cada vez que se acumulan `operations` operaciones sin sincronizar. Con `None` no se usa ese criterio.
+    #[allow(dead_code)]
+    pub fn set_sync_policy(&mut self, interval: Option<Duration>, operations: Option<u64>) -> Result<(), DiskError> {
+        if let Some(interval) = interval {
+            self.journal.start_periodic_sync(interval)?;
+        }
+        self.sync_after_operations = operations;
+        Ok(())
+    }
+
+    /// Limita a `size` bytes la memoria que usa la caché de bloques leídos de la imagen.
+    #[allow(dead_code)]
+    pub fn set_cache_size(&mut self, size: usize) {
+        self.cache.get_mut().set_capacity(size / self.block_size);
+    }
+
+    /// Vuelve a aplicar un cambio leído de la bitácora.
+    fn apply_journal_record(&mut self, record: JournalRecord) -> Result<(), DiskError> {
+        match record {
+            JournalRecord::Inode { index, inode } => match self.super_block.get_mut(index) {
+                Some(slot) => {
+                    if inode.is_some() {
+                        self.inode_map.set(index);
+                    } else {
+                        self.inode_map.clear(index);
+                    }
+                    *slot = inode.map(|inode| *inode);
+                    self.dirty_inodes.insert(index);
+                },
+                None => return Err(DiskError::Corrupted(format!("la bitácora: el inodo {} está fuera de la tabla", index + 1)))
+            },
+            JournalRecord::Block { index, data } => match index < self.block_count {
+                true => {
+                    if data.is_some() {
+                        self.block_map.set(index);
+                    } else {
+                        self.block_map.clear(index);
+                    }
+                    self.cache.get_mut().remove(index);
+                    self.modified_blocks.insert(index, data.map(Rc::from));
+                },
+                false => return Err(DiskError::Corrupted(format!("la bitácora: el bloque {} está fuera del disco", index)))
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Contenido de `inode.qrfs` y `disco.qrfs` sin las casillas vacías ni las de los inodos y bloques libres, para
+    /// imprimirlo: cada archivo se serializa como la lista de sus tramos escritos con su posición, como los devuelve
+    /// `ImageFiles::extents`.
+    #[allow(dead_code)]
+    pub fn image_extents(&self) -> Result<(Vec<u8>, Vec<u8>), DiskError> {
+        let image = self.image.as_ref()
+            .ok_or_else(|| DiskError::Io(io::Error::new(io::ErrorKind::NotFound, "la imagen todavía no se guardó")))?;
+        let (inodes, data) = image.extents(&self.inode_map, &self.block_map)?;
+        Ok((serialize(&inodes)?, serialize(&data)?))
+    }
+
+    /// Ranuras de llave del disco, en sus posiciones fijas.
+    #[allow(dead_code)]
+    pub fn key_slots(&self) -> &[Option<KeySlot>] {
+        &self.header.encryption.slots
+    }
+
+    /// Directorio donde se guardan los archivos del disco.
+    #[allow(dead_code)]
+    pub fn root_path(&self) -> &str {
+        &self.root_path
+    }
+
+    /// Indica si el disco se abrió con `open_read_only`.
+    #[allow(dead_code)]
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Número de la ranura de llave con la que se abrió el disco.
+    #[allow(dead_code)]
+    pub fn unlocked_slot(&self) -> usize {
+        self.unlocked_slot
+    }
+
+    /// Reparte la llave maestra en `total` partes con el esquema de Shamir, de las que bastan `threshold` para
+    /// reconstruirla sin ninguna frase.
+    #[allow(dead_code)]
+    pub fn split_key(&self, threshold: u8, total: u8) -> Vec<KeyShare> {
+        self.key.split(threshold, total)
+    }
+
+    /// Devuelve el acceso al disco de `root_path` a partir de la llave maestra `key` reconstruida con sus partes, sin
+    /// ninguna frase. Si la llave descifra el inodo raíz, la credencial `credential` se guarda en la ranura `replace`
+    /// o, si no se indica, en la primera libre; si no queda ninguna libre no se cambia nada y se devuelve un error. Como
+    /// al cambiar las ranuras, solo se reescribe el encabezado de `inode.qrfs` de forma atómica. Devuelve el número de
+    /// la ranura.
+    #[allow(dead_code)]
+    pub fn recover_access(root_path: &str, key: &Key, credential: &Credential, replace: Option<usize>) -> Result<usize, DiskError> {
+        let directory = DiskDirectory::open(root_path)?;
+        directory.recover_commit()?;
+
+        let ser_inodes = directory.read(INODE_FILE)?;
+
+        if !ser_inodes.starts_with(&MAGIC) {
+            return Err(DiskError::InvalidMagic);
+        }
+
+        let mut header = SuperBlockHeader::read_unlocked(&mut Cursor::new(&ser_inodes))?;
+        let root = ImageFiles::open(&directory, Disk::layout_of(&header))?.read_inode(0)?;
+        if root.is_none_or(|sealed| key.decrypt(&sealed, &inode_context(0)).is_none()) {
+            return Err(DiskError::KeySlot(String::from("las partes no reconstruyen la llave maestra de este disco")));
+        }
+
+        let encryption = &mut header.encryption;
+        let slot = match replace {
+            Some(slot) if slot < encryption.slots.len() => {
+                encryption.replace_slot(slot, credential, key);
+                slot
+            },
+            Some(slot) => return Err(DiskError::KeySlot(format!("la ranura {} no existe: el disco tiene {} ranuras", slot, encryption.slots.len()))),
+            None => encryption.add_slot(credential, key)
+                .ok_or_else(|| DiskError::KeySlot(String::from("no queda ninguna ranura de llave libre: hay que indicar cuál reemplazar")))?
+        };
+
+        Disk::replace_header(&directory, &header)?;
+        Ok(slot)
+    }
+
+    /// Reemplaza la credencial de la ranura con la que se abrió el disco por `credential`.
+    #[allow(dead_code)]
+    pub fn change_key(&mut self, credential: &Credential) -> Result<(), DiskError> {
+        let slot = self.unlocked_slot;
+        self.update_key_slots(|encryption, key| {
+            encryption.replace_slot(slot, credential, key);
+            Ok(())
+        })
+    }
+
+    /// Agrega una ranura de llave que se abre con `credential`. Devuelve el número de la ranura.
+    #[allow(dead_code)]
+    pub fn add_key(&mut self, credential: &Credential) -> Result<usize, DiskError> {
+        self.update_key_slots(|encryption, key| {
+            encryption.add_slot(credential, key)
+                .ok_or_else(|| DiskError::KeySlot(String::from("no quedan ranuras de llave libres")))
+        })
+    }
+
+    /// Revoca la ranura de llave `slot`. La única ranura en uso no se puede revocar, porque el disco quedaría sin
+    /// ninguna forma de abrirse.
+    #[allow(dead_code)]
+    pub fn revoke_key(&mut self, slot: usize) -> Result<(), DiskError> {
+        self.update_key_slots(|encryption, _| {
+            match encryption.slots.get(slot) {
+                Some(Some(_)) => (),
+                _ => return Err(DiskError::KeySlot(format!("la ranura de llave {} no está en uso", slot)))
+            }
+
+            if encryption.active_slots() == 1 {
+                return Err(DiskError::KeySlot(String::from("no se puede revocar la única ranura de llave del disco")));
+            }
+
+            encryption.slots[slot] = None;
+            Ok(())
+        })
+    }
+
+    /// Aplica `update` a las ranuras de llave y reescribe el encabezado de `inode.qrfs`. La llave maestra no cambia, así
+    /// que las casillas de los inodos y los bloques quedan como estaban. Si algo falla, las ranuras en memoria vuelven a
+    /// su estado anterior.
+    fn update_key_slots<T, F>(&mut self, update: F) -> Result<T, DiskError>
+    where F: FnOnce(&mut EncryptionHeader, &Key) -> Result<T, DiskError> {
+        if self.read_only {
+            return Err(DiskError::ReadOnly);
+        }
+
+        let previous = self.header.encryption.clone();
+        let result = update(&mut self.header.encryption, &self.key).and_then(|value| {
+            Disk::replace_header(&self.directory, &self.header).map(|_| value)
+        });
+
+        if result.is_err() {
+            self.header.encryption = previous;
+        }
+
+        // El archivo abierto quedó reemplazado por el nuevo
+        self.image = Some(ImageFiles::open(&self.directory, self.layout)?);
+        result
+    }
+
+    /// Reescribe el encabezado al inicio de `inode.qrfs` sin tocar las casillas de los inodos. El archivo nuevo se
+    /// escribe aparte y reemplaza al anterior con un `rename`, de modo que una interrupción deja el encabezado anterior
+    /// o el nuevo.
+    fn replace_header(directory: &DiskDirectory, header: &SuperBlockHeader) -> Result<(), DiskError> {
+        let header = serialize(header)?;
+        let region = HEADER_REGION_SIZE as usize;
+        if header.len() > region {
+            return Err(DiskError::KeySlot(String::from("el encabezado no cabe en su espacio reservado")));
+        }
+
+        let mut content = directory.read(INODE_FILE)?;
+        if content.len() < region {
+            content.resize(region, 0);
+        }
+        content[..region].iter_mut().for_each(|byte| *byte = 0);
+        content[..header.len()].copy_from_slice(&header);
+
+        Ok(directory.replace(INODE_FILE, &content)?)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cifrado::MAX_KEY_SLOTS;
+    use crate::directorio::{COMMIT_FILE, INODE_TEMP_FILE};
+    use crate::pruebas::{add_file, add_node, reload_disk, remove_disk, test_attributes, test_credential, test_directory, test_disk};
+
+    #[test]
+    fn entries_outside_the_inode_table_are_reported() {
+        let mut disk = test_disk("persistencia-entradas-fuera-de-la-tabla");
+        for (ino, name) in [(17, "fuera"), (u64::MAX, "maximo")] {
+            disk.add_directory_entry(1, DirectoryEntry { ino, kind: FileType::Symlink, name: OsString::from(name) }).unwrap();
+        }
+
+        assert!(disk.get_inode(0).is_none());
+        assert!(disk.get_inode(u64::MAX).is_none());
+        assert!(disk.get_inode_as_mut(17).is_none());
+
+        let problems = disk.check_consistency();
+        assert!(problems.iter().any(|problem| problem.contains(&format!("apunta al inodo {},", u64::MAX))));
+        assert!(problems.iter().any(|problem| problem.contains("apunta al inodo 17,")));
+        assert_eq!(disk.dangling_symlinks().len(), 2);
+
+        remove_disk(disk);
+    }
+    #[test]
+    fn directory_contains_reports_missing_directories() {
+        let mut disk = test_disk("persistencia-directorio-inexistente");
+        disk.add_directory_entry(1, DirectoryEntry { ino: 9, kind: FileType::Directory, name: OsString::from("perdido") }).unwrap();
+
+        assert!(matches!(disk.directory_contains(1, 5), Err(DiskError::Inconsistent(_))));
+        remove_disk(disk);
+    }
+    #[test]
+    fn directories_count_only_the_blocks_of_their_entries() {
+        let mut disk = test_disk("persistencia-bloques-de-directorio");
+        let directory = add_node(&mut disk, 1, "directorio", FileType::Directory);
+        assert_eq!(disk.get_inode(directory).unwrap().attributes.blocks, 0);
+
+        add_node(&mut disk, directory, "archivo", FileType::RegularFile);
+        assert_eq!(disk.get_inode(directory).unwrap().attributes.blocks, 1);
+        remove_disk(disk);
+    }
+    #[test]
+    fn files_are_freed_after_their_last_link() {
+        let mut disk = test_disk("persistencia-enlaces");
+        let free_blocks = disk.usage().free_blocks;
+        let ino = add_file(&mut disk, "archivo");
+        disk.write_file(ino, 0, &[1; 1024]).unwrap();
+
+        // Un enlace duro es otra entrada con el mismo inodo
+        disk.add_directory_entry(1, DirectoryEntry { ino, kind: FileType::RegularFile, name: OsString::from("enlace") }).unwrap();
+        disk.increase_links(ino);
+        assert_eq!(disk.get_inode(ino).unwrap().attributes.nlink, 2);
+        disk.commit_transaction().unwrap();
+
+        disk.remove_directory_entry(1, OsStr::new("archivo")).unwrap();
+        disk.decrease_links(ino).unwrap();
+        assert_eq!(disk.get_inode(ino).unwrap().attributes.nlink, 1);
+        assert_eq!(disk.read_file(ino, 0, 4).unwrap().unwrap(), vec![1; 4]);
+
+        disk.remove_directory_entry(1, OsStr::new("enlace")).unwrap();
+        disk.decrease_links(ino).unwrap();
+        assert!(disk.get_inode(ino).is_none());
+        assert_eq!(disk.usage().free_blocks, free_blocks);
+        assert_eq!(disk.usage().free_inodes, 15);
+        disk.commit_transaction().unwrap();
+
+        let disk = reload_disk(disk);
+        assert!(disk.get_inode(ino).is_none());
+        assert!(disk.check_consistency().is_empty());
+        remove_disk(disk);
+    }
+    #[test]
+    fn unlinked_files_stay_alive_while_open() {
+        let mut disk = test_disk("persistencia-abierto-sin-enlaces");
+        let free_blocks = disk.usage().free_blocks;
+        let ino = add_file(&mut disk, "archivo");
+        disk.write_file(ino, 0, b"contenido").unwrap();
+        disk.open_handle(ino);
+        disk.open_handle(ino);
+
+        disk.remove_directory_entry(1, OsStr::new("archivo")).unwrap();
+        disk.decrease_links(ino).unwrap();
+        assert_eq!(disk.get_inode(ino).unwrap().attributes.nlink, 0);
+        assert_eq!(disk.read_file(ino, 0, 64).unwrap().unwrap(), b"contenido");
+
+        // Se libera al cerrar el último handle
+        disk.release_handle(ino).unwrap();
+        assert!(disk.get_inode(ino).is_some());
+        disk.release_handle(ino).unwrap();
+        assert!(disk.get_inode(ino).is_none());
+        assert_eq!(disk.usage().free_blocks, free_blocks);
+        remove_disk(disk);
+    }
+    #[test]
+    fn unlinked_files_left_open_are_freed_at_the_next_load() {
+        let mut disk = test_disk("persistencia-sin-enlaces-al-cargar");
+        let ino = add_file(&mut disk, "archivo");
+        disk.write_file(ino, 0, b"contenido").unwrap();
+        disk.open_handle(ino);
+        disk.remove_directory_entry(1, OsStr::new("archivo")).unwrap();
+        disk.decrease_links(ino).unwrap();
+        disk.commit_transaction().unwrap();
+
+        // El sistema se detuvo sin cerrar el handle
+        let disk = reload_disk(disk);
+        assert!(disk.get_inode(ino).is_none());
+        assert_eq!(disk.usage().free_blocks, 64);
+        assert!(disk.check_consistency().is_empty());
+        remove_disk(disk);
+    }
+    #[test]
+    fn symlink_targets_move_to_blocks_past_the_inline_capacity() {
+        let mut disk = test_disk("persistencia-destinos");
+        let inline = add_node(&mut disk, 1, "corto", FileType::Symlink);
+        let stored = add_node(&mut disk, 1, "largo", FileType::Symlink);
+        let short_target = "a".repeat(INLINE_TARGET_CAPACITY);
+        let long_target = "b/".repeat(SYMLINK_MAX / 2) + "c";
+        disk.write_symlink_target(inline, OsStr::new(&short_target)).unwrap();
+        disk.write_symlink_target(stored, OsStr::new(&long_target)).unwrap();
+
+        assert!(matches!(disk.write_symlink_target(stored, OsStr::new(&"d".repeat(SYMLINK_MAX + 1))), Err(DiskError::TargetTooLong(4096))));
+        assert_eq!(disk.get_inode(inline).unwrap().attributes.blocks, 0);
+        assert!(disk.get_inode(stored).unwrap().target.is_none());
+        assert_eq!(disk.get_inode(stored).unwrap().attributes.blocks, 8);
+        disk.commit_transaction().unwrap();
+
+        let disk = reload_disk(disk);
+        assert_eq!(disk.read_symlink_target(inline).unwrap().unwrap(), OsString::from(short_target));
+        assert_eq!(disk.read_symlink_target(stored).unwrap().unwrap(), OsString::from(long_target));
+        assert_eq!(disk.get_inode(stored).unwrap().attributes.size, SYMLINK_MAX as u64);
+        assert!(disk.read_symlink_target(1).unwrap().is_none());
+        remove_disk(disk);
+    }
+    #[test]
+    fn dangling_symlinks_are_missing_targets_and_cycles() {
+        let mut disk = test_disk("persistencia-enlaces-rotos");
+        let directory = add_node(&mut disk, 1, "directorio", FileType::Directory);
+        add_node(&mut disk, directory, "archivo", FileType::RegularFile);
+        for (parent, name, target) in [(1, "bueno", "directorio/archivo"), (1, "absoluto", "/etc/passwd"), (directory, "arriba", "../directorio/./archivo"),
+                                       (1, "roto", "directorio/nada"), (1, "ciclo", "ciclo"), (directory, "por-archivo", "archivo/nada")] {
+            let ino = add_node(&mut disk, parent, name, FileType::Symlink);
+            disk.write_symlink_target(ino, OsStr::new(target)).unwrap();
+        }
+
+        let mut dangling = disk.dangling_symlinks();
+        dangling.sort();
+        assert_eq!(dangling.len(), 3);
+        assert!(dangling[0].contains("\"ciclo\" del directorio 1"));
+        assert!(dangling[1].contains(&format!("\"por-archivo\" del directorio {}", directory)));
+        assert!(dangling[2].contains("\"roto\" del directorio 1 apunta a \"directorio/nada\""));
+        remove_disk(disk);
+    }
+    #[test]
+    fn xattr_values_move_to_blocks_past_the_inline_capacity() {
+        let mut disk = test_disk("persistencia-atributos");
+        let ino = add_file(&mut disk, "archivo");
+        let free_blocks = disk.usage().free_blocks;
+        let name = OsStr::new("user.valor");
+        let long_value = vec![2; INLINE_XATTR_CAPACITY + 600];
+
+        disk.set_xattr(ino, name, &vec![1; INLINE_XATTR_CAPACITY]).unwrap();
+        assert_eq!(disk.usage().free_blocks, free_blocks);
+        disk.set_xattr(ino, name, &long_value).unwrap();
+        assert_eq!(disk.usage().free_blocks, free_blocks - 2);
+        disk.commit_transaction().unwrap();
+
+        let mut disk = reload_disk(disk);
+        assert_eq!(disk.get_xattr(ino, name).unwrap().unwrap(), long_value);
+        assert_eq!(disk.xattr_names(ino), vec![name.to_os_string()]);
+
+        // Un valor corto libera los bloques del largo, y un atributo quitado ya no tiene valor
+        disk.set_xattr(ino, name, b"corto").unwrap();
+        assert_eq!(disk.usage().free_blocks, free_blocks);
+        assert!(disk.remove_xattr(ino, name));
+        assert!(!disk.remove_xattr(ino, name));
+        assert!(disk.get_xattr(ino, name).unwrap().is_none());
+
+        // Un inodo que no existe no recibe atributos
+        assert!(matches!(disk.set_xattr(9, name, b"valor"), Err(DiskError::InodeNotFound(9))));
+        remove_disk(disk);
+    }
+    #[test]
+    fn xattrs_stop_at_the_inode_capacity() {
+        let mut disk = test_disk("persistencia-atributos-llenos");
+        let ino = add_file(&mut disk, "archivo");
+        let free_blocks = disk.usage().free_blocks;
+
+        let mut stored = 0;
+        while disk.set_xattr(ino, OsStr::new(&format!("user.{}", stored)), &[0; 200]).is_ok() {
+            stored += 1;
+        }
+        assert!(stored > 0 && stored < XATTR_INODE_CAPACITY as usize / 200);
+        assert!(serialized_size(&disk.get_inode(ino).unwrap().xattrs).unwrap() <= XATTR_INODE_CAPACITY);
+        assert!(disk.get_xattr(ino, OsStr::new(&format!("user.{}", stored))).unwrap().is_none());
+
+        // Un valor en bloques que no entra tampoco deja bloques ocupados
+        assert!(matches!(disk.set_xattr(ino, OsStr::new("user.largo"), &[0; 1000]), Err(DiskError::NoSpace)));
+        assert_eq!(disk.usage().free_blocks, free_blocks);
+        remove_disk(disk);
+    }
+    #[test]
+    fn inodes_outside_the_inode_table_are_not_written() {
+        let mut disk = test_disk("persistencia-inodo-fuera-de-la-tabla");
+        for ino in [0, 17, u64::MAX] {
+            let inode = Inode { attributes: test_attributes(ino, FileType::RegularFile, 0o644), references: [None; 128], target: None, xattrs: BTreeMap::new() };
+            assert!(matches!(disk.write_inode(inode), Err(DiskError::InodeOutOfRange(number)) if number == ino));
+        }
+
+        // Nada queda pendiente de registrar en la bitácora
+        assert_eq!(disk.usage().free_inodes, 15);
+        assert!(disk.pending_inodes.is_empty());
+        remove_disk(disk);
+    }
+
+    #[test]
+    fn abort_transaction_restores_the_last_committed_state() {
+        let mut disk = test_disk("persistencia-deshacer");
+        let ino = add_file(&mut disk, "archivo");
+        disk.commit_transaction().unwrap();
+        disk.write_file(ino, 0, b"contenido anterior").unwrap();
+        disk.commit_transaction().unwrap();
+        let usage = disk.usage();
+        let journal_size = disk.journal.size();
+
+        // Una operación que cambia el archivo, su entrada, otro inodo y varios bloques y luego falla
+        disk.write_file(ino, 0, &vec![7; 3 * 512]).unwrap();
+        disk.remove_directory_entry(1, OsStr::new("archivo")).unwrap();
+        add_file(&mut disk, "nuevo");
+        disk.abort_transaction();
+
+        assert_eq!(disk.read_file(ino, 0, 64).unwrap().unwrap(), b"contenido anterior");
+        assert_eq!(disk.find_inode_in_references_by_name(1, OsStr::new("archivo")).unwrap().map(|inode| inode.attributes.ino), Some(ino));
+        assert!(disk.find_inode_in_references_by_name(1, OsStr::new("nuevo")).unwrap().is_none());
+        assert_eq!(disk.usage().free_blocks, usage.free_blocks);
+        assert_eq!(disk.usage().free_inodes, usage.free_inodes);
+        assert!(disk.check_consistency().is_empty());
+
+        // No queda nada de la operación deshecha para registrar con la siguiente
+        disk.commit_transaction().unwrap();
+        assert_eq!(disk.journal.size(), journal_size);
+        remove_disk(disk);
+    }
+    #[test]
+    fn abort_transaction_drops_only_the_indexes_it_changed() {
+        let mut disk = test_disk("persistencia-deshacer-indices");
+        let untouched = add_node(&mut disk, 1, "quieto", FileType::Directory);
+        let changed = add_node(&mut disk, 1, "cambiado", FileType::Directory);
+        add_node(&mut disk, untouched, "archivo", FileType::RegularFile);
+        disk.commit_transaction().unwrap();
+
+        add_node(&mut disk, changed, "nuevo", FileType::RegularFile);
+        disk.abort_transaction();
+
+        assert!(disk.directory_index.borrow_mut().get(untouched).is_some());
+        assert!(disk.directory_index.borrow_mut().get(changed).is_none());
+        assert!(disk.find_inode_in_references_by_name(changed, OsStr::new("nuevo")).unwrap().is_none());
+        assert!(disk.find_inode_in_references_by_name(untouched, OsStr::new("archivo")).unwrap().is_some());
+        remove_disk(disk);
+    }
+    #[test]
+    fn legacy_test_disk_is_migrated() {
+        // El disco de `Test/`, guardado con el formato original, se carga desde una copia
+        let path = test_directory("persistencia-disco-original");
+        let fixture = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../Test");
+        for name in [INODE_FILE, DATA_FILE].iter() {
+            std::fs::copy(fixture.join(name), path.join(name)).unwrap();
+        }
+        let disk = Disk::new(path.to_string_lossy().into_owned(), test_credential()).unwrap();
+        assert!(disk.check_consistency().is_empty());
+
+        // Las entradas de los directorios pasaron a sus bloques de datos y el contenido a los mapas de bloques, y el
+        // disco migrado se vuelve a cargar con el formato actual
+        let disk = reload_disk(disk);
+        let names = |ino| -> Vec<OsString> {
+            disk.directory_entries(ino).unwrap().unwrap().into_iter().map(|entry| entry.name).collect()
+        };
+        let lookup = |parent, name| disk.find_inode_in_references_by_name(parent, OsStr::new(name)).unwrap().unwrap().attributes;
+        assert_eq!(names(1), vec![OsString::from("HOla"), OsString::from("testeo9.txt")]);
+        let directory = lookup(1, "HOla");
+        assert_eq!(directory.kind, FileType::Directory);
+        assert_eq!(names(directory.ino), vec![OsString::from("testeo1.txt")]);
+        for (parent, name, content) in [(1, "testeo9.txt", "pepeasda\n"), (directory.ino, "testeo1.txt", "hello\n")].iter() {
+            let file = lookup(*parent, name);
+            assert_eq!(file.size, content.len() as u64);
+            assert_eq!(disk.read_file(file.ino, 0, 100).unwrap().unwrap(), content.as_bytes());
+        }
+        assert!(disk.check_consistency().is_empty());
+        remove_disk(disk);
+    }
+    #[test]
+    fn evicted_directory_indexes_are_rebuilt_from_their_entries() {
+        let mut disk = test_disk("persistencia-indices-descartados");
+        let first = add_node(&mut disk, 1, "primero", FileType::Directory);
+        let second = add_node(&mut disk, 1, "segundo", FileType::Directory);
+        add_node(&mut disk, first, "archivo", FileType::RegularFile);
+        disk.directory_index.get_mut().set_capacity(1);
+
+        // Usar el segundo directorio descarta el índice del primero
+        assert!(disk.find_inode_in_references_by_name(second, OsStr::new("archivo")).unwrap().is_none());
+        assert!(disk.directory_index.borrow_mut().get(first).is_none());
+        assert!(disk.find_inode_in_references_by_name(first, OsStr::new("archivo")).unwrap().is_some());
+        remove_disk(disk);
+    }
+    #[test]
+    fn writes_stop_at_the_maximum_file_size() {
+        let mut disk = test_disk("persistencia-tamano-maximo");
+        let ino = add_file(&mut disk, "archivo");
+        let max_file_size = disk.max_file_size();
+
+        assert!(matches!(disk.write_file(ino, max_file_size, b"x"), Err(DiskError::FileTooLarge(_))));
+        assert_eq!(disk.write_file(ino, u64::MAX, b"").unwrap(), 0);
+
+        // Una escritura que cruza el tamaño máximo se corta en él
+        assert_eq!(disk.write_file(ino, max_file_size - 10, &[5; 100]).unwrap(), 10);
+        assert_eq!(disk.get_inode(ino).unwrap().attributes.size, max_file_size);
+        assert_eq!(disk.read_file(ino, max_file_size - 10, 100).unwrap().unwrap(), vec![5; 10]);
+        remove_disk(disk);
+    }
+    #[test]
+    fn maximum_file_size_saturates_at_the_largest_block_size() {
+        // 126 referencias directas, 64 en el bloque indirecto y 64 * 64 en el doble indirecto
+        assert_eq!(Disk::max_file_size_for(512), (126 + 64 + 64 * 64) * 512);
+
+        // Con bloques de 8 MiB todavía cabe en un u64; con 16 MiB, el máximo que acepta mkfs, ya no
+        let per_block = 1 << 20;
+        assert_eq!(Disk::max_file_size_for(8 * 1024 * 1024), (126 + per_block + per_block * per_block) * 8 * 1024 * 1024);
+        assert_eq!(Disk::max_file_size_for(16 * 1024 * 1024), u64::MAX);
+    }
+    #[test]
+    fn writes_that_store_data_update_the_file_times() {
+        let mut disk = test_disk("persistencia-tiempos-de-escritura");
+        let ino = add_file(&mut disk, "archivo");
+        let old = Timespec::new(1, 0);
+        let attributes = &mut disk.get_inode_as_mut(ino).unwrap().attributes;
+        attributes.mtime = old;
+        attributes.ctime = old;
+
+        assert_eq!(disk.write_file(ino, 0, b"").unwrap(), 0);
+        assert_eq!(disk.get_inode(ino).unwrap().attributes.mtime, old);
+
+        disk.write_file(ino, 0, b"contenido").unwrap();
+        let attributes = disk.get_inode(ino).unwrap().attributes;
+        assert!(attributes.mtime > old);
+        assert_eq!(attributes.ctime, attributes.mtime);
+        remove_disk(disk);
+    }
+    #[test]
+    fn block_positions_cross_the_indirect_boundaries() {
+        let mut disk = test_disk("persistencia-bloques-indirectos");
+        let ino = add_file(&mut disk, "archivo");
+        let per_block = disk.references_per_block();
+
+        // El último bloque directo, el primero y el último del indirecto simple, el primero y el último del doble
+        let last_single = DIRECT_REFERENCES + per_block - 1;
+        let last_double = last_single + per_block * per_block;
+        assert!(matches!(disk.block_path(DIRECT_REFERENCES - 1), Some(BlockPath::Direct(125))));
+        assert!(matches!(disk.block_path(DIRECT_REFERENCES), Some(BlockPath::SingleIndirect(0))));
+        assert!(matches!(disk.block_path(last_single), Some(BlockPath::SingleIndirect(entry)) if entry == per_block - 1));
+        assert!(matches!(disk.block_path(last_single + 1), Some(BlockPath::DoubleIndirect(0, 0))));
+        assert!(matches!(disk.block_path(last_double), Some(BlockPath::DoubleIndirect(first, second)) if first == per_block - 1 && second == per_block - 1));
+        assert!(disk.block_path(last_double + 1).is_none());
+
+        let free_blocks = disk.usage().free_blocks;
+        for (value, position) in [DIRECT_REFERENCES - 1, DIRECT_REFERENCES, last_single, last_single + 1].iter().enumerate() {
+            assert_eq!(disk.write_file(ino, (*position * 512) as u64, &[value as u8 + 1]).unwrap(), 1);
+        }
+
+        // Cuatro bloques de datos, el indirecto simple y los dos niveles del doble
+        let inode = disk.get_inode(ino).unwrap();
+        let (single, double) = (inode.references[SINGLE_INDIRECT_REFERENCE].unwrap(), inode.references[DOUBLE_INDIRECT_REFERENCE].unwrap());
+        assert_eq!(inode.attributes.blocks, 7);
+        assert_eq!(disk.usage().free_blocks, free_blocks - 7);
+        assert_eq!(disk.get_data_block_index(ino, DIRECT_REFERENCES - 1).unwrap(), inode.references[DIRECT_REFERENCES - 1]);
+        assert_eq!(disk.get_data_block_index(ino, DIRECT_REFERENCES).unwrap(), disk.read_indirect_entry(single, 0).unwrap());
+        assert_eq!(disk.get_data_block_index(ino, last_single).unwrap(), disk.read_indirect_entry(single, per_block - 1).unwrap());
+        let second_level = disk.read_indirect_entry(double, 0).unwrap().unwrap();
+        assert_eq!(disk.get_data_block_index(ino, last_single + 1).unwrap(), disk.read_indirect_entry(second_level, 0).unwrap());
+        for (value, position) in [DIRECT_REFERENCES - 1, DIRECT_REFERENCES, last_single, last_single + 1].iter().enumerate() {
+            assert_eq!(disk.read_file(ino, (*position * 512) as u64, 1).unwrap().unwrap(), vec![value as u8 + 1]);
+        }
+        remove_disk(disk);
+    }
+    #[test]
+    fn free_data_blocks_from_releases_empty_indirect_blocks() {
+        let mut disk = test_disk("persistencia-liberar-bloques");
+        let ino = add_file(&mut disk, "archivo");
+        let last_single = DIRECT_REFERENCES + disk.references_per_block() - 1;
+        let free_blocks = disk.usage().free_blocks;
+        for position in [0, DIRECT_REFERENCES - 1, DIRECT_REFERENCES, last_single, last_single + 1].iter() {
+            disk.write_file(ino, (*position * 512) as u64, b"x").unwrap();
+        }
+
+        assert_eq!(disk.get_inode(ino).unwrap().attributes.blocks, 8);
+
+        // El indirecto doble se queda sin entradas y se libera con sus dos niveles
+        disk.free_data_blocks_from(ino, last_single + 1).unwrap();
+        assert!(disk.get_inode(ino).unwrap().references[DOUBLE_INDIRECT_REFERENCE].is_none());
+        assert_eq!(disk.get_inode(ino).unwrap().attributes.blocks, 5);
+        assert_eq!(disk.usage().free_blocks, free_blocks - 5);
+
+        // El indirecto simple conserva su primera entrada
+        disk.free_data_blocks_from(ino, DIRECT_REFERENCES + 1).unwrap();
+        assert!(disk.get_inode(ino).unwrap().references[SINGLE_INDIRECT_REFERENCE].is_some());
+        assert!(disk.get_data_block_index(ino, last_single).unwrap().is_none());
+        assert_eq!(disk.get_inode(ino).unwrap().attributes.blocks, 4);
+        assert_eq!(disk.usage().free_blocks, free_blocks - 4);
+
+        disk.free_data_blocks_from(ino, 1).unwrap();
+        let inode = disk.get_inode(ino).unwrap();
+        assert!(inode.references[SINGLE_INDIRECT_REFERENCE].is_none());
+        assert!(inode.references[DIRECT_REFERENCES - 1].is_none());
+        assert!(inode.references[0].is_some());
+        assert_eq!(inode.attributes.blocks, 1);
+        assert_eq!(disk.usage().free_blocks, free_blocks - 1);
+        assert!(disk.check_consistency().is_empty());
+        remove_disk(disk);
+    }
+    #[test]
+    fn block_and_reference_writes_check_their_targets() {
+        let mut disk = test_disk("persistencia-escrituras-invalidas");
+
+        assert!(matches!(disk.write_content_as_bytes(64, Box::default()), Err(DiskError::BlockOutOfRange(64))));
+        assert!(matches!(disk.write_content_as_bytes(10, vec![0; 513].into_boxed_slice()), Err(DiskError::BlockOverflow(513))));
+        assert!(matches!(disk.write_reference_in_inode(5, 0, 10), Err(DiskError::InodeNotFound(5))));
+        assert!(matches!(disk.get_references_from_inode(5), Err(DiskError::InodeNotFound(5))));
+        assert!(disk.get_references_from_inode(1).is_ok());
+        assert!(disk.pending_blocks.is_empty());
+        remove_disk(disk);
+    }
+    #[test]
+    fn truncate_shrinks_and_grows_with_zeros() {
+        let mut disk = test_disk("persistencia-truncar");
+        let ino = add_file(&mut disk, "archivo");
+        let free_blocks = disk.usage().free_blocks;
+        disk.write_file(ino, 0, &[9; 1200]).unwrap();
+        assert_eq!(disk.get_inode(ino).unwrap().attributes.blocks, 3);
+
+        // Achicar a la mitad del primer bloque libera los otros dos
+        disk.truncate_file(ino, 300).unwrap();
+        assert_eq!(disk.get_inode(ino).unwrap().attributes.size, 300);
+        assert_eq!(disk.get_inode(ino).unwrap().attributes.blocks, 1);
+        assert_eq!(disk.usage().free_blocks, free_blocks - 1);
+
+        // Al agrandarlo lo que estaba más allá del tamaño se lee como ceros
+        disk.truncate_file(ino, 1100).unwrap();
+        let content = disk.read_file(ino, 0, 2000).unwrap().unwrap();
+        assert_eq!(content.len(), 1100);
+        assert_eq!(&content[..300], &[9; 300][..]);
+        assert!(content[300..].iter().all(|byte| *byte == 0));
+        assert_eq!(disk.usage().free_blocks, free_blocks - 1);
+        remove_disk(disk);
+    }
+    #[test]
+    fn blocks_are_counted_in_512_byte_units() {
+        let path = test_directory("persistencia-bloques-en-sectores");
+        let mut disk = Disk::create(path.to_string_lossy().into_owned(), 4096, 64, 16, test_credential()).unwrap();
+        let ino = add_file(&mut disk, "archivo");
+        disk.write_file(ino, 0, &[1; 5000]).unwrap();
+        assert_eq!(disk.get_inode(ino).unwrap().attributes.blocks, 2 * 8);
+
+        // El bloque indirecto simple también ocupa lugar en el disco
+        disk.write_file(ino, (DIRECT_REFERENCES * 4096) as u64, b"x").unwrap();
+        assert_eq!(disk.get_inode(ino).unwrap().attributes.blocks, 4 * 8);
+
+        disk.truncate_file(ino, 0).unwrap();
+        assert_eq!(disk.get_inode(ino).unwrap().attributes.blocks, 0);
+        remove_disk(disk);
+    }
+    #[test]
+    fn truncate_releases_the_indirect_blocks() {
+        let mut disk = test_disk("persistencia-truncar-indirectos");
+        let ino = add_file(&mut disk, "archivo");
+        let free_blocks = disk.usage().free_blocks;
+        let last_single = DIRECT_REFERENCES + disk.references_per_block() - 1;
+        for position in [0, DIRECT_REFERENCES, last_single + 1].iter() {
+            disk.write_file(ino, (*position * 512) as u64, b"x").unwrap();
+        }
+
+        // Por debajo del indirecto doble se liberan sus dos niveles
+        disk.truncate_file(ino, ((last_single + 1) * 512) as u64).unwrap();
+        assert!(disk.get_inode(ino).unwrap().references[DOUBLE_INDIRECT_REFERENCE].is_none());
+        assert!(disk.get_inode(ino).unwrap().references[SINGLE_INDIRECT_REFERENCE].is_some());
+        assert_eq!(disk.usage().free_blocks, free_blocks - 3);
+
+        // Por debajo del indirecto simple queda solo el bloque directo
+        disk.truncate_file(ino, (DIRECT_REFERENCES * 512) as u64).unwrap();
+        let inode = disk.get_inode(ino).unwrap();
+        assert!(inode.references[SINGLE_INDIRECT_REFERENCE].is_none());
+        assert_eq!(inode.attributes.blocks, 1);
+        assert_eq!(inode.attributes.size, (DIRECT_REFERENCES * 512) as u64);
+        assert_eq!(disk.usage().free_blocks, free_blocks - 1);
+        assert!(disk.check_consistency().is_empty());
+        remove_disk(disk);
+    }
+    #[test]
+    fn truncate_stops_at_the_maximum_file_size() {
+        let mut disk = test_disk("persistencia-truncar-maximo");
+        let ino = add_file(&mut disk, "archivo");
+        let max_file_size = disk.max_file_size();
+
+        assert!(matches!(disk.truncate_file(ino, max_file_size + 1), Err(DiskError::FileTooLarge(_))));
+        assert_eq!(disk.get_inode(ino).unwrap().attributes.size, 0);
+        disk.truncate_file(ino, max_file_size).unwrap();
+        assert_eq!(disk.get_inode(ino).unwrap().attributes.size, max_file_size);
+        assert_eq!(disk.get_inode(ino).unwrap().attributes.blocks, 0);
+        remove_disk(disk);
+    }
+    /// Contenido de los archivos del directorio `root_path`, por nombre.
+    fn read_files(root_path: &str) -> BTreeMap<OsString, Vec<u8>> {
+        std::fs::read_dir(root_path).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .map(|path| (path.file_name().unwrap().to_os_string(), std::fs::read(&path).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn read_only_loads_report_repairs_without_writing() {
+        let mut disk = test_disk("persistencia-solo-lectura");
+        let ino = add_file(&mut disk, "archivo");
+        disk.write_file(ino, 0, b"contenido").unwrap();
+        let unlinked = add_file(&mut disk, "abierto");
+        disk.open_handle(unlinked);
+        disk.remove_directory_entry(1, OsStr::new("abierto")).unwrap();
+        disk.decrease_links(unlinked).unwrap();
+        disk.commit_transaction().unwrap();
+        let root_path = disk.root_path().to_string();
+        drop(disk);
+        let files = read_files(&root_path);
+
+        let (mut disk, repairs) = Disk::open_read_only(root_path.clone(), test_credential()).unwrap();
+        assert_eq!(repairs.len(), 2);
+        assert!(repairs[0].contains("bitácora"));
+        assert!(repairs[1].starts_with("1 archivos"));
+        assert_eq!(disk.read_file(ino, 0, 64).unwrap().unwrap(), b"contenido");
+        assert!(disk.check_consistency().is_empty());
+
+        // El disco no registra ni guarda cambios
+        add_file(&mut disk, "nuevo");
+        assert!(matches!(disk.commit_transaction(), Err(DiskError::ReadOnly)));
+        assert!(matches!(disk.write_to_disk(), Err(DiskError::ReadOnly)));
+        assert!(matches!(disk.add_key(&test_credential()), Err(DiskError::ReadOnly)));
+        drop(disk);
+        assert_eq!(read_files(&root_path), files);
+
+        // Al cargarlo para usarlo se arregla y ya no queda nada pendiente
+        drop(Disk::new(root_path.clone(), test_credential()).unwrap());
+        let (disk, repairs) = Disk::open_read_only(root_path, test_credential()).unwrap();
+        assert!(repairs.is_empty());
+        assert!(disk.get_inode(unlinked).is_none());
+        remove_disk(disk);
+    }
+    #[test]
+    fn read_only_loads_refuse_interrupted_saves() {
+        let disk = test_disk("persistencia-solo-lectura-interrumpido");
+        let root_path = disk.root_path().to_string();
+        drop(disk);
+        std::fs::write(format!("{}/{}", root_path, COMMIT_FILE), b"").unwrap();
+        let files = read_files(&root_path);
+
+        assert!(matches!(Disk::open_read_only(root_path.clone(), test_credential()), Err(DiskError::NeedsRepair(_))));
+        assert_eq!(read_files(&root_path), files);
+
+        // Sin la marca, las copias a medias solo se informan
+        std::fs::remove_file(format!("{}/{}", root_path, COMMIT_FILE)).unwrap();
+        std::fs::write(format!("{}/{}", root_path, INODE_TEMP_FILE), b"").unwrap();
+        let (disk, repairs) = Disk::open_read_only(root_path, test_credential()).unwrap();
+        assert_eq!(repairs.len(), 1);
+        remove_disk(disk);
+    }
+    #[test]
+    fn recover_access_needs_a_free_slot_or_a_named_one() {
+        let disk = test_disk("persistencia-recuperar-acceso");
+        let (root_path, key) = (disk.root_path.clone(), disk.key.clone());
+        drop(disk);
+
+        let credential = Credential::KeyFile(b"llave recuperada".to_vec());
+        for slot in 1..MAX_KEY_SLOTS {
+            assert_eq!(Disk::recover_access(&root_path, &key, &credential, None).unwrap(), slot);
+        }
+        assert!(matches!(Disk::recover_access(&root_path, &key, &credential, None), Err(DiskError::KeySlot(_))));
+        assert!(matches!(Disk::recover_access(&root_path, &key, &credential, Some(MAX_KEY_SLOTS)), Err(DiskError::KeySlot(_))));
+
+        // La ranura 0 sigue abriendo el disco hasta que se pide reemplazarla
+        assert!(Disk::new(root_path.clone(), test_credential()).is_ok());
+        assert_eq!(Disk::recover_access(&root_path, &key, &credential, Some(0)).unwrap(), 0);
+        assert!(matches!(Disk::new(root_path.clone(), test_credential()), Err(DiskError::WrongPhrase)));
+
+        std::fs::remove_dir_all(root_path).unwrap();
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::BTreeMap; //Libreria para los atributos extendidos de los inodos nuevos
+use std::ffi::OsString; //Libreria para los nombres de las entradas
+use std::path::PathBuf; //Libreria para las rutas de los directorios temporales
+use fuse::{FileAttr, FileType}; //Libreria para el manejo del FileSytem en User Space
+use crate::cifrado::Credential; //Libreria propietaria del proyecto para el cifrado del disco
+use crate::entradas::DirectoryEntry; //Libreria propietaria del proyecto para las entradas de los directorios
+use crate::persistencia::{Disk, Inode}; //Libreria propietaria del proyecto
+
+// Utilidades compartidas por las pruebas de los módulos: discos de prueba en directorios temporales y los inodos que
+// crean las operaciones del sistema de archivos.
+
+/// Directorio temporal vacío para la prueba `name`, distinto en cada ejecución de las pruebas. El nombre tiene que ser
+/// único entre todas las pruebas, porque corren al mismo tiempo.
+pub fn test_directory(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("qrfs-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    std::fs::create_dir_all(&path).unwrap();
+    path
+}
+
+/// Credencial de los discos de prueba: un archivo de llave, para no esperar a Argon2 con cada frase.
+pub fn test_credential() -> Credential {
+    Credential::KeyFile(b"llave de prueba".to_vec())
+}
+
+/// Disco nuevo de 64 bloques de 512 bytes y 16 inodos en el directorio temporal de la prueba `name`.
+pub fn test_disk(name: &str) -> Disk {
+    let path = test_directory(name);
+    Disk::create(path.to_string_lossy().into_owned(), 512, 64, 16, test_credential()).unwrap()
+}
+
+/// Vuelve a cargar el disco de `disk` desde sus archivos, como al montarlo otra vez.
+pub fn reload_disk(disk: Disk) -> Disk {
+    let root_path = disk.root_path().to_string();
+    drop(disk);
+    Disk::new(root_path, test_credential()).unwrap()
+}
+
+/// Cierra el disco y borra su directorio.
+pub fn remove_disk(disk: Disk) {
+    let root_path = disk.root_path().to_string();
+    drop(disk);
+    std::fs::remove_dir_all(root_path).unwrap();
+}
+
+/// Atributos de un inodo nuevo `ino` de tipo `kind`, de root, con los permisos `perm` y un enlace.
+pub fn test_attributes(ino: u64, kind: FileType, perm: u16) -> FileAttr {
+    let ts = time::now().to_timespec();
+    FileAttr {
+        ino, size: 0, blocks: 0, atime: ts, mtime: ts, ctime: ts, crtime: ts, kind, perm,
+        nlink: 1, uid: 0, gid: 0, rdev: 0, flags: 0
+    }
+}
+
+/// Crea `name` en el directorio `parent` con un inodo nuevo de tipo `kind`, como `create`, sin registrar la operación.
+/// Devuelve su número `ino`.
+pub fn add_node(disk: &mut Disk, parent: u64, name: &str, kind: FileType) -> u64 {
+    let ino = disk.find_ino_available().unwrap();
+    disk.add_directory_entry(parent, DirectoryEntry { ino, kind, name: OsString::from(name) }).unwrap();
+    disk.write_inode(Inode { attributes: test_attributes(ino, kind, 0o644), references: [None; 128], target: None, xattrs: BTreeMap::new() }).unwrap();
+    ino
+}
+
+/// Crea el archivo regular `name` en la raíz, como `create`, sin registrar la operación.
+pub fn add_file(disk: &mut Disk, name: &str) -> u64 {
+    add_node(disk, 1, name, FileType::RegularFile)
+}
@@ -0,0 +1,127 @@
+use std::ffi::OsStr; //Libreria para los nombres, que son bytes cualesquiera
+use std::os::unix::ffi::OsStrExt; //Libreria para pasar los nombres a bytes sin convertirlos
+use serde::{Serialize, Deserialize}; //Libreria para serializar los atributos junto con su inodo
+use libc::{ERANGE, E2BIG, EEXIST, EINVAL, ENODATA, ENOTSUP, XATTR_CREATE, XATTR_REPLACE}; //Libreria para el manejo de banderas del codigo en C
+
+// Largo máximo en bytes del nombre de un atributo extendido, con su prefijo, como `XATTR_NAME_MAX`
+pub const XATTR_NAME_MAX: usize = 255;
+// Largo máximo en bytes del valor de un atributo extendido, como `XATTR_SIZE_MAX`
+pub const XATTR_SIZE_MAX: usize = 65536;
+// Largo máximo de un valor que se guarda dentro del inodo; los más largos van en bloques de datos propios
+pub const INLINE_XATTR_CAPACITY: usize = 256;
+// Bytes del inodo serializado que pueden ocupar sus atributos extendidos: nombres, valores cortos y referencias a los
+// bloques de los valores largos
+pub const XATTR_INODE_CAPACITY: u64 = 2048;
+
+/// Valor de un atributo extendido. Los valores de hasta `INLINE_XATTR_CAPACITY` bytes se guardan en el inodo; los más
+/// largos se reparten en bloques de datos que solo usa el atributo, en orden.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum XattrValue {
+    Inline(Vec<u8>),
+    Blocks { length: usize, blocks: Vec<usize> }
+}
+
+impl XattrValue {
+    /// Bloques de datos que ocupa el valor.
+    pub fn blocks(&self) -> &[usize] {
+        match self {
+            XattrValue::Inline(_) => &[],
+            XattrValue::Blocks { blocks, .. } => blocks
+        }
+    }
+}
+
+/// Espacio de nombres de un atributo extendido, dado por el prefijo de su nombre.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Namespace {
+    // Atributos de los usuarios, con los permisos del archivo
+    User,
+    // Atributos que solo root puede ver y cambiar
+    Trusted,
+    // Etiquetas de seguridad, como las de SELinux
+    Security
+}
+
+/// Espacio de nombres del atributo `name`. Devuelve el código de error de `setxattr(2)` si el nombre es demasiado
+/// largo (`ERANGE`), no tiene nada después del prefijo (`EINVAL`) o su prefijo no se soporta (`ENOTSUP`).
+pub fn xattr_namespace(name: &OsStr) -> Result<Namespace, i32> {
+    let name = name.as_bytes();
+    if name.len() > XATTR_NAME_MAX {
+        return Err(ERANGE);
+    }
+
+    let prefixes = [(&b"user."[..], Namespace::User), (b"trusted.", Namespace::Trusted), (b"security.", Namespace::Security)];
+    for (prefix, namespace) in prefixes.iter() {
+        if name.starts_with(prefix) {
+            return match name.len() > prefix.len() {
+                true => Ok(*namespace),
+                false => Err(EINVAL)
+            };
+        }
+    }
+
+    Err(ENOTSUP)
+}
+
+/// Revisa que un valor de `length` bytes se pueda guardar en un atributo extendido.
+pub fn check_xattr_size(length: usize) -> Result<(), i32> {
+    match length > XATTR_SIZE_MAX {
+        true => Err(E2BIG),
+        false => Ok(())
+    }
+}
+
+/// Revisa las banderas `flags` de `setxattr(2)` contra si el atributo ya existe: `XATTR_CREATE` falla con `EEXIST` si
+/// existe y `XATTR_REPLACE` con `ENODATA` si no.
+pub fn check_xattr_flags(exists: bool, flags: i32) -> Result<(), i32> {
+    if exists && flags & XATTR_CREATE != 0 {
+        return Err(EEXIST); // “File exists.”
+    }
+    if !exists && flags & XATTR_REPLACE != 0 {
+        return Err(ENODATA); // “No data available.”
+    }
+    Ok(())
+}
+
+/// Revisa que una respuesta de `length` bytes quepa en el búfer de `size` bytes de `getxattr(2)` o `listxattr(2)`. Un
+/// búfer de tamaño 0 solo pide el largo y siempre alcanza.
+pub fn check_xattr_buffer(length: usize, size: u32) -> Result<(), i32> {
+    match size != 0 && length > size as usize {
+        true => Err(ERANGE), // “Result too large.”
+        false => Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_need_a_known_prefix_and_a_suffix() {
+        assert_eq!(xattr_namespace(OsStr::new("user.nombre")), Ok(Namespace::User));
+        assert_eq!(xattr_namespace(OsStr::new("trusted.nombre")), Ok(Namespace::Trusted));
+        assert_eq!(xattr_namespace(OsStr::new("security.selinux")), Ok(Namespace::Security));
+        assert_eq!(xattr_namespace(OsStr::new("user.")), Err(EINVAL));
+        assert_eq!(xattr_namespace(OsStr::new("system.posix_acl_access")), Err(ENOTSUP));
+        assert_eq!(xattr_namespace(OsStr::new(&format!("user.{}", "a".repeat(XATTR_NAME_MAX)))), Err(ERANGE));
+        assert_eq!(check_xattr_size(XATTR_SIZE_MAX), Ok(()));
+        assert_eq!(check_xattr_size(XATTR_SIZE_MAX + 1), Err(E2BIG));
+    }
+
+    #[test]
+    fn create_and_replace_flags_check_whether_the_attribute_exists() {
+        assert_eq!(check_xattr_flags(false, 0), Ok(()));
+        assert_eq!(check_xattr_flags(true, 0), Ok(()));
+        assert_eq!(check_xattr_flags(false, XATTR_CREATE), Ok(()));
+        assert_eq!(check_xattr_flags(true, XATTR_CREATE), Err(EEXIST));
+        assert_eq!(check_xattr_flags(true, XATTR_REPLACE), Ok(()));
+        assert_eq!(check_xattr_flags(false, XATTR_REPLACE), Err(ENODATA));
+    }
+
+    #[test]
+    fn short_buffers_get_erange() {
+        assert_eq!(check_xattr_buffer(10, 0), Ok(()));
+        assert_eq!(check_xattr_buffer(10, 10), Ok(()));
+        assert_eq!(check_xattr_buffer(10, 9), Err(ERANGE));
+    }
+}
@@ -0,0 +1,254 @@
+use std::fs::File; // Liberia para el manejo de archivos
+use std::io::{self, Write}; //Libreria para el manejo de entradas y salidas
+use std::convert::TryInto; //Libreria para leer el largo de cada transacción
+use std::sync::Arc; //Libreria para compartir el contador con el hilo de sincronización
+use std::sync::atomic::{AtomicU64, Ordering}; //Libreria para el contador de transacciones sin sincronizar
+use std::thread; //Libreria para el hilo de sincronización periódica
+use std::time::Duration; //Libreria para el intervalo de sincronización
+use serde::{Serialize, Deserialize}; //Libreria para el manejo de serealizacion
+use bincode::{serialize, deserialize}; //Libreria para encodificar y codificar en binario
+use libc::{O_APPEND, O_CREAT, O_RDONLY, O_WRONLY}; //Libreria para el manejo de banderas del codigo en C
+use crate::cifrado::Key; //Libreria propietaria del proyecto para el cifrado del disco
+use crate::directorio::DiskDirectory; //Libreria propietaria del proyecto para los archivos del disco
+use crate::persistencia::Inode; //Libreria propietaria del proyecto
+
+// Nombre de la bitácora dentro del directorio del disco
+pub const JOURNAL_FILE: &str = "journal.qrfs";
+// Tamaño del prefijo con el largo de cada transacción
+const LENGTH_SIZE: usize = 8;
+
+/// Cambio registrado en la bitácora. Guarda el estado completo del inodo o del bloque después del cambio, así que
+/// volver a aplicarlo sobre un disco que ya lo tiene no cambia nada.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum JournalRecord {
+    Inode { index: usize, inode: Option<Box<Inode>> },
+    Block { index: usize, data: Option<Box<[u8]>> }
+}
+
+impl JournalRecord {
+    /// Posición del inodo que cambia el registro, o `None` si cambia un bloque.
+    pub fn inode_index(&self) -> Option<usize> {
+        match self {
+            JournalRecord::Inode { index, .. } => Some(*index),
+            JournalRecord::Block { .. } => None
+        }
+    }
+}
+
+/// Contexto autenticado junto con la transacción número `sequence`, para que no se puedan reordenar ni quitar
+/// transacciones del medio de la bitácora.
+fn transaction_context(sequence: u64) -> Vec<u8> {
+    format!("qrfs-bitacora-{}", sequence).into_bytes()
+}
+
+/// Bitácora de escritura anticipada, guardada en `journal.qrfs` junto a `disco.qrfs`. Cada operación del sistema de
+/// archivos agrega una transacción con los cambios que hizo; al cargar el disco se vuelven a aplicar las transacciones
+/// completas sobre la imagen y la bitácora se vacía cada vez que sus cambios se escriben en la imagen.
+///
+/// Cada transacción se guarda como su largo (8 bytes, little endian) seguido de la lista de cambios cifrada con la
+/// llave maestra. Una transacción cortada por una interrupción no se puede descifrar y marca el final de la bitácora.
+#[derive(Debug)]
+pub struct Journal {
+    file: File,
+    transactions: u64,
+    size: u64,
+    // Transacciones escritas que todavía no se sincronizaron con el dispositivo
+    unsynced: Arc<AtomicU64>
+}
+
+impl Journal {
+    /// Abre la bitácora del directorio `directory`, creándola si no existe. Se espera que esté vacía: las
+    /// transacciones anteriores se leen antes con `read_transactions` y se vacía con `reset`.
+    pub fn open(directory: &DiskDirectory) -> io::Result<Journal> {
+        let file = directory.open_file(JOURNAL_FILE, O_WRONLY | O_CREAT | O_APPEND)?;
+        let size = file.metadata()?.len();
+
+        Ok(Journal {
+            file,
+            transactions: 0,
+            size,
+            unsynced: Arc::new(AtomicU64::new(0))
+        })
+    }
+
+    /// Abre la bitácora del directorio `directory` solo para leer, sin crearla si no existe, para revisar el disco sin
+    /// cambiarlo. Sin bitácora se usa `/dev/null`. Agregar una transacción o vaciarla falla.
+    pub fn open_read_only(directory: &DiskDirectory) -> io::Result<Journal> {
+        let file = match directory.exists(JOURNAL_FILE) {
+            true => directory.open_file(JOURNAL_FILE, O_RDONLY)?,
+            false => File::open("/dev/null")?
+        };
+        let size = file.metadata()?.len();
+
+        Ok(Journal {
+            file,
+            transactions: 0,
+            size,
+            unsynced: Arc::new(AtomicU64::new(0))
+        })
+    }
+
+    /// Lee las transacciones completas de la bitácora de `directory`, en orden, descifrándolas con `key`.
+    pub fn read_transactions(directory: &DiskDirectory, key: &Key) -> io::Result<Vec<Vec<JournalRecord>>> {
+        if !directory.exists(JOURNAL_FILE) {
+            return Ok(Vec::new());
+        }
+
+        let content = directory.read(JOURNAL_FILE)?;
+        let mut transactions = Vec::new();
+        let mut position = 0;
+
+        while let Some(length) = content.get(position..position + LENGTH_SIZE) {
+            let length = u64::from_le_bytes(length.try_into().unwrap()) as usize;
+            let start = position + LENGTH_SIZE;
+
+            let records = content.get(start..start.saturating_add(length))
+                .and_then(|sealed| key.decrypt(sealed, &transaction_context(transactions.len() as u64)))
+                .and_then(|records| deserialize::<Vec<JournalRecord>>(&records).ok());
+
+            match records {
+                Some(records) => transactions.push(records),
+                None => break
+            }
+            position = start + length;
+        }
+
+        Ok(transactions)
+    }
+
+    /// Agrega una transacción con los cambios `records` al final de la bitácora.
+    pub fn append(&mut self, key: &Key, records: &[JournalRecord]) -> io::Result<()> {
+        let content = serialize(records).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let sealed = key.encrypt(&content, &transaction_context(self.transactions));
+
+        // El largo y la transacción se escriben juntos para no dejar un largo sin su contenido
+        let mut frame = Vec::with_capacity(LENGTH_SIZE + sealed.len());
+        frame.extend_from_slice(&(sealed.len() as u64).to_le_bytes());
+        frame.extend_from_slice(&sealed);
+        self.file.write_all(&frame)?;
+
+        self.transactions += 1;
+        self.size += frame.len() as u64;
+        self.unsynced.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Sincroniza la bitácora con el dispositivo, de modo que todas las transacciones agregadas sobrevivan a un corte
+    /// de energía.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.unsynced.store(0, Ordering::SeqCst);
+        self.file.sync_data()
+    }
+
+    /// Cantidad de transacciones agregadas desde la última sincronización.
+    pub fn unsynced(&self) -> u64 {
+        self.unsynced.load(Ordering::SeqCst)
+    }
+
+    /// Inicia un hilo que sincroniza la bitácora cada `interval` si tiene transacciones nuevas. El hilo usa su propia
+    /// copia del descriptor, así que no bloquea las operaciones del sistema de archivos.
+    pub fn start_periodic_sync(&self, interval: Duration) -> io::Result<()> {
+        let file = self.file.try_clone()?;
+        let unsynced = Arc::clone(&self.unsynced);
+
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            if unsynced.swap(0, Ordering::SeqCst) > 0 {
+                if let Err(e) = file.sync_data() {
+                    println!("¡Error al sincronizar la bitácora! {}", e);
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Vacía la bitácora, una vez que la imagen guardada ya contiene todos sus cambios.
+    pub fn reset(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.sync_all()?;
+
+        self.transactions = 0;
+        self.size = 0;
+        self.unsynced.store(0, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Tamaño en bytes de la bitácora.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+    use std::fs::OpenOptions;
+    use std::path::Path;
+    use crate::pruebas::{add_file, reload_disk, remove_disk, test_directory, test_disk};
+
+    fn block_record(index: usize, byte: u8) -> JournalRecord {
+        JournalRecord::Block { index, data: Some(Box::from(&[byte; 16][..])) }
+    }
+
+    fn block_byte(record: &JournalRecord) -> Option<u8> {
+        match record {
+            JournalRecord::Block { data: Some(data), .. } => Some(data[0]),
+            _ => None
+        }
+    }
+
+    #[test]
+    fn transactions_are_read_in_order_up_to_a_cut_one() {
+        let path = test_directory("bitacora-cortada");
+        let directory = DiskDirectory::open(&path.to_string_lossy()).unwrap();
+        let key = Key::generate();
+
+        let mut journal = Journal::open(&directory).unwrap();
+        journal.append(&key, &[block_record(3, 1)]).unwrap();
+        journal.append(&key, &[block_record(4, 2), block_record(5, 3)]).unwrap();
+        journal.sync().unwrap();
+
+        // Una transacción a medio escribir, como la que deja un corte de energía
+        let mut file = OpenOptions::new().append(true).open(path.join(JOURNAL_FILE)).unwrap();
+        file.write_all(&1000u64.to_le_bytes()).unwrap();
+        file.write_all(&[0; 40]).unwrap();
+
+        let transactions = Journal::read_transactions(&directory, &key).unwrap();
+        let bytes: Vec<Vec<Option<u8>>> = transactions.iter().map(|records| records.iter().map(block_byte).collect()).collect();
+        assert_eq!(bytes, vec![vec![Some(1)], vec![Some(2), Some(3)]]);
+
+        // Con otra llave no se puede leer ninguna
+        assert!(Journal::read_transactions(&directory, &Key::generate()).unwrap().is_empty());
+
+        journal.reset().unwrap();
+        assert_eq!(journal.size(), 0);
+        assert!(Journal::read_transactions(&directory, &key).unwrap().is_empty());
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn loading_the_disk_replays_the_journal_and_empties_it() {
+        let mut disk = test_disk("bitacora-reaplicar");
+        let journal = Path::new(disk.root_path()).join(JOURNAL_FILE);
+
+        // Crea un archivo y le escribe sin que sus cambios lleguen a la imagen
+        let ino = add_file(&mut disk, "archivo");
+        disk.commit_transaction().unwrap();
+        disk.write_file(ino, 0, &[9; 700]).unwrap();
+        disk.commit_transaction().unwrap();
+        assert!(std::fs::metadata(&journal).unwrap().len() > 0);
+
+        let disk = reload_disk(disk);
+        assert_eq!(disk.find_inode_in_references_by_name(1, OsStr::new("archivo")).unwrap().map(|inode| inode.attributes.ino), Some(ino));
+        assert_eq!(disk.read_file(ino, 0, 1024).unwrap().unwrap(), vec![9; 700]);
+        assert!(disk.check_consistency().is_empty());
+        assert_eq!(std::fs::metadata(&journal).unwrap().len(), 0);
+
+        // Volver a cargarlo sin bitácora deja el mismo contenido, que ya está en la imagen
+        let disk = reload_disk(disk);
+        assert_eq!(disk.read_file(ino, 0, 1024).unwrap().unwrap(), vec![9; 700]);
+        remove_disk(disk);
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::{BTreeMap, HashMap}; //Libreria para el manejo de diccionarios
+use std::hash::Hash; //Libreria para usar cualquier clave de diccionario
+use std::rc::Rc; //Libreria para compartir el contenido de los bloques sin copiarlo
+
+// Memoria usada por defecto para los bloques leídos de la imagen, en bytes
+pub const DEFAULT_CACHE_SIZE: usize = 16 * 1024 * 1024;
+// Cantidad de directorios cuyos índices de entradas se mantienen en memoria
+pub const DIRECTORY_INDEX_CACHE_SIZE: usize = 1024;
+
+/// Caché de tamaño acotado que, cuando se llena, descarta el valor que lleva más tiempo sin usarse (LRU). Solo sirve
+/// para valores que se pueden volver a armar a partir del disco, porque descartar uno no debe perder datos.
+#[derive(Debug)]
+pub struct LruCache<K, V> {
+    capacity: usize,
+    // Contador que aumenta con cada uso, para saber qué valor se usó hace más tiempo
+    clock: u64,
+    values: HashMap<K, (V, u64)>,
+    // Claves ordenadas por su último uso
+    order: BTreeMap<u64, K>
+}
+
+/// Caché de los bloques leídos de la imagen. Solo guarda bloques iguales a su casilla en `disco.qrfs`: uno descartado
+/// se vuelve a leer la próxima vez que se necesite.
+pub type BlockCache = LruCache<usize, Rc<[u8]>>;
+
+impl<K: Copy + Eq + Hash, V> LruCache<K, V> {
+    /// Crea una caché vacía con lugar para `capacity` valores.
+    pub fn new(capacity: usize) -> LruCache<K, V> {
+        LruCache {
+            capacity: capacity.max(1),
+            clock: 0,
+            values: HashMap::new(),
+            order: BTreeMap::new()
+        }
+    }
+
+    /// Devuelve el valor de `index`, si está en la caché, y lo marca como el último usado.
+    pub fn get(&mut self, index: K) -> Option<&mut V> {
+        let (data, used) = self.values.get_mut(&index)?;
+
+        self.order.remove(used);
+        self.clock += 1;
+        *used = self.clock;
+        self.order.insert(self.clock, index);
+
+        Some(data)
+    }
+
+    /// Guarda el valor de `index` en la caché, descartando el que lleva más tiempo sin usarse si ya no queda lugar.
+    pub fn insert(&mut self, index: K, data: V) {
+        self.remove(index);
+
+        while self.values.len() >= self.capacity && self.evict_oldest() {}
+
+        self.clock += 1;
+        self.values.insert(index, (data, self.clock));
+        self.order.insert(self.clock, index);
+    }
+
+    /// Quita el valor de `index` de la caché, si está.
+    pub fn remove(&mut self, index: K) {
+        if let Some((_, used)) = self.values.remove(&index) {
+            self.order.remove(&used);
+        }
+    }
+
+    /// Cambia la cantidad de valores que caben en la caché, descartando los que sobren.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity.max(1);
+
+        while self.values.len() > self.capacity && self.evict_oldest() {}
+    }
+
+    /// Descarta el valor que lleva más tiempo sin usarse. Devuelve `false` si la caché estaba vacía.
+    fn evict_oldest(&mut self) -> bool {
+        let oldest = self.order.iter().next().map(|(used, index)| (*used, *index));
+        match oldest {
+            Some((used, index)) => {
+                self.order.remove(&used);
+                self.values.remove(&index);
+                true
+            },
+            None => false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(byte: u8) -> Rc<[u8]> {
+        Rc::from(vec![byte; 8])
+    }
+
+    fn cached(cache: &mut BlockCache, indexes: &[usize]) -> Vec<bool> {
+        indexes.iter().map(|index| cache.get(*index).is_some()).collect()
+    }
+
+    #[test]
+    fn full_cache_evicts_the_least_recently_used_block() {
+        let mut cache = BlockCache::new(3);
+        for index in 0..3 {
+            cache.insert(index, block(index as u8));
+        }
+
+        // Leer el bloque 0 lo vuelve el último usado, así que el siguiente en salir es el 1
+        assert_eq!(&**cache.get(0).unwrap(), &[0; 8]);
+        cache.insert(3, block(3));
+        assert!(cache.get(1).is_none());
+        assert_eq!(cached(&mut cache, &[0, 2, 3]), vec![true; 3]);
+
+        // Volver a guardar un bloque reemplaza su contenido sin descartar otro
+        cache.insert(2, block(7));
+        assert_eq!(&**cache.get(2).unwrap(), &[7; 8]);
+        assert_eq!(cached(&mut cache, &[0, 3]), vec![true; 2]);
+    }
+
+    #[test]
+    fn shrinking_the_capacity_evicts_the_oldest_blocks() {
+        let mut cache = BlockCache::new(4);
+        for index in 0..4 {
+            cache.insert(index, block(index as u8));
+        }
+        cache.get(0);
+
+        cache.set_capacity(2);
+        assert_eq!(cached(&mut cache, &[1, 2]), vec![false; 2]);
+        assert_eq!(cached(&mut cache, &[3, 0]), vec![true; 2]);
+
+        // Una capacidad de cero deja lugar para un bloque
+        cache.set_capacity(0);
+        cache.insert(5, block(5));
+        assert_eq!(cached(&mut cache, &[0, 3, 5]), vec![false, false, true]);
+
+        cache.remove(5);
+        assert!(cache.get(5).is_none());
+    }
+}
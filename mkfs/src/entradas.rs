@@ -0,0 +1,271 @@
+use std::collections::{BTreeSet, HashMap}; //Libreria para el manejo de diccionarios y conjuntos
+use std::convert::TryInto; //Libreria para leer el número de inodo de cada entrada
+use std::ffi::{OsStr, OsString}; //Libreria para los nombres, que son bytes cualesquiera
+use std::os::unix::ffi::{OsStrExt, OsStringExt}; //Libreria para pasar los nombres a bytes sin convertirlos
+use fuse::FileType; //Libreria para el manejo del FileSytem en User Space
+use crate::persistencia::DiskError; //Libreria propietaria del proyecto
+
+// Largo máximo en bytes del nombre de una entrada
+pub const NAME_CAPACITY: usize = 255;
+// Tamaño de cada entrada en el contenido de un directorio: número de inodo (8 bytes, little endian), tipo (1 byte),
+// largo del nombre (1 byte) y nombre
+pub const DIRECTORY_ENTRY_SIZE: usize = 8 + 1 + 1 + NAME_CAPACITY;
+
+/// Entrada de un directorio: un nombre que apunta al inodo `ino`. El nombre se guarda con los bytes que lo forman,
+/// sin convertirlo a UTF-8. El contenido de un directorio es una lista de
+/// entradas de tamaño fijo guardada en sus bloques de datos, igual que el contenido de un archivo regular. Una entrada
+/// con `ino` 0 es una posición libre que se vuelve a usar.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirectoryEntry {
+    pub ino: u64,
+    pub kind: FileType,
+    pub name: OsString
+}
+
+/// Revisa que `name` se pueda usar como nombre de una entrada: que no pase de `NAME_CAPACITY` bytes y que no esté
+/// vacío ni tenga `/` o el byte nulo.
+pub fn check_name(name: &OsStr) -> Result<(), DiskError> {
+    let bytes = name.as_bytes();
+    if bytes.len() > NAME_CAPACITY {
+        return Err(DiskError::NameTooLong(name.to_os_string()));
+    }
+
+    if bytes.is_empty() || bytes.iter().any(|byte| *byte == b'/' || *byte == 0) {
+        return Err(DiskError::InvalidName(name.to_os_string()));
+    }
+
+    Ok(())
+}
+
+/// Código con el que se guarda el tipo de archivo de una entrada.
+fn kind_code(kind: FileType) -> u8 {
+    match kind {
+        FileType::NamedPipe => 1,
+        FileType::CharDevice => 2,
+        FileType::BlockDevice => 3,
+        FileType::Directory => 4,
+        FileType::RegularFile => 5,
+        FileType::Symlink => 6,
+        FileType::Socket => 7
+    }
+}
+
+fn kind_from_code(code: u8) -> Option<FileType> {
+    match code {
+        1 => Some(FileType::NamedPipe),
+        2 => Some(FileType::CharDevice),
+        3 => Some(FileType::BlockDevice),
+        4 => Some(FileType::Directory),
+        5 => Some(FileType::RegularFile),
+        6 => Some(FileType::Symlink),
+        7 => Some(FileType::Socket),
+        _ => None
+    }
+}
+
+impl DirectoryEntry {
+    /// Bytes con los que se guarda la entrada. Devuelve `None` si el nombre no cabe en `NAME_CAPACITY` bytes.
+    pub fn encode(&self) -> Option<Vec<u8>> {
+        let name = self.name.as_bytes();
+        if name.len() > NAME_CAPACITY {
+            return None;
+        }
+
+        let mut bytes = Vec::with_capacity(DIRECTORY_ENTRY_SIZE);
+        bytes.extend_from_slice(&self.ino.to_le_bytes());
+        bytes.push(kind_code(self.kind));
+        bytes.push(name.len() as u8);
+        bytes.extend_from_slice(name);
+        bytes.resize(DIRECTORY_ENTRY_SIZE, 0);
+        Some(bytes)
+    }
+
+    /// Lee la entrada guardada en `bytes`. Devuelve `Ok(None)` si es una posición libre.
+    pub fn decode(bytes: &[u8]) -> Result<Option<DirectoryEntry>, DiskError> {
+        let invalid = || DiskError::Corrupted(String::from("una entrada de directorio"));
+        if bytes.len() != DIRECTORY_ENTRY_SIZE {
+            return Err(invalid());
+        }
+
+        let ino = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+        if ino == 0 {
+            return Ok(None);
+        }
+
+        let kind = kind_from_code(bytes[8]).ok_or_else(invalid)?;
+        let length = bytes[9] as usize;
+        let name = OsString::from_vec(bytes[10..10 + length].to_vec());
+        Ok(Some(DirectoryEntry { ino, kind, name }))
+    }
+}
+
+/// Índice en memoria de las entradas de un directorio, para buscarlas por nombre sin leer su contenido. Se arma la
+/// primera vez que se usa el directorio y se mantiene al agregar o quitar entradas.
+#[derive(Debug, Default)]
+pub struct DirectoryIndex {
+    // Número de inodo y posición de cada entrada, por nombre
+    entries: HashMap<OsString, (u64, usize)>,
+    // Posiciones libres entre las entradas
+    free_slots: BTreeSet<usize>,
+    // Cantidad de posiciones del directorio, ocupadas o libres
+    slots: usize
+}
+
+impl DirectoryIndex {
+    /// Arma el índice a partir del contenido `content` del directorio. Si dos entradas tienen el mismo nombre, el
+    /// índice queda con la primera.
+    pub fn build(content: &[u8]) -> Result<DirectoryIndex, DiskError> {
+        let mut index = DirectoryIndex::default();
+
+        for (slot, bytes) in content.chunks(DIRECTORY_ENTRY_SIZE).enumerate() {
+            match DirectoryEntry::decode(bytes)? {
+                Some(entry) => {
+                    index.entries.entry(entry.name).or_insert((entry.ino, slot));
+                },
+                None => {
+                    index.free_slots.insert(slot);
+                }
+            }
+            index.slots = slot + 1;
+        }
+
+        Ok(index)
+    }
+
+    /// Número de inodo de la entrada llamada `name`, si existe.
+    pub fn get(&self, name: &OsStr) -> Option<u64> {
+        self.entries.get(name).map(|(ino, _)| *ino)
+    }
+
+    /// Cantidad de entradas ocupadas.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Posición donde se guardaría una entrada nueva: la primera libre o una al final.
+    pub fn next_slot(&self) -> usize {
+        self.free_slots.iter().next().copied().unwrap_or(self.slots)
+    }
+
+    /// Registra la entrada `name` en la posición `slot`.
+    pub fn insert(&mut self, name: OsString, ino: u64, slot: usize) {
+        self.free_slots.remove(&slot);
+        self.slots = self.slots.max(slot + 1);
+        self.entries.insert(name, (ino, slot));
+    }
+
+    /// Hace que la entrada `name` apunte al inodo `ino` y devuelve el número de inodo al que apuntaba y su posición.
+    pub fn replace(&mut self, name: &OsStr, ino: u64) -> Option<(u64, usize)> {
+        let (previous, slot) = self.entries.get_mut(name)?;
+        Some((std::mem::replace(previous, ino), *slot))
+    }
+
+    /// Quita la entrada `name` y devuelve su posición, que queda libre.
+    pub fn remove(&mut self, name: &OsStr) -> Option<usize> {
+        let (_, slot) = self.entries.remove(name)?;
+        self.free_slots.insert(slot);
+        Some(slot)
+    }
+
+    /// Descarta las posiciones libres del final y devuelve la nueva cantidad de posiciones.
+    pub fn trim(&mut self) -> usize {
+        while self.slots > 0 && self.free_slots.remove(&(self.slots - 1)) {
+            self.slots -= 1;
+        }
+        self.slots
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(ino: u64, kind: FileType, name: &[u8]) -> DirectoryEntry {
+        DirectoryEntry { ino, kind, name: OsString::from_vec(name.to_vec()) }
+    }
+
+    fn content(entries: &[Option<DirectoryEntry>]) -> Vec<u8> {
+        entries.iter()
+            .flat_map(|entry| entry.as_ref().map_or(vec![0; DIRECTORY_ENTRY_SIZE], |entry| entry.encode().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn names_must_fit_and_not_contain_slashes_or_nulls() {
+        assert!(check_name(OsStr::new("archivo.txt")).is_ok());
+        assert!(check_name(OsStr::from_bytes(&[0xff, 0xfe])).is_ok());
+        assert!(check_name(OsStr::from_bytes(&[b'a'; NAME_CAPACITY])).is_ok());
+
+        assert!(matches!(check_name(OsStr::from_bytes(&[b'a'; NAME_CAPACITY + 1])), Err(DiskError::NameTooLong(_))));
+        for name in [&b""[..], b"a/b", b"a\0b"] {
+            assert!(matches!(check_name(OsStr::from_bytes(name)), Err(DiskError::InvalidName(_))));
+        }
+    }
+
+    #[test]
+    fn entries_round_trip_through_their_bytes() {
+        let kinds = [
+            FileType::NamedPipe, FileType::CharDevice, FileType::BlockDevice, FileType::Directory,
+            FileType::RegularFile, FileType::Symlink, FileType::Socket
+        ];
+        for (ino, kind) in (1..).zip(kinds) {
+            let original = entry(ino, kind, b"nombre \xff sin UTF-8");
+            let bytes = original.encode().unwrap();
+            assert_eq!(bytes.len(), DIRECTORY_ENTRY_SIZE);
+            assert_eq!(DirectoryEntry::decode(&bytes).unwrap(), Some(original));
+        }
+
+        let longest = entry(u64::MAX, FileType::RegularFile, &[b'x'; NAME_CAPACITY]);
+        assert_eq!(DirectoryEntry::decode(&longest.encode().unwrap()).unwrap(), Some(longest));
+        assert!(entry(2, FileType::RegularFile, &[b'x'; NAME_CAPACITY + 1]).encode().is_none());
+    }
+
+    #[test]
+    fn free_slots_and_damaged_entries_are_told_apart() {
+        assert_eq!(DirectoryEntry::decode(&[0; DIRECTORY_ENTRY_SIZE]).unwrap(), None);
+
+        let mut bytes = entry(3, FileType::Directory, b"dir").encode().unwrap();
+        bytes[8] = 0;
+        assert!(matches!(DirectoryEntry::decode(&bytes), Err(DiskError::Corrupted(_))));
+        assert!(matches!(DirectoryEntry::decode(&bytes[1..]), Err(DiskError::Corrupted(_))));
+    }
+
+    #[test]
+    fn index_reuses_free_slots_and_trims_the_end() {
+        let mut index = DirectoryIndex::build(&content(&[
+            Some(entry(2, FileType::RegularFile, b"a")),
+            None,
+            Some(entry(3, FileType::Directory, b"b"))
+        ])).unwrap();
+        assert_eq!((index.len(), index.get(OsStr::new("a")), index.get(OsStr::new("b"))), (2, Some(2), Some(3)));
+        assert_eq!(index.next_slot(), 1);
+
+        index.insert(OsString::from("c"), 5, 1);
+        assert_eq!(index.next_slot(), 3);
+        assert_eq!(index.replace(OsStr::new("c"), 6), Some((5, 1)));
+        assert_eq!(index.get(OsStr::new("c")), Some(6));
+        assert!(index.replace(OsStr::new("falta"), 7).is_none());
+
+        // Quitar las entradas del final deja el directorio más corto
+        assert_eq!(index.remove(OsStr::new("b")), Some(2));
+        assert_eq!(index.trim(), 2);
+        assert_eq!(index.remove(OsStr::new("a")), Some(0));
+        assert_eq!(index.trim(), 2);
+        assert_eq!(index.remove(OsStr::new("c")), Some(1));
+        assert_eq!((index.trim(), index.len(), index.next_slot()), (0, 0, 0));
+        assert!(index.remove(OsStr::new("c")).is_none());
+    }
+
+    #[test]
+    fn index_keeps_the_first_of_repeated_names() {
+        let mut index = DirectoryIndex::build(&content(&[
+            Some(entry(2, FileType::RegularFile, b"a")),
+            Some(entry(3, FileType::RegularFile, b"a"))
+        ])).unwrap();
+        assert_eq!((index.len(), index.get(OsStr::new("a"))), (1, Some(2)));
+
+        // La entrada repetida sigue ocupando su posición
+        assert_eq!(index.remove(OsStr::new("a")), Some(0));
+        assert_eq!((index.trim(), index.next_slot()), (2, 0));
+    }
+}
@@ -0,0 +1,272 @@
+use std::fs::File; // Liberia para el manejo de archivos
+use std::io; //Libreria para el manejo de entradas y salidas
+use std::os::unix::fs::FileExt; //Libreria para leer y escribir en posiciones fijas de un archivo
+use libc::{O_RDONLY, O_RDWR}; //Libreria para el manejo de banderas del codigo en C
+use crate::cifrado::SEAL_OVERHEAD; //Libreria propietaria del proyecto para el cifrado del disco
+use crate::directorio::{DiskDirectory, INODE_FILE, DATA_FILE}; //Libreria propietaria del proyecto para los archivos del disco
+use crate::mapa::{Bitmap, BITMAP_CHUNK_SIZE}; //Libreria propietaria del proyecto para los mapas de bits
+
+// Espacio reservado al inicio de `inode.qrfs` para el encabezado del superbloque
+pub const HEADER_REGION_SIZE: u64 = 16 * 1024;
+// Espacio reservado al inicio de `disco.qrfs` para su número mágico y versión
+pub const DATA_HEADER_SIZE: u64 = 4096;
+// Tamaño máximo de un inodo serializado
+pub const INODE_CAPACITY: usize = 4096;
+// Tamaño del prefijo con el largo del contenido cifrado de cada casilla. Un largo 0 indica una casilla vacía.
+const LENGTH_SIZE: usize = 4;
+// Tamaño de la casilla de cada inodo en `inode.qrfs`
+pub const INODE_SLOT_SIZE: usize = LENGTH_SIZE + SEAL_OVERHEAD + INODE_CAPACITY;
+// Tamaño de la casilla de cada parte de los mapas de bits en `inode.qrfs`
+const BITMAP_SLOT_SIZE: usize = LENGTH_SIZE + SEAL_OVERHEAD + BITMAP_CHUNK_SIZE;
+
+// Tramos escritos de un archivo de la imagen, cada uno con su posición
+pub type Extents = Vec<(u64, Vec<u8>)>;
+
+/// Mapa de bits guardado en `inode.qrfs`, después de las casillas de los inodos.
+#[derive(Debug, Clone, Copy)]
+pub enum BitmapKind {
+    Inodes,
+    Blocks
+}
+
+/// Posiciones fijas de los inodos y bloques dentro de `inode.qrfs` y `disco.qrfs`. Cada inodo y cada bloque tiene
+/// su propia casilla, así que se pueden leer y escribir por separado sin tocar el resto de la imagen. Al final de
+/// `inode.qrfs` están las casillas de los mapas de bits de inodos y de bloques en uso, una por cada parte del mapa.
+#[derive(Debug, Clone, Copy)]
+pub struct ImageLayout {
+    block_size: usize,
+    block_count: usize,
+    max_files: usize
+}
+
+impl ImageLayout {
+    pub fn new(block_size: usize, block_count: usize, max_files: usize) -> ImageLayout {
+        ImageLayout { block_size, block_count, max_files }
+    }
+
+    /// Tamaño de la casilla de cada bloque en `disco.qrfs`.
+    fn block_slot_size(&self) -> u64 {
+        (LENGTH_SIZE + SEAL_OVERHEAD + self.block_size) as u64
+    }
+
+    fn inode_offset(&self, index: usize) -> u64 {
+        HEADER_REGION_SIZE + (index * INODE_SLOT_SIZE) as u64
+    }
+
+    fn block_offset(&self, index: usize) -> u64 {
+        DATA_HEADER_SIZE + index as u64 * self.block_slot_size()
+    }
+
+    /// Cantidad de partes del mapa de bits `kind`.
+    pub fn bitmap_chunks(&self, kind: BitmapKind) -> usize {
+        let bits = match kind {
+            BitmapKind::Inodes => self.max_files,
+            BitmapKind::Blocks => self.block_count
+        };
+        bits.div_ceil(BITMAP_CHUNK_SIZE * 8)
+    }
+
+    fn bitmap_offset(&self, kind: BitmapKind, chunk: usize) -> u64 {
+        let first = match kind {
+            BitmapKind::Inodes => 0,
+            BitmapKind::Blocks => self.bitmap_chunks(BitmapKind::Inodes)
+        };
+        self.inode_offset(self.max_files) + ((first + chunk) * BITMAP_SLOT_SIZE) as u64
+    }
+
+    /// Tamaño completo de `inode.qrfs`.
+    fn inode_file_size(&self) -> u64 {
+        self.bitmap_offset(BitmapKind::Blocks, self.bitmap_chunks(BitmapKind::Blocks))
+    }
+
+    /// Tamaño completo de `disco.qrfs`.
+    fn data_file_size(&self) -> u64 {
+        self.block_offset(self.block_count)
+    }
+}
+
+/// Lee `buffer.len()` bytes desde `offset`. Lo que quede más allá del final del archivo se lee como ceros, igual que
+/// los huecos de un archivo disperso, así que una imagen sin los ceros finales sigue siendo válida.
+fn read_padded(file: &File, offset: u64, buffer: &mut [u8]) -> io::Result<()> {
+    let mut read = 0;
+    while read < buffer.len() {
+        match file.read_at(&mut buffer[read..], offset + read as u64) {
+            Ok(0) => break,
+            Ok(count) => read += count,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e)
+        }
+    }
+
+    buffer[read..].iter_mut().for_each(|byte| *byte = 0);
+    Ok(())
+}
+
+/// Archivos de la imagen abiertos para leer y escribir casillas en su lugar.
+#[derive(Debug)]
+pub struct ImageFiles {
+    inodes: File,
+    data: File,
+    layout: ImageLayout
+}
+
+impl ImageFiles {
+    /// Abre `inode.qrfs` y `disco.qrfs` del directorio `directory`.
+    pub fn open(directory: &DiskDirectory, layout: ImageLayout) -> io::Result<ImageFiles> {
+        Ok(ImageFiles {
+            inodes: directory.open_file(INODE_FILE, O_RDWR)?,
+            data: directory.open_file(DATA_FILE, O_RDWR)?,
+            layout
+        })
+    }
+
+    /// Abre `inode.qrfs` y `disco.qrfs` del directorio `directory` solo para leer, para revisar el disco sin
+    /// cambiarlo. Escribir una casilla con estos archivos falla.
+    pub fn open_read_only(directory: &DiskDirectory, layout: ImageLayout) -> io::Result<ImageFiles> {
+        Ok(ImageFiles {
+            inodes: directory.open_file(INODE_FILE, O_RDONLY)?,
+            data: directory.open_file(DATA_FILE, O_RDONLY)?,
+            layout
+        })
+    }
+
+    /// Usa los archivos `inodes` y `data`, recién creados, como imagen. Quedan con su tamaño completo, pero como
+    /// archivos dispersos: las casillas que nunca se escriben no ocupan espacio.
+    pub fn create(inodes: File, data: File, layout: ImageLayout) -> io::Result<ImageFiles> {
+        inodes.set_len(layout.inode_file_size())?;
+        data.set_len(layout.data_file_size())?;
+        Ok(ImageFiles { inodes, data, layout })
+    }
+
+    /// Lee el encabezado al inicio de `inode.qrfs`.
+    pub fn read_header(&self) -> io::Result<Vec<u8>> {
+        let mut header = vec![0; HEADER_REGION_SIZE as usize];
+        read_padded(&self.inodes, 0, &mut header)?;
+        Ok(header)
+    }
+
+    /// Escribe el encabezado al inicio de `inode.qrfs`.
+    pub fn write_header(&self, header: &[u8]) -> io::Result<()> {
+        if header.len() as u64 > HEADER_REGION_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "el encabezado no cabe en su espacio reservado"));
+        }
+
+        self.inodes.write_all_at(header, 0)
+    }
+
+    /// Lee el encabezado al inicio de `disco.qrfs`.
+    pub fn read_data_header(&self) -> io::Result<Vec<u8>> {
+        let mut header = vec![0; DATA_HEADER_SIZE as usize];
+        read_padded(&self.data, 0, &mut header)?;
+        Ok(header)
+    }
+
+    /// Escribe el encabezado al inicio de `disco.qrfs`.
+    pub fn write_data_header(&self, header: &[u8]) -> io::Result<()> {
+        self.data.write_all_at(header, 0)
+    }
+
+    /// Lee el contenido cifrado del inodo `index`, o `None` si su casilla está vacía.
+    pub fn read_inode(&self, index: usize) -> io::Result<Option<Vec<u8>>> {
+        read_slot(&self.inodes, self.layout.inode_offset(index), INODE_SLOT_SIZE)
+    }
+
+    /// Escribe el contenido cifrado del inodo `index`, o vacía su casilla con `None`.
+    pub fn write_inode(&self, index: usize, sealed: Option<&[u8]>) -> io::Result<()> {
+        write_slot(&self.inodes, self.layout.inode_offset(index), INODE_SLOT_SIZE, sealed)
+    }
+
+    /// Lee el contenido cifrado del bloque `index`, o `None` si su casilla está vacía.
+    pub fn read_block(&self, index: usize) -> io::Result<Option<Vec<u8>>> {
+        read_slot(&self.data, self.layout.block_offset(index), self.layout.block_slot_size() as usize)
+    }
+
+    /// Escribe el contenido cifrado del bloque `index`, o vacía su casilla con `None`.
+    pub fn write_block(&self, index: usize, sealed: Option<&[u8]>) -> io::Result<()> {
+        write_slot(&self.data, self.layout.block_offset(index), self.layout.block_slot_size() as usize, sealed)
+    }
+
+    /// Lee el contenido cifrado de la parte `chunk` del mapa de bits `kind`, o `None` si su casilla está vacía.
+    pub fn read_bitmap(&self, kind: BitmapKind, chunk: usize) -> io::Result<Option<Vec<u8>>> {
+        read_slot(&self.inodes, self.layout.bitmap_offset(kind, chunk), BITMAP_SLOT_SIZE)
+    }
+
+    /// Escribe el contenido cifrado de la parte `chunk` del mapa de bits `kind`.
+    pub fn write_bitmap(&self, kind: BitmapKind, chunk: usize, sealed: &[u8]) -> io::Result<()> {
+        write_slot(&self.inodes, self.layout.bitmap_offset(kind, chunk), BITMAP_SLOT_SIZE, Some(sealed))
+    }
+
+    /// Tramos escritos de `inode.qrfs` y `disco.qrfs`, cada uno con su posición: el encabezado sin sus ceros finales y
+    /// las casillas de los inodos y bloques marcados en `inodes_in_use` y `blocks_in_use`. Las casillas de los inodos y
+    /// bloques liberados pueden conservar su contenido anterior, que no se copia. Los mapas de bits tampoco se copian:
+    /// al cargar una imagen sin ellos se vuelven a armar a partir de los inodos. Escribiendo cada tramo en su posición
+    /// se vuelve a armar el archivo, sin los huecos de las casillas vacías.
+    pub fn extents(&self, inodes_in_use: &Bitmap, blocks_in_use: &Bitmap) -> io::Result<(Extents, Extents)> {
+        let mut inodes = vec![(0, trim_zeros(self.read_header()?))];
+        for index in (0..self.layout.max_files).filter(|index| inodes_in_use.is_set(*index)) {
+            if let Some(sealed) = self.read_inode(index)? {
+                inodes.push((self.layout.inode_offset(index), slot_bytes(&sealed)));
+            }
+        }
+
+        let mut data = vec![(0, trim_zeros(self.read_data_header()?))];
+        for index in (0..self.layout.block_count).filter(|index| blocks_in_use.is_set(*index)) {
+            if let Some(sealed) = self.read_block(index)? {
+                data.push((self.layout.block_offset(index), slot_bytes(&sealed)));
+            }
+        }
+
+        Ok((inodes, data))
+    }
+
+    /// Sincroniza los dos archivos con el dispositivo.
+    pub fn sync(&self) -> io::Result<()> {
+        self.inodes.sync_data()?;
+        self.data.sync_data()
+    }
+}
+
+/// Lee la casilla de `slot_size` bytes en `offset`: el largo del contenido seguido del contenido.
+fn read_slot(file: &File, offset: u64, slot_size: usize) -> io::Result<Option<Vec<u8>>> {
+    let mut length = [0; LENGTH_SIZE];
+    read_padded(file, offset, &mut length)?;
+
+    let length = u32::from_le_bytes(length) as usize;
+    if length == 0 {
+        return Ok(None);
+    }
+
+    if length > slot_size - LENGTH_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "el largo de una casilla excede su tamaño"));
+    }
+
+    let mut sealed = vec![0; length];
+    read_padded(file, offset + LENGTH_SIZE as u64, &mut sealed)?;
+    Ok(Some(sealed))
+}
+
+/// Escribe la casilla de `slot_size` bytes en `offset`. El largo y el contenido se escriben juntos; lo que quede de
+/// un contenido anterior más largo queda fuera del largo y no se vuelve a leer.
+fn write_slot(file: &File, offset: u64, slot_size: usize, sealed: Option<&[u8]>) -> io::Result<()> {
+    let sealed = sealed.unwrap_or(&[]);
+    if sealed.len() > slot_size - LENGTH_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "el contenido no cabe en su casilla"));
+    }
+
+    file.write_all_at(&slot_bytes(sealed), offset)
+}
+
+/// Bytes de una casilla con el contenido `sealed`: su largo seguido del contenido.
+fn slot_bytes(sealed: &[u8]) -> Vec<u8> {
+    let mut slot = Vec::with_capacity(LENGTH_SIZE + sealed.len());
+    slot.extend_from_slice(&(sealed.len() as u32).to_le_bytes());
+    slot.extend_from_slice(sealed);
+    slot
+}
+
+/// Quita los ceros finales de `content`.
+fn trim_zeros(mut content: Vec<u8>) -> Vec<u8> {
+    let length = content.iter().rposition(|byte| *byte != 0).map_or(0, |last| last + 1);
+    content.truncate(length);
+    content
+}
@@ -3,48 +3,182 @@ extern crate fuse;
 extern crate serde_big_array;
 mod persistencia;
 mod serialization;
+mod cifrado;
+mod directorio;
+mod bitacora;
+mod imagen;
+mod cache;
+mod mapa;
+mod entradas;
+mod permisos;
+mod atributos;
+#[cfg(test)]
+mod pruebas;
 
 // Libreria para el manejo del file system en el user space
-use fuse::{Filesystem, Request, ReplyCreate, ReplyEmpty, ReplyAttr, ReplyEntry, ReplyOpen, ReplyData, ReplyDirectory, ReplyWrite, FileType, FileAttr};
+use fuse::{Filesystem, Request, ReplyCreate, ReplyEmpty, ReplyAttr, ReplyEntry, ReplyOpen, ReplyData, ReplyDirectory, ReplyWrite, ReplyXattr, ReplyStatfs, FileType, FileAttr};
 // Libreria para el manejo de banderas del codigo en C
-use libc::{ENOSYS, ENOENT, EIO, EISDIR, ENOSPC};
+use libc::{ENOENT, EIO, EISDIR, ENOSPC, EEXIST, ENOTDIR, ENOTEMPTY, EINVAL, EPERM, EACCES, F_OK, W_OK, ENODATA, O_TRUNC};
 // Liberia para obtener el tiempo
 use time::{Timespec};
 // Libreria para obtener el ambiente de ejecucion
 use std::env;
-// Libreria para el manejo de memoria
-use std::mem;
 // Libreria para obtener el mejor manejo de strings de nuestro sistema operativo
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
+// Libreria para pasar los destinos de los enlaces simbólicos a bytes
+use std::os::unix::ffi::OsStrExt;
 // Libreria para la implementacion de nuestra persistencia
-use crate::persistencia::{Disk, Inode};
+use crate::persistencia::{Disk, DiskError, Inode};
+// Libreria para las entradas de los directorios
+use crate::entradas::{DirectoryEntry, NAME_CAPACITY};
+// Tamaño que ocupa cada inodo en la imagen
+use crate::imagen::INODE_SLOT_SIZE;
+// Libreria para los permisos y dueños de los archivos
+use crate::permisos::{Caller, change_mode, change_owner, check_add_entry, check_list, check_lookup, check_remove_entry, check_xattr_read, check_xattr_write, drop_set_id_on_write, has_access, lists_xattr, may_set_times, new_owner, open_mask, permissions_from_mode};
+// Libreria para los atributos extendidos
+use crate::atributos::{check_xattr_buffer, check_xattr_flags, check_xattr_size, xattr_namespace};
+// Libreria para las credenciales que abren el disco
+use crate::cifrado::{Credential, Key, KeyShare};
+// Libreria para los atributos extendidos de los inodos nuevos
+use std::collections::BTreeMap;
+// Libreria para verificar si un archivo existe
+use std::path::Path;
 use std::io::{stdin,stdout,Write};
+// Libreria para leer los archivos con las partes de la llave
+use std::fs;
+
+// Geometría por defecto del disco
+const DEFAULT_MEMORY_SIZE: usize = 1024 * 1024 * 1024;
+const DEFAULT_BLOCK_SIZE: usize = 4096;
+const DEFAULT_MAX_FILES: usize = 1024;
+// Límites aceptados para el tamaño de bloque. Con bloques más chicos que el mínimo el mapa de bloques de un inodo
+// alcanza para muy poco: con 64 bytes los archivos no pasan de 12 KiB.
+const MIN_BLOCK_SIZE: usize = 512;
+const MAX_BLOCK_SIZE: usize = 16 * 1024 * 1024;
+
+/// Geometría del disco elegida con las opciones de mkfs_qrfs
+struct Geometry {
+    memory_size: usize,
+    block_size: usize,
+    max_files: usize
+}
+
+impl Geometry {
+    /// Lee la geometría de las opciones `--size`, `--block-size` e `--inodes`. Las que no se indiquen toman el
+    /// valor por defecto. Los tamaños aceptan los sufijos K, M y G.
+    fn from_args(args: &[String]) -> Result<Geometry, String> {
+        let mut geometry = Geometry {
+            memory_size: DEFAULT_MEMORY_SIZE,
+            block_size: DEFAULT_BLOCK_SIZE,
+            max_files: DEFAULT_MAX_FILES
+        };
+
+        let mut args = args.iter();
+        while let Some(option) = args.next() {
+            let value = match args.next() {
+                Some(value) => value,
+                None => return Err(format!("Falta el valor de la opción {}", option))
+            };
+            let parsed = parse_size(value).ok_or(format!("Valor inválido para {}: {}", option, value))?;
+
+            match option.as_str() {
+                "--size" => geometry.memory_size = parsed,
+                "--block-size" => geometry.block_size = parsed,
+                "--inodes" => geometry.max_files = parsed,
+                _ => return Err(format!("Opción desconocida: {}", option))
+            }
+        }
+
+        Ok(geometry)
+    }
+
+    /// Tamaño reservado para la tabla de inodos, redondeado a bloques completos. Devuelve `None` si no se puede
+    /// representar.
+    fn inode_table_size(&self) -> Option<usize> {
+        self.max_files.checked_mul(INODE_SLOT_SIZE)?.div_ceil(self.block_size).checked_mul(self.block_size)
+    }
+
+    /// Cantidad de bloques de datos que quedan después de reservar la tabla de inodos.
+    fn block_count(&self) -> usize {
+        match self.inode_table_size() {
+            Some(table_size) => self.memory_size.saturating_sub(table_size) / self.block_size,
+            None => 0
+        }
+    }
+
+    /// Verifica que el tamaño total, el tamaño de bloque y la cantidad de inodos sean compatibles entre sí.
+    fn validate(&self) -> Result<(), String> {
+        if !self.block_size.is_power_of_two() || self.block_size < MIN_BLOCK_SIZE || self.block_size > MAX_BLOCK_SIZE {
+            return Err(format!("El tamaño de bloque debe ser una potencia de 2 entre {} y {} bytes", MIN_BLOCK_SIZE, MAX_BLOCK_SIZE));
+        }
+
+        if self.max_files < 1 {
+            return Err(String::from("Se necesita al menos un inodo para el directorio raíz"));
+        }
+
+        if self.block_size > self.memory_size {
+            return Err(String::from("El tamaño de bloque no puede ser mayor que el tamaño del disco"));
+        }
+
+        let table_size = match self.inode_table_size() {
+            Some(table_size) => table_size,
+            None => return Err(format!("La tabla de {} inodos es demasiado grande", self.max_files))
+        };
+
+        if self.block_count() < 1 {
+            return Err(format!(
+                "Un disco de {} bytes no alcanza para la tabla de {} inodos ({} bytes) y al menos un bloque de datos",
+                self.memory_size, self.max_files, table_size
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Interpreta un tamaño como `4096`, `64K`, `16M` o `1G`.
+fn parse_size(value: &str) -> Option<usize> {
+    let (number, multiplier) = match value.chars().last()? {
+        'K' | 'k' => (&value[..value.len() - 1], 1024),
+        'M' | 'm' => (&value[..value.len() - 1], 1024 * 1024),
+        'G' | 'g' => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (value, 1)
+    };
+
+    number.parse::<usize>().ok()?.checked_mul(multiplier)
+}
+
+/// Indica si en `root_path` ya hay un disco: su tabla de inodos y su archivo de datos.
+fn disk_exists(root_path: &str) -> bool {
+    Path::new(&format!("{}/disco.qrfs", root_path)).exists() && Path::new(&format!("{}/inode.qrfs", root_path)).exists()
+}
 
 struct QrFS {
     disk: Disk
 }
 
 impl QrFS {
-    /// Inicializa FS con el tamaño especificado en `memory_size` con bloques de memoria de tamaño    
-    /// `block_size`.
-    fn new(root_path: String, phrase: String) -> Self {
-        let max_files: usize = 1024;
-        let memory_size: usize = 1024 * 1024 * 1024;
-        let block_size: usize = max_files * (mem::size_of::<Box<[Inode]>>() + mem::size_of::<Inode>());
-
-        let disk = Disk::new(root_path, memory_size, block_size, phrase);
+    /// Inicializa FS con la geometría `geometry`. Si ya existe un disco en `root_path` se carga con la geometría de
+    /// su encabezado.
+    fn new(root_path: String, credential: Credential, geometry: &Geometry) -> Result<Self, DiskError> {
+        let disk = if disk_exists(&root_path) {
+            Disk::new(root_path, credential)?
+        } else {
+            Disk::create(root_path, geometry.block_size, geometry.block_count(), geometry.max_files, credential)?
+        };
 
-        QrFS {
+        Ok(QrFS {
             disk
-        }
+        })
     }
 }
-#[allow(unused_must_use)]
 impl Drop for QrFS {
     fn drop(&mut self) {
         println!("\nsaving content...");
-        &self.disk.write_to_disk();
-        println!("success!");
+        match self.disk.write_to_disk() {
+            Ok(()) => println!("success!"),
+            Err(e) => println!("¡Error al guardar el disco! {}", e)
+        }
     }
 }
 
@@ -52,28 +186,38 @@ impl Drop for QrFS {
 impl Filesystem for QrFS {
     fn lookup(
         &mut self, 
-        _req: &Request, 
+        req: &Request, 
         parent: u64, 
         name: &OsStr, 
         reply: ReplyEntry
     ) {
         println!("lookup(parent={:?}, name={:?})", parent, name);
-        let file_name = name.to_str().unwrap();
-        let inode = self.disk.find_inode_in_references_by_name(parent, file_name);
+
+        // Buscar un nombre necesita permiso de búsqueda sobre el directorio
+        if let Err(errno) = check_lookup(&Caller::of(req), self.disk.get_inode(parent).map(|inode| &inode.attributes)) {
+            reply.error(errno);
+            return;
+        }
+
+        let inode = self.disk.find_inode_in_references_by_name(parent, name);
 
         match inode {
-            Some(inode) => {
+            Ok(Some(inode)) => {
                 let ttl = time::now().to_timespec();
                 println!("        - lookup(parent={:?}, attr={:?})", parent, inode.attributes);
                 reply.entry(&ttl, &inode.attributes, 0)
             },
-            None => reply.error(ENOENT) // “No such file or directory.”
+            Ok(None) => reply.error(ENOENT), // “No such file or directory.”
+            Err(e) => {
+                println!("¡Error al buscar la entrada! {}", e);
+                reply.error(e.errno())
+            }
         }
     }
     //Implementacion para cuando se ejecute un create en el filesystem
     fn create(
         &mut self, 
-        _req: &Request, 
+        req: &Request, 
         parent: u64, 
         name: &OsStr, 
         mode: u32, 
@@ -82,66 +226,96 @@ impl Filesystem for QrFS {
     ) {
         println!("create(name={:?}, mode={}, flags={})", name, mode, flags);
 
-        let ref_index = self.disk.find_index_of_empty_reference_in_inode(parent);
-        // Si no hay más espacio en el vector de referencias, indica que no es posible ubicar más archivos dentro de la carpeta        
-        if ref_index == None {
-            println!("¡No se pueden crear más archivos en este directorio!");
-            reply.error(EIO); // “Input/output error.”
+        match self.disk.find_inode_in_references_by_name(parent, name) {
+            Ok(Some(_)) => {
+                reply.error(EEXIST); // “File exists.”
+                return;
+            },
+            Ok(None) => (),
+            Err(e) => {
+                println!("¡Error al buscar la entrada! {}", e);
+                reply.error(e.errno());
+                return;
+            }
+        }
+
+        // Agregar una entrada necesita permisos de escritura y de búsqueda sobre el directorio
+        let caller = Caller::of(req);
+        if let Err(errno) = check_add_entry(&caller, self.disk.get_inode(parent).map(|inode| &inode.attributes)) {
+            reply.error(errno);
             return;
         }
 
         let ino_available = self.disk.find_ino_available();
-        let memory_block_index = self.disk.find_index_of_empty_memory_block();
 
-        if ino_available == None || memory_block_index == None {
+        if ino_available.is_none() {
             reply.error(ENOSPC); // “No hay mas espacio en el dispositivo.”
             return;
         }
 
         let ino_available = ino_available.unwrap();
-        let memory_block_index = memory_block_index.unwrap();
+
+        let parent_attributes = match self.disk.get_inode(parent) {
+            Some(inode) => inode.attributes,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        let (uid, gid, perm) = new_owner(&caller, &parent_attributes, FileType::RegularFile, permissions_from_mode(mode));
 
         let ts = time::now().to_timespec();
 
         let attr = FileAttr {
             ino: ino_available,
             size: 0,
-            blocks: 1,
+            blocks: 0,
             atime: ts,
             mtime: ts,
             ctime: ts,
             crtime: ts,
             kind: FileType::RegularFile,
-            perm: 0o755,
-            nlink: 0,
-            uid: 0,
-            gid: 0,
+            perm,
+            nlink: 1,
+            uid,
+            gid,
             rdev: 0,
             flags,
         };
-        
-        let name = name.to_str().unwrap();
-        let name: Vec<char> = name.chars().collect();
 
-        let mut name_char = ['\0'; 64];
-        name_char[..name.len()].clone_from_slice(&name);
-
-        let mut inode = Inode {
-            name: name_char,
+        // Los bloques de datos se reservan a medida que se escribe en el archivo
+        let inode = Inode {
             attributes: attr,
-            references: [None; 128]
+            references: [None; 128],
+            target: None,
+            xattrs: BTreeMap::new()
         };
 
-        inode.references[0] = Some(memory_block_index);
+        // Agrega la entrada del archivo nuevo al directorio "principal", que crece si hace falta
+        let entry = DirectoryEntry { ino: ino_available, kind: FileType::RegularFile, name: name.to_os_string() };
+        if let Err(e) = self.disk.add_directory_entry(parent, entry) {
+            println!("¡No se pudo agregar el archivo al directorio! {}", e);
+            self.disk.abort_transaction();
+            reply.error(e.errno());
+            return;
+        }
 
-        self.disk.write_inode(inode);
-        let content: Box<[u8]> = Box::default();
-        self.disk.write_content_as_bytes(memory_block_index, content);
+        if let Err(e) = self.disk.write_inode(inode) {
+            println!("¡No se pudo guardar el inodo del archivo! {}", e);
+            self.disk.abort_transaction();
+            reply.error(e.errno());
+            return;
+        }
 
-        // Agregua la referencia del inodo creada en las referencias vectoriales al inodo "principal" (desde el directorio)
-        let ref_index = ref_index.unwrap();
-        self.disk.write_reference_in_inode(parent, ref_index, ino_available as usize);
+        if let Err(e) = self.disk.commit_transaction() {
+            println!("¡Error al registrar la operación en la bitácora! {}", e);
+            self.disk.abort_transaction();
+            reply.error(EIO);
+            return;
+        }
 
+        // El archivo queda abierto hasta que el núcleo lo cierre con release
+        self.disk.open_handle(ino_available);
         reply.created(&ts, &attr, 1, ino_available, flags)
     }
     // Esta funcion se encarga de sincronizar un archivo en el estado del núcleo con el dispositivo de almacenamiento
@@ -154,20 +328,45 @@ impl Filesystem for QrFS {
         reply: ReplyEmpty
     ) { 
         println!("fsync(ino={}, fh={}, datasync={})", ino, fh, datasync);
-        reply.error(ENOSYS);
+        // Los cambios del archivo ya están registrados en la bitácora, basta con sincronizarla
+        match self.disk.sync() {
+            Ok(()) => reply.ok(),
+            Err(e) => {
+                println!("¡Error al sincronizar el disco! {}", e);
+                reply.error(EIO);
+            }
+        }
+    }
+    // Esta funcion se encarga de sincronizar el contenido de un directorio con el dispositivo de almacenamiento
+    fn fsyncdir(
+        &mut self, 
+        _req: &Request, 
+        ino: u64, 
+        fh: u64, 
+        datasync: bool, 
+        reply: ReplyEmpty
+    ) { 
+        println!("fsyncdir(ino={}, fh={}, datasync={})", ino, fh, datasync);
+        match self.disk.sync() {
+            Ok(()) => reply.ok(),
+            Err(e) => {
+                println!("¡Error al sincronizar el disco! {}", e);
+                reply.error(EIO);
+            }
+        }
     }
     // Funcion se encarga de aplicar los atributos a un archivo
     fn setattr(
         &mut self, 
-        _req: &Request, 
+        req: &Request, 
         ino: u64, 
-        _mode: Option<u32>, 
+        mode: Option<u32>, 
         uid: Option<u32>, 
         gid: Option<u32>, 
         size: Option<u64>, 
         atime: Option<Timespec>, 
         mtime: Option<Timespec>, 
-        _fh: Option<u64>, 
+        fh: Option<u64>, 
         crtime: Option<Timespec>, 
         _chgtime: Option<Timespec>, 
         _bkuptime: Option<Timespec>, 
@@ -175,21 +374,90 @@ impl Filesystem for QrFS {
         reply: ReplyAttr
     ) {
         println!("setattr(ino={})", ino);
+        let mut attributes = match self.disk.get_inode(ino) {
+            Some(inode) => inode.attributes,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+
+        // Los permisos se revisan antes de cambiar nada, así un pedido rechazado no deja cambios a medias
+        let caller = Caller::of(req);
+        if let Some(mode) = mode {
+            if !change_mode(&caller, &mut attributes, mode) {
+                reply.error(EPERM); // “Operation not permitted.”
+                return;
+            }
+        }
+        if (uid.is_some() || gid.is_some()) && !change_owner(&caller, &mut attributes, uid, gid) {
+            reply.error(EPERM);
+            return;
+        }
+        if (atime.is_some() || mtime.is_some() || crtime.is_some()) && !may_set_times(&caller, &attributes) {
+            reply.error(EPERM);
+            return;
+        }
+        // Con un handle abierto el permiso de escritura ya se revisó al abrir el archivo, como en ftruncate
+        if size.is_some() && fh.is_none() && !has_access(&caller, &attributes, W_OK) {
+            reply.error(EACCES); // “Permission denied.”
+            return;
+        }
+
+        // El cambio de tamaño de un archivo regular pasa por su mapa de bloques: al achicarlo se liberan los bloques que
+        // quedan afuera y al agrandarlo la parte nueva queda como un hueco que se lee como ceros
+        if let Some(size) = size {
+            match attributes.kind {
+                FileType::RegularFile => (),
+                FileType::Directory => {
+                    reply.error(EISDIR); // “Is a directory.”
+                    return;
+                },
+                _ => {
+                    reply.error(EINVAL); // “Invalid argument.”
+                    return;
+                }
+            }
+
+            if let Err(e) = self.disk.truncate_file(ino, size) {
+                println!("¡Error al cambiar el tamaño del archivo! {}", e);
+                self.disk.abort_transaction();
+                reply.error(e.errno());
+                return;
+            }
+
+            if let Some(inode) = self.disk.get_inode(ino) {
+                attributes.size = inode.attributes.size;
+                attributes.blocks = inode.attributes.blocks;
+                attributes.mtime = inode.attributes.mtime;
+                attributes.ctime = inode.attributes.ctime;
+            }
+            drop_set_id_on_write(&caller, &mut attributes);
+        }
+
         let inode = self.disk.get_inode_as_mut(ino);
         
         match inode {
             Some(inode) => {
-                if let Some(size) = size { inode.attributes.size = size; }
-                if let Some(atime) = atime { inode.attributes.atime = atime; }
-                if let Some(mtime) = mtime { inode.attributes.mtime = mtime; }
-                if let Some(crtime) = crtime { inode.attributes.crtime = crtime; }
-                if let Some(gid) = gid { inode.attributes.gid = gid; }
-                if let Some(uid) = uid { inode.attributes.uid = uid; }
-                if let Some(flags) = flags { inode.attributes.flags = flags; }
+                if let Some(atime) = atime { attributes.atime = atime; }
+                if let Some(mtime) = mtime { attributes.mtime = mtime; }
+                if let Some(crtime) = crtime { attributes.crtime = crtime; }
+                if let Some(flags) = flags { attributes.flags = flags; }
+                if mode.is_some() || uid.is_some() || gid.is_some() {
+                    attributes.ctime = time::now().to_timespec();
+                }
+                inode.attributes = attributes;
+
+                if let Err(e) = self.disk.commit_transaction() {
+                    println!("¡Error al registrar la operación en la bitácora! {}", e);
+                    self.disk.abort_transaction();
+                    reply.error(EIO);
+                    return;
+                }
 
                 let ttl = time::now().to_timespec();
 
-                reply.attr(&ttl, &inode.attributes)
+                reply.attr(&ttl, &attributes)
             },
             None => reply.error(ENOENT)
         }
@@ -211,90 +479,197 @@ impl Filesystem for QrFS {
             None => reply.error(ENOENT)
         }
     }
+    // Esta funcion se encarga de revisar si quien pregunta tiene los permisos pedidos sobre un archivo
+    fn access(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        mask: u32,
+        reply: ReplyEmpty
+    ) {
+        println!("access(ino={}, mask={})", ino, mask);
+
+        match self.disk.get_inode(ino) {
+            Some(_) if mask as i32 == F_OK => reply.ok(),
+            Some(inode) if has_access(&Caller::of(req), &inode.attributes, mask as i32) => reply.ok(),
+            Some(_) => reply.error(EACCES), // “Permission denied.”
+            None => reply.error(ENOENT)
+        }
+    }
+    // Esta funcion se encarga de informar la ocupación del disco, como la muestra `df`
+    fn statfs(
+        &mut self,
+        _req: &Request,
+        _ino: u64,
+        reply: ReplyStatfs
+    ) {
+        println!("statfs()");
+
+        let usage = self.disk.usage();
+        reply.statfs(
+            usage.total_blocks as u64,
+            usage.free_blocks as u64,
+            usage.free_blocks as u64,
+            usage.total_inodes as u64,
+            usage.free_inodes as u64,
+            usage.block_size as u32,
+            NAME_CAPACITY as u32,
+            usage.block_size as u32
+        );
+    }
     // Esta funcion se encarga de crear un directorio nuevo
     fn mkdir(
         &mut self, 
-        _req: &Request, 
+        req: &Request, 
         parent: u64, 
         name: &OsStr, 
-        _mode: u32, 
+        mode: u32, 
         reply: ReplyEntry
     ) {
-        let reference_index = self.disk.find_index_of_empty_reference_in_inode(parent);
-        
-        match reference_index {
-            Some(reference_index) => {
-
-                let ino = self.disk.find_ino_available();
-                match ino {
-                    Some(ino) => {
-                        let ts = time::now().to_timespec();
-                        let attr = FileAttr {
-                            ino: ino as u64,
-                            size: 0,
-                            blocks: 1,
-                            atime: ts,
-                            mtime: ts,
-                            ctime: ts,
-                            crtime: ts,
-                            kind: FileType::Directory,
-                            perm: 0o755,
-                            nlink: 0,
-                            uid: 0,
-                            gid: 0,
-                            rdev: 0,
-                            flags: 0,
-                        };
-
-                        let name = name.to_str().unwrap();
-                        let name: Vec<char> = name.chars().collect();
-
-                        let mut name_char = ['\0'; 64];
-                        name_char[..name.len()].clone_from_slice(&name);
-
-                        let inode = Inode {
-                            name: name_char,
-                            attributes: attr,
-                            references: [None; 128]
-                        };
-
-                        self.disk.write_inode(inode);
-                        self.disk.write_reference_in_inode(parent, reference_index, ino as usize);
-
-                        reply.entry(&ts, &attr, 0);
-                    },
-                    None => reply.error(ENOSPC) // “No space left on device.”
+        match self.disk.find_inode_in_references_by_name(parent, name) {
+            Ok(Some(_)) => {
+                reply.error(EEXIST); // “File exists.”
+                return;
+            },
+            Ok(None) => (),
+            Err(e) => {
+                println!("¡Error al buscar la entrada! {}", e);
+                reply.error(e.errno());
+                return;
+            }
+        }
+
+        // Agregar una entrada necesita permisos de escritura y de búsqueda sobre el directorio
+        let caller = Caller::of(req);
+        if let Err(errno) = check_add_entry(&caller, self.disk.get_inode(parent).map(|inode| &inode.attributes)) {
+            reply.error(errno);
+            return;
+        }
+
+        let parent_attributes = match self.disk.get_inode(parent) {
+            Some(inode) => inode.attributes,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        let (uid, gid, perm) = new_owner(&caller, &parent_attributes, FileType::Directory, permissions_from_mode(mode));
+
+        let ino = self.disk.find_ino_available();
+        match ino {
+            Some(ino) => {
+                let ts = time::now().to_timespec();
+                let attr = FileAttr {
+                    ino,
+                    size: 0,
+                    blocks: 0,
+                    atime: ts,
+                    mtime: ts,
+                    ctime: ts,
+                    crtime: ts,
+                    kind: FileType::Directory,
+                    perm,
+                    nlink: 2,
+                    uid,
+                    gid,
+                    rdev: 0,
+                    flags: 0,
+                };
+
+                // Las entradas del directorio nuevo se guardan en sus bloques de datos, que se reservan a medida que crece
+                let inode = Inode {
+                    attributes: attr,
+                    references: [None; 128],
+                    target: None,
+                    xattrs: BTreeMap::new()
+                };
+
+                let entry = DirectoryEntry { ino, kind: FileType::Directory, name: name.to_os_string() };
+                if let Err(e) = self.disk.add_directory_entry(parent, entry) {
+                    println!("¡No se pudo agregar el directorio a su directorio padre! {}", e);
+                    self.disk.abort_transaction();
+                    reply.error(e.errno());
+                    return;
+                }
+
+                if let Err(e) = self.disk.write_inode(inode) {
+                    println!("¡No se pudo guardar el inodo del directorio! {}", e);
+                    self.disk.abort_transaction();
+                    reply.error(e.errno());
+                    return;
+                }
+                // La entrada ".." del directorio nuevo es un enlace más de su directorio padre
+                self.disk.increase_links(parent);
+
+                if let Err(e) = self.disk.commit_transaction() {
+                    println!("¡Error al registrar la operación en la bitácora! {}", e);
+                    self.disk.abort_transaction();
+                    reply.error(EIO);
+                    return;
                 }
+
+                reply.entry(&ts, &attr, 0);
             },
-            None => { println!("¡Límite de archivos dentro de la carpeta alcanzado!"); reply.error(EIO); }
+            None => reply.error(ENOSPC) // “No space left on device.”
         }
     }
     // Esta funcion se encarga de eliminar un directorio
     fn rmdir(
         &mut self, 
-        _req: &Request, 
+        req: &Request, 
         parent: u64, 
         name: &OsStr, 
         reply: ReplyEmpty
     ) {
-        let name = name.to_str().unwrap();
         let inode = self.disk.find_inode_in_references_by_name(parent, name);
-        // Eliminamos el directorio de la tabla de inodos
+        // Eliminamos el directorio de su directorio padre y de la tabla de inodos, junto con los bloques de sus entradas
         match inode {
-            Some(inode) => {
+            Ok(Some(inode)) => {
+                if inode.attributes.kind != FileType::Directory {
+                    reply.error(ENOTDIR);
+                    return;
+                }
+
+                // Quitar la entrada necesita permisos de escritura y de búsqueda sobre el directorio, y en un directorio
+                // con el bit sticky ser dueño del directorio o del archivo
+                let parent_attributes = self.disk.get_inode(parent).map(|inode| &inode.attributes);
+                if let Err(errno) = check_remove_entry(&Caller::of(req), parent_attributes, &inode.attributes) {
+                    reply.error(errno);
+                    return;
+                }
+
                 let ino = inode.attributes.ino;
-                self.disk.clear_reference_in_inode(parent, ino as usize);
-                self.disk.clear_inode(ino);
+                let result = self.disk.directory_entry_count(ino).and_then(|count| match count {
+                    0 => {
+                        self.disk.remove_directory_entry(parent, name)?;
+                        self.disk.decrease_links(parent)?;
+                        self.disk.free_inode(ino)?;
+                        self.disk.commit_transaction().map(|_| true)
+                    },
+                    _ => Ok(false)
+                });
 
-                reply.ok();
+                match result {
+                    Ok(true) => reply.ok(),
+                    Ok(false) => reply.error(ENOTEMPTY),
+                    Err(e) => {
+                        println!("¡Error al eliminar el directorio! {}", e);
+                        self.disk.abort_transaction();
+                        reply.error(e.errno());
+                    }
+                }
             },
-            None => reply.error(EIO) // "Input/output error."
+            Ok(None) => reply.error(ENOENT),
+            Err(e) => {
+                println!("¡Error al leer el disco! {}", e);
+                reply.error(e.errno())
+            }
         }
     }
     // Esta funcion se encarga de abrir un archivo o directorio
     fn open(
         &mut self,
-        _req: &Request,
+        req: &Request,
         ino: u64,
         flags: u32,
         reply: ReplyOpen
@@ -302,10 +677,58 @@ impl Filesystem for QrFS {
         println!("open(ino={}, flags={})", ino, flags);
         // Buscamos el inodo 
         let inode = self.disk.get_inode(ino);
+        let caller = Caller::of(req);
 
         match inode {
-            Some(_) => reply.opened(ino, flags),
-            None => reply.error(ENOSYS)
+            Some(inode) if !has_access(&caller, &inode.attributes, open_mask(flags)) => {
+                reply.error(EACCES); // “Permission denied.”
+            },
+            Some(inode) if flags & O_TRUNC as u32 != 0 && inode.attributes.kind == FileType::RegularFile => {
+                // Abrir con O_TRUNC deja el archivo vacío, y como toda escritura le quita los bits set-user-ID y
+                // set-group-ID
+                let truncated = self.disk.truncate_file(ino, 0).and_then(|_| {
+                    if let Some(inode) = self.disk.get_inode_as_mut(ino) {
+                        drop_set_id_on_write(&caller, &mut inode.attributes);
+                    }
+                    self.disk.commit_transaction()
+                });
+                if let Err(e) = truncated {
+                    println!("¡Error al truncar el archivo! {}", e);
+                    self.disk.abort_transaction();
+                    reply.error(e.errno());
+                    return;
+                }
+                self.disk.open_handle(ino);
+                reply.opened(ino, flags)
+            },
+            Some(_) => {
+                self.disk.open_handle(ino);
+                reply.opened(ino, flags)
+            },
+            None => reply.error(ENOENT)
+        }
+    }
+    // Esta funcion se encarga de cerrar un archivo. Un archivo borrado mientras estaba abierto se libera al cerrar su
+    // último handle
+    fn release(
+        &mut self, 
+        _req: &Request, 
+        ino: u64, 
+        fh: u64, 
+        _flags: u32, 
+        _lock_owner: u64, 
+        _flush: bool, 
+        reply: ReplyEmpty
+    ) {
+        println!("release(ino={}, fh={})", ino, fh);
+        let released = self.disk.release_handle(ino).and_then(|_| self.disk.commit_transaction());
+        match released {
+            Ok(()) => reply.ok(),
+            Err(e) => {
+                println!("¡Error al liberar el archivo! {}", e);
+                self.disk.abort_transaction();
+                reply.error(e.errno());
+            }
         }
     }
     // Esta funcion se encarga de leer un archivo 
@@ -320,17 +743,37 @@ impl Filesystem for QrFS {
     ) {
         println!("read(ino={}, fh={}, offset={}, size={})", ino, fh, offset, size);
 
-        let memory_block = self.disk.get_content_as_bytes(ino as usize);
+        let offset = offset.max(0) as u64;
+        let content = self.disk.read_file(ino, offset, size);
         
-        match memory_block {
-            Some(memory_block) => reply.data(memory_block),
-            None => reply.error(EIO)
+        match content {
+            Ok(Some(content)) => reply.data(&content),
+            Ok(None) => reply.error(ENOENT),
+            Err(e) => {
+                println!("¡Error al leer el disco! {}", e);
+                reply.error(EIO)
+            }
+        }
+    }
+    // Esta funcion se encarga de abrir un directorio para listarlo, lo que necesita permiso de lectura
+    fn opendir(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        flags: u32,
+        reply: ReplyOpen
+    ) {
+        println!("opendir(ino={}, flags={})", ino, flags);
+
+        match check_list(&Caller::of(req), self.disk.get_inode(ino).map(|inode| &inode.attributes)) {
+            Ok(()) => reply.opened(ino, flags),
+            Err(errno) => reply.error(errno)
         }
     }
     // Esta funcion se encarga de leer un directorio
     fn readdir(
         &mut self, 
-        _req: &Request, 
+        req: &Request, 
         ino: u64, 
         fh: u64, 
         offset: i64, 
@@ -338,56 +781,45 @@ impl Filesystem for QrFS {
     ) {
         println!("readdir(ino={}, fh={}, offset={})", ino, fh, offset);
 
-        // Pequeño "ajuste técnico" para mostrar el "." y ".." en la primera carpeta.
-        if ino == 1 {
-            if offset == 0 {
-                reply.add(1, 0, FileType::Directory, ".");
-                reply.add(1, 1, FileType::Directory, "..");
-            }
+        // Listar las entradas necesita permiso de lectura, como al abrir el directorio con opendir
+        if let Err(errno) = check_list(&Caller::of(req), self.disk.get_inode(ino).map(|inode| &inode.attributes)) {
+            reply.error(errno);
+            return;
         }
 
-        // Inodo "padre" (el directorio)
-        let inode: Option<&Inode> = self.disk.get_inode(ino);
+        let entries = match self.disk.directory_entries(ino) {
+            Ok(Some(entries)) => entries,
+            Ok(None) => { println!("ERROR ino={:?}", ino); reply.error(ENOENT); return; },
+            Err(e) => {
+                println!("¡Error al leer el disco! {}", e);
+                reply.error(e.errno());
+                return;
+            }
+        };
 
-        // Desplazamiento representado como el tamaño entero del Inodo, ya que todo el contenido se leerá a la vez
-        // del directorio. Si el desplazamiento es el mismo que el tamaño del inodo principal, haga clic en "Aceptar" y devuelva el contenido.
-        if mem::size_of_val(&inode) == offset as usize {
-            reply.ok();
-            return;
+        // Pequeño "ajuste técnico" para mostrar el "." y ".." en la primera carpeta.
+        let mut listing = Vec::with_capacity(entries.len() + 2);
+        if ino == 1 {
+            listing.push((1, FileType::Directory, OsString::from(".")));
+            listing.push((1, FileType::Directory, OsString::from("..")));
         }
+        listing.extend(entries.into_iter().map(|entry| (entry.ino, entry.kind, entry.name)));
 
-        match inode {
-            Some(inode) => {
-                let references = inode.references;
-                // Ciclo a través del vector de referencia del inodo padre. Cada posición indica un archivo que está presente
-                // en el directorio.
-                for ino in references.iter() {
-
-                    if let Some(ino) = ino {
-                        let inode = self.disk.get_inode(*ino as u64);
-
-                        if let Some(inode_data) = inode {
-                            if inode_data.attributes.ino == 1 {
-                                continue;
-                            }
-
-                            let name = inode_data.name.iter().collect::<String>();
-                            println!("    - readdir(ino={}, name={})", inode_data.attributes.ino, name);
-                            let offset = mem::size_of_val(&inode) as i64;
-                            reply.add(inode_data.attributes.ino, offset, inode_data.attributes.kind, name);
-                        }
-                    }
-                }
-
-                reply.ok()
-            },
-            None => { println!("ERROR ino={:?}", ino); reply.error(ENOENT) }
+        // El desplazamiento de cada entrada es su posición en la lista más uno, para que la siguiente llamada continúe
+        // después de la última entrada que entró en la respuesta
+        for (position, (entry_ino, kind, name)) in listing.iter().enumerate().skip(offset.max(0) as usize) {
+            println!("    - readdir(ino={}, name={:?})", entry_ino, name);
+            if reply.add(*entry_ino, (position + 1) as i64, *kind, name) {
+                break;
+            }
         }
+
+        reply.ok()
     }
     // Esta funcion se encarga de escribir datos
     fn write(
         &mut self, 
-        _req: &Request, 
+        req: &Request, 
         ino: u64, 
         _fh: u64, 
         offset: i64, 
@@ -396,15 +828,37 @@ impl Filesystem for QrFS {
         reply: ReplyWrite
     ) {
         println!("write(ino={}, offset={}, data={})", ino, offset, data.len());
-        let inode = self.disk.get_inode_as_mut(ino);
-        let content: Box<[u8]> = data.to_vec().into_boxed_slice();
-        // Buscamos el inode y le empezamos a escribir los bytes al disko
+        let inode = self.disk.get_inode(ino);
+        // Buscamos el inode y le escribimos los bytes al disko a partir de `offset`, bloque por bloque
         match inode {
-            Some(inode) => {
-                inode.attributes.size = data.len() as u64;
-                let index = (ino as usize) - 1;
-                self.disk.write_content_as_bytes(index, content);
-                reply.written(data.len() as u32);
+            Some(_) => {
+                let written = match self.disk.write_file(ino, offset.max(0) as u64, data) {
+                    Ok(written) => written,
+                    Err(e) => {
+                        println!("¡Error al escribir el archivo! {}", e);
+                        self.disk.abort_transaction();
+                        reply.error(e.errno());
+                        return;
+                    }
+                };
+                // Una escritura de alguien que no es root le quita al archivo los bits set-user-ID y set-group-ID
+                if written > 0 {
+                    if let Some(inode) = self.disk.get_inode_as_mut(ino) {
+                        drop_set_id_on_write(&Caller::of(req), &mut inode.attributes);
+                    }
+                }
+                if let Err(e) = self.disk.commit_transaction() {
+                    println!("¡Error al registrar la operación en la bitácora! {}", e);
+                    self.disk.abort_transaction();
+                    reply.error(EIO);
+                    return;
+                }
+
+                if written == 0 && !data.is_empty() {
+                    reply.error(ENOSPC);
+                } else {
+                    reply.written(written as u32);
+                }
             },
             None => {
                 println!("Nodo no encontrado");
@@ -415,50 +869,967 @@ impl Filesystem for QrFS {
     // Esta funcion se encarga de eliminar un archivo
     fn unlink(
         &mut self, 
-        _req: &Request, 
+        req: &Request, 
         parent: u64, 
         name: &OsStr, 
         reply: ReplyEmpty
     ) {
-        let name = name.to_str().unwrap();
         let inode = self.disk.find_inode_in_references_by_name(parent, name);
         // Buscamos el inode y lo eliminamos de la tabla, y tambien borramos los datos del disco
         match inode {
-            Some(inode) => {
+            Ok(Some(inode)) => {
                 if inode.attributes.kind == FileType::Directory {
                     reply.error(EISDIR);
-                } else {
-                    let ino = inode.attributes.ino;
-                    let memory_block_index = (ino as usize) - 1;
-                    self.disk.clear_inode(ino);
-                    self.disk.clear_memory_block(memory_block_index);
-                    self.disk.clear_reference_in_inode(parent, ino as usize);
-                    reply.ok()
+                    return;
+                }
+
+                // Quitar la entrada necesita permisos de escritura y de búsqueda sobre el directorio, y en un directorio
+                // con el bit sticky ser dueño del directorio o del archivo
+                let parent_attributes = self.disk.get_inode(parent).map(|inode| &inode.attributes);
+                if let Err(errno) = check_remove_entry(&Caller::of(req), parent_attributes, &inode.attributes) {
+                    reply.error(errno);
+                    return;
+                }
+
+                // El archivo y sus bloques se liberan cuando no le quedan enlaces ni handles abiertos
+                let ino = inode.attributes.ino;
+                let removed = self.disk.remove_directory_entry(parent, name)
+                    .and_then(|_| self.disk.decrease_links(ino));
+                if let Err(e) = removed {
+                    println!("¡Error al leer el disco! {}", e);
+                    self.disk.abort_transaction();
+                    reply.error(e.errno());
+                    return;
+                }
+
+                if let Err(e) = self.disk.commit_transaction() {
+                    println!("¡Error al registrar la operación en la bitácora! {}", e);
+                    self.disk.abort_transaction();
+                    reply.error(EIO);
+                    return;
                 }
+
+                reply.ok()
             },
-            None => reply.error(EIO)
+            Ok(None) => reply.error(ENOENT),
+            Err(e) => {
+                println!("¡Error al leer el disco! {}", e);
+                reply.error(e.errno())
+            }
         }
     }
-}
+    // Esta funcion se encarga de crear un enlace duro: una entrada nueva que apunta a un archivo existente
+    fn link(
+        &mut self, 
+        req: &Request, 
+        ino: u64, 
+        newparent: u64, 
+        newname: &OsStr, 
+        reply: ReplyEntry
+    ) {
+        println!("link(ino={}, newparent={}, newname={:?})", ino, newparent, newname);
 
-fn main() {
-    let mountpoint = match env::args().nth(1) {
-        Some(path) => path,
-        None => {
-            println!("Usage: {} <MOUNTPOINT>", env::args().nth(0).unwrap());
+        let kind = match self.disk.get_inode(ino) {
+            Some(inode) => inode.attributes.kind,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+
+        // Los directorios no pueden tener enlaces duros
+        if kind == FileType::Directory {
+            reply.error(EPERM);
             return;
         }
-    };
-    let mut s=String::new();
-    print!("Please enter your phrase: ");
-    let _=stdout().flush();
-    stdin().read_line(&mut s).expect("Did not enter a correct string");
-    if let Some('\n')=s.chars().next_back() {
-        s.pop();
-    }
-    if let Some('\r')=s.chars().next_back() {
-        s.pop();
-    }
 
-    QrFS::new(mountpoint.clone(),s.clone());
-}
\ No newline at end of file
+        match self.disk.find_inode_in_references_by_name(newparent, newname) {
+            Ok(Some(_)) => {
+                reply.error(EEXIST); // “File exists.”
+                return;
+            },
+            Ok(None) => (),
+            Err(e) => {
+                println!("¡Error al buscar la entrada! {}", e);
+                reply.error(e.errno());
+                return;
+            }
+        }
+
+        // Agregar una entrada necesita permisos de escritura y de búsqueda sobre el directorio
+        if let Err(errno) = check_add_entry(&Caller::of(req), self.disk.get_inode(newparent).map(|inode| &inode.attributes)) {
+            reply.error(errno);
+            return;
+        }
+
+        let entry = DirectoryEntry { ino, kind, name: newname.to_os_string() };
+        if let Err(e) = self.disk.add_directory_entry(newparent, entry) {
+            println!("¡No se pudo agregar el enlace al directorio! {}", e);
+            self.disk.abort_transaction();
+            reply.error(e.errno());
+            return;
+        }
+        self.disk.increase_links(ino);
+
+        if let Err(e) = self.disk.commit_transaction() {
+            println!("¡Error al registrar la operación en la bitácora! {}", e);
+            self.disk.abort_transaction();
+            reply.error(EIO);
+            return;
+        }
+
+        match self.disk.get_inode(ino) {
+            Some(inode) => reply.entry(&time::now().to_timespec(), &inode.attributes, 0),
+            None => reply.error(ENOENT)
+        }
+    }
+    // Esta funcion se encarga de crear un enlace simbólico. Los destinos cortos se guardan en el inodo y los largos en
+    // sus bloques de datos
+    fn symlink(
+        &mut self, 
+        req: &Request, 
+        parent: u64, 
+        name: &OsStr, 
+        link: &Path, 
+        reply: ReplyEntry
+    ) {
+        println!("symlink(parent={}, name={:?}, link={:?})", parent, name, link);
+
+        match self.disk.find_inode_in_references_by_name(parent, name) {
+            Ok(Some(_)) => {
+                reply.error(EEXIST); // “File exists.”
+                return;
+            },
+            Ok(None) => (),
+            Err(e) => {
+                println!("¡Error al buscar la entrada! {}", e);
+                reply.error(e.errno());
+                return;
+            }
+        }
+
+        // Agregar una entrada necesita permisos de escritura y de búsqueda sobre el directorio
+        let caller = Caller::of(req);
+        if let Err(errno) = check_add_entry(&caller, self.disk.get_inode(parent).map(|inode| &inode.attributes)) {
+            reply.error(errno);
+            return;
+        }
+
+        if link.as_os_str().is_empty() {
+            reply.error(ENOENT);
+            return;
+        }
+
+        let parent_attributes = match self.disk.get_inode(parent) {
+            Some(inode) => inode.attributes,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        // Los permisos de un enlace simbólico no se usan: siempre son 0o777
+        let (uid, gid, _) = new_owner(&caller, &parent_attributes, FileType::Symlink, 0o777);
+
+        let ino = match self.disk.find_ino_available() {
+            Some(ino) => ino,
+            None => {
+                reply.error(ENOSPC); // “No space left on device.”
+                return;
+            }
+        };
+
+        let ts = time::now().to_timespec();
+        let attr = FileAttr {
+            ino,
+            size: 0,
+            blocks: 0,
+            atime: ts,
+            mtime: ts,
+            ctime: ts,
+            crtime: ts,
+            kind: FileType::Symlink,
+            perm: 0o777,
+            nlink: 1,
+            uid,
+            gid,
+            rdev: 0,
+            flags: 0,
+        };
+
+        let inode = Inode {
+            attributes: attr,
+            references: [None; 128],
+            target: None,
+            xattrs: BTreeMap::new()
+        };
+        if let Err(e) = self.disk.write_inode(inode) {
+            println!("¡No se pudo guardar el inodo del enlace simbólico! {}", e);
+            reply.error(e.errno());
+            return;
+        }
+
+        let entry = DirectoryEntry { ino, kind: FileType::Symlink, name: name.to_os_string() };
+        let added = self.disk.write_symlink_target(ino, link.as_os_str())
+            .and_then(|_| self.disk.add_directory_entry(parent, entry));
+        if let Err(e) = added {
+            println!("¡No se pudo crear el enlace simbólico! {}", e);
+            self.disk.abort_transaction();
+            reply.error(e.errno());
+            return;
+        }
+
+        if let Err(e) = self.disk.commit_transaction() {
+            println!("¡Error al registrar la operación en la bitácora! {}", e);
+            self.disk.abort_transaction();
+            reply.error(EIO);
+            return;
+        }
+
+        match self.disk.get_inode(ino) {
+            Some(inode) => reply.entry(&ts, &inode.attributes, 0),
+            None => reply.error(ENOENT)
+        }
+    }
+    // Esta funcion se encarga de leer el destino de un enlace simbólico
+    fn readlink(
+        &mut self, 
+        _req: &Request, 
+        ino: u64, 
+        reply: ReplyData
+    ) {
+        println!("readlink(ino={})", ino);
+
+        match self.disk.read_symlink_target(ino) {
+            Ok(Some(target)) => reply.data(target.as_bytes()),
+            Ok(None) => reply.error(if self.disk.get_inode(ino).is_some() { EINVAL } else { ENOENT }),
+            Err(e) => {
+                println!("¡Error al leer el disco! {}", e);
+                reply.error(e.errno())
+            }
+        }
+    }
+    // Esta funcion se encarga de crear o cambiar un atributo extendido de un archivo
+    fn setxattr(
+        &mut self, 
+        req: &Request, 
+        ino: u64, 
+        name: &OsStr, 
+        value: &[u8], 
+        flags: u32, 
+        _position: u32, 
+        reply: ReplyEmpty
+    ) {
+        println!("setxattr(ino={}, name={:?}, size={}, flags={})", ino, name, value.len(), flags);
+
+        let inode = match self.disk.get_inode(ino) {
+            Some(inode) => inode,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+
+        let allowed = xattr_namespace(name)
+            .and_then(|namespace| check_xattr_write(&Caller::of(req), &inode.attributes, namespace))
+            .and_then(|_| check_xattr_size(value.len()));
+        if let Err(errno) = allowed {
+            reply.error(errno);
+            return;
+        }
+
+        if let Err(errno) = check_xattr_flags(inode.xattrs.contains_key(name.as_bytes()), flags as i32) {
+            reply.error(errno);
+            return;
+        }
+
+        if let Err(e) = self.disk.set_xattr(ino, name, value) {
+            println!("¡No se pudo guardar el atributo! {}", e);
+            self.disk.abort_transaction();
+            reply.error(e.errno());
+            return;
+        }
+
+        if let Err(e) = self.disk.commit_transaction() {
+            println!("¡Error al registrar la operación en la bitácora! {}", e);
+            self.disk.abort_transaction();
+            reply.error(EIO);
+            return;
+        }
+
+        reply.ok()
+    }
+    // Esta funcion se encarga de leer un atributo extendido de un archivo. Con `size` 0 solo se responde su largo
+    fn getxattr(
+        &mut self, 
+        req: &Request, 
+        ino: u64, 
+        name: &OsStr, 
+        size: u32, 
+        reply: ReplyXattr
+    ) {
+        println!("getxattr(ino={}, name={:?}, size={})", ino, name, size);
+
+        let attributes = match self.disk.get_inode(ino) {
+            Some(inode) => inode.attributes,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+
+        let allowed = xattr_namespace(name)
+            .and_then(|namespace| check_xattr_read(&Caller::of(req), &attributes, namespace));
+        if let Err(errno) = allowed {
+            reply.error(errno);
+            return;
+        }
+
+        match self.disk.get_xattr(ino, name) {
+            Ok(Some(value)) => match check_xattr_buffer(value.len(), size) {
+                Ok(()) if size == 0 => reply.size(value.len() as u32),
+                Ok(()) => reply.data(&value),
+                Err(errno) => reply.error(errno)
+            },
+            Ok(None) => reply.error(ENODATA), // “No data available.”
+            Err(e) => {
+                println!("¡Error al leer el disco! {}", e);
+                reply.error(e.errno())
+            }
+        }
+    }
+    // Esta funcion se encarga de listar los atributos extendidos de un archivo, cada nombre terminado en un byte nulo
+    fn listxattr(
+        &mut self, 
+        req: &Request, 
+        ino: u64, 
+        size: u32, 
+        reply: ReplyXattr
+    ) {
+        println!("listxattr(ino={}, size={})", ino, size);
+
+        if self.disk.get_inode(ino).is_none() {
+            reply.error(ENOENT);
+            return;
+        }
+
+        let caller = Caller::of(req);
+        let mut list = Vec::new();
+        for name in self.disk.xattr_names(ino) {
+            if xattr_namespace(&name).is_ok_and(|namespace| lists_xattr(&caller, namespace)) {
+                list.extend_from_slice(name.as_bytes());
+                list.push(0);
+            }
+        }
+
+        match check_xattr_buffer(list.len(), size) {
+            Ok(()) if size == 0 => reply.size(list.len() as u32),
+            Ok(()) => reply.data(&list),
+            Err(errno) => reply.error(errno)
+        }
+    }
+    // Esta funcion se encarga de quitar un atributo extendido de un archivo
+    fn removexattr(
+        &mut self, 
+        req: &Request, 
+        ino: u64, 
+        name: &OsStr, 
+        reply: ReplyEmpty
+    ) {
+        println!("removexattr(ino={}, name={:?})", ino, name);
+
+        let attributes = match self.disk.get_inode(ino) {
+            Some(inode) => inode.attributes,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+
+        let allowed = xattr_namespace(name)
+            .and_then(|namespace| check_xattr_write(&Caller::of(req), &attributes, namespace));
+        if let Err(errno) = allowed {
+            reply.error(errno);
+            return;
+        }
+
+        if !self.disk.remove_xattr(ino, name) {
+            reply.error(ENODATA); // “No data available.”
+            return;
+        }
+
+        if let Err(e) = self.disk.commit_transaction() {
+            println!("¡Error al registrar la operación en la bitácora! {}", e);
+            self.disk.abort_transaction();
+            reply.error(EIO);
+            return;
+        }
+
+        reply.ok()
+    }
+    // Esta funcion se encarga de cambiar el nombre de un archivo o directorio, o de moverlo a otro directorio
+    fn rename(
+        &mut self, 
+        req: &Request, 
+        parent: u64, 
+        name: &OsStr, 
+        newparent: u64, 
+        newname: &OsStr, 
+        reply: ReplyEmpty
+    ) {
+        println!("rename(parent={}, name={:?}, newparent={}, newname={:?})", parent, name, newparent, newname);
+
+        let source = match self.disk.find_inode_in_references_by_name(parent, name) {
+            Ok(Some(inode)) => inode.attributes,
+            Ok(None) => {
+                reply.error(ENOENT);
+                return;
+            },
+            Err(e) => {
+                println!("¡Error al buscar la entrada! {}", e);
+                reply.error(e.errno());
+                return;
+            }
+        };
+
+        match self.disk.get_inode(newparent) {
+            Some(inode) if inode.attributes.kind == FileType::Directory => (),
+            Some(_) => {
+                reply.error(ENOTDIR);
+                return;
+            },
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        }
+
+        let target = match self.disk.find_inode_in_references_by_name(newparent, newname) {
+            Ok(inode) => inode.map(|inode| (inode.attributes.ino, inode.attributes.kind)),
+            Err(e) => {
+                println!("¡Error al buscar la entrada! {}", e);
+                reply.error(e.errno());
+                return;
+            }
+        };
+
+        let (ino, kind) = (source.ino, source.kind);
+
+        // Si el destino ya es el mismo archivo no hay nada que cambiar
+        if target.map(|(target_ino, _)| target_ino) == Some(ino) {
+            reply.ok();
+            return;
+        }
+
+        // Mover la entrada la quita de su directorio y la agrega al nuevo, que pierde la entrada que se reemplaza. Un
+        // directorio que cambia de padre además necesita permiso de escritura para cambiar su entrada ".."
+        let caller = Caller::of(req);
+        let newparent_attributes = self.disk.get_inode(newparent).map(|inode| &inode.attributes);
+        let permitted = check_remove_entry(&caller, self.disk.get_inode(parent).map(|inode| &inode.attributes), &source)
+            .and_then(|_| check_add_entry(&caller, newparent_attributes))
+            .and_then(|_| match target.and_then(|(target_ino, _)| self.disk.get_inode(target_ino)) {
+                Some(replaced) => check_remove_entry(&caller, newparent_attributes, &replaced.attributes),
+                None => Ok(())
+            })
+            .and_then(|_| match kind == FileType::Directory && parent != newparent && !has_access(&caller, &source, W_OK) {
+                true => Err(EACCES),
+                false => Ok(())
+            });
+        if let Err(errno) = permitted {
+            reply.error(errno);
+            return;
+        }
+
+        // Un directorio no se puede mover adentro de sí mismo ni de alguno de sus subdirectorios
+        if kind == FileType::Directory {
+            match self.disk.directory_contains(ino, newparent) {
+                Ok(false) => (),
+                Ok(true) => {
+                    reply.error(EINVAL);
+                    return;
+                },
+                Err(e) => {
+                    println!("¡Error al leer el disco! {}", e);
+                    reply.error(e.errno());
+                    return;
+                }
+            }
+        }
+
+        // Un directorio solo reemplaza a otro directorio, que además debe estar vacío, y un archivo no reemplaza a un
+        // directorio
+        if let Some((target_ino, target_kind)) = target {
+            let errno = match (kind == FileType::Directory, target_kind == FileType::Directory) {
+                (true, false) => Some(ENOTDIR),
+                (false, true) => Some(EISDIR),
+                (true, true) => match self.disk.directory_entry_count(target_ino) {
+                    Ok(0) => None,
+                    Ok(_) => Some(ENOTEMPTY),
+                    Err(e) => {
+                        println!("¡Error al leer el disco! {}", e);
+                        Some(e.errno())
+                    }
+                },
+                (false, false) => None
+            };
+
+            if let Some(errno) = errno {
+                reply.error(errno);
+                return;
+            }
+        }
+
+        // El destino se reemplaza en su misma entrada y todos los cambios van en una sola transacción de la bitácora,
+        // así que el destino siempre existe, con el archivo anterior o con el nuevo. El archivo reemplazado pierde un
+        // enlace y un directorio reemplazado, que está vacío, se libera junto con su entrada ".."
+        let entry = DirectoryEntry { ino, kind, name: newname.to_os_string() };
+        let moved = match target {
+            Some((target_ino, FileType::Directory)) => self.disk.replace_directory_entry(newparent, entry)
+                .and_then(|_| self.disk.free_inode(target_ino))
+                .and_then(|_| self.disk.decrease_links(newparent)),
+            Some((target_ino, _)) => self.disk.replace_directory_entry(newparent, entry)
+                .and_then(|_| self.disk.decrease_links(target_ino)),
+            None => self.disk.add_directory_entry(newparent, entry)
+        };
+
+        // La entrada ".." de un directorio que cambia de padre pasa a contar como enlace del padre nuevo
+        let moved = moved.and_then(|_| self.disk.remove_directory_entry(parent, name)).and_then(|_| {
+            if kind == FileType::Directory && parent != newparent {
+                self.disk.decrease_links(parent)?;
+                self.disk.increase_links(newparent);
+            }
+            Ok(())
+        });
+
+        // Un paso que falla deja el cambio a medias: se deshace entero antes de responder
+        if let Err(e) = moved {
+            println!("¡No se pudo mover la entrada! {}", e);
+            self.disk.abort_transaction();
+            reply.error(e.errno());
+            return;
+        }
+
+        if let Err(e) = self.disk.commit_transaction() {
+            println!("¡Error al registrar la operación en la bitácora! {}", e);
+            self.disk.abort_transaction();
+            reply.error(EIO);
+            return;
+        }
+
+        reply.ok()
+    }
+}
+
+/// Administración de las ranuras de llave de un disco existente
+enum KeyCommand {
+    ChangePhrase,
+    List,
+    AddPhrase,
+    AddKeyFile(String),
+    Revoke(usize)
+}
+
+/// Opciones de mkfs_qrfs: el archivo de llave indicado con `--key-file`, la acción sobre las ranuras de llave, si se
+/// pidió alguna, los archivos con las partes de la llave maestra para `--recover` y las opciones de geometría que se
+/// usan al crear el disco.
+struct Options {
+    key_file: Option<String>,
+    key_command: Option<KeyCommand>,
+    share_files: Option<Vec<String>>,
+    // Ranura que reemplaza la credencial nueva al recuperar el acceso, si no debe ir en una libre
+    replace_slot: Option<usize>,
+    geometry: Vec<String>
+}
+
+impl Options {
+    fn from_args(args: &[String]) -> Result<Options, String> {
+        let mut options = Options {
+            key_file: None,
+            key_command: None,
+            share_files: None,
+            replace_slot: None,
+            geometry: Vec::new()
+        };
+
+        let mut args = args.iter();
+        while let Some(option) = args.next() {
+            let command = match option.as_str() {
+                "--key-file" => {
+                    options.key_file = Some(args.next().ok_or(format!("Falta el valor de la opción {}", option))?.clone());
+                    continue;
+                },
+                "--replace-slot" => {
+                    let value = args.next().ok_or(format!("Falta el valor de la opción {}", option))?;
+                    options.replace_slot = Some(value.parse().map_err(|_| format!("Valor inválido para {}: {}", option, value))?);
+                    continue;
+                },
+                "--recover" => {
+                    // Todos los argumentos que siguen son archivos con partes de la llave
+                    options.share_files = Some(args.by_ref().cloned().collect());
+                    continue;
+                },
+                "--change-phrase" => KeyCommand::ChangePhrase,
+                "--list-keys" => KeyCommand::List,
+                "--add-phrase" => KeyCommand::AddPhrase,
+                "--add-key-file" => KeyCommand::AddKeyFile(args.next().ok_or(format!("Falta el valor de la opción {}", option))?.clone()),
+                "--revoke-key" => {
+                    let value = args.next().ok_or(format!("Falta el valor de la opción {}", option))?;
+                    KeyCommand::Revoke(value.parse().map_err(|_| format!("Valor inválido para {}: {}", option, value))?)
+                },
+                _ => {
+                    // Las opciones de geometría se validan después, con `Geometry::from_args`
+                    options.geometry.push(option.clone());
+                    options.geometry.extend(args.next().cloned());
+                    continue;
+                }
+            };
+
+            if options.key_command.replace(command).is_some() {
+                return Err(String::from("Solo se puede indicar una acción sobre las llaves a la vez"));
+            }
+        }
+
+        if (options.key_command.is_some() || options.share_files.is_some()) && !options.geometry.is_empty() {
+            return Err(String::from("Las opciones de geometría solo se usan al crear el disco"));
+        }
+
+        if options.key_command.is_some() && options.share_files.is_some() {
+            return Err(String::from("Solo se puede indicar una acción sobre las llaves a la vez"));
+        }
+
+        if options.share_files.as_ref().is_some_and(|files| files.is_empty()) {
+            return Err(String::from("Falta el valor de la opción --recover"));
+        }
+
+        if options.replace_slot.is_some() && options.share_files.is_none() {
+            return Err(String::from("La opción --replace-slot solo se usa con --recover"));
+        }
+
+        Ok(options)
+    }
+}
+
+/// Lee una frase de la entrada estándar, sin el salto de línea final.
+fn read_phrase(prompt: &str) -> String {
+    let mut s=String::new();
+    print!("{}", prompt);
+    let _=stdout().flush();
+    stdin().read_line(&mut s).expect("Did not enter a correct string");
+    if let Some('\n')=s.chars().next_back() {
+        s.pop();
+    }
+    if let Some('\r')=s.chars().next_back() {
+        s.pop();
+    }
+    s
+}
+
+/// Credencial para crear o abrir el disco: el archivo de llave `key_file` si se indicó, o la frase ingresada.
+fn read_credential(key_file: &Option<String>) -> Result<Credential, String> {
+    match key_file {
+        Some(path) => Credential::from_key_file(path).map_err(|e| format!("No se pudo leer el archivo de llave {}: {}", path, e)),
+        None => Ok(Credential::Phrase(read_phrase("Please enter your phrase: ")))
+    }
+}
+
+/// Pide una frase nueva dos veces. Devuelve `None` si no coinciden.
+fn read_new_phrase() -> Option<Credential> {
+    let new_phrase = read_phrase("Please enter the new phrase: ");
+    if read_phrase("Please repeat the new phrase: ") != new_phrase {
+        println!("Las frases no coinciden, no se modificaron las llaves");
+        return None;
+    }
+
+    Some(Credential::Phrase(new_phrase))
+}
+
+/// Abre el disco de `root_path` con la credencial indicada y aplica `command` a sus ranuras de llave.
+fn manage_keys(root_path: String, key_file: &Option<String>, command: KeyCommand) {
+    let credential = match read_credential(key_file) {
+        Ok(credential) => credential,
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut disk = match Disk::new(root_path, credential) {
+        Ok(disk) => disk,
+        Err(e) => {
+            println!("No se pudo cargar el disco: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let result = match command {
+        KeyCommand::List => {
+            println!("Ranura  Tipo              Creada");
+            for (index, slot) in disk.key_slots().iter().enumerate() {
+                if let Some(slot) = slot {
+                    let opened = if index == disk.unlocked_slot() { " (abierta)" } else { "" };
+                    println!("{:<7} {:<17} {}{}", index, slot.kind.to_string(), time::at_utc(slot.created).rfc3339(), opened);
+                }
+            }
+            Ok(())
+        },
+        KeyCommand::ChangePhrase => match read_new_phrase() {
+            Some(new_phrase) => disk.change_key(&new_phrase)
+                .map(|_| println!("¡Frase de la ranura {} cambiada!", disk.unlocked_slot())),
+            None => std::process::exit(1)
+        },
+        KeyCommand::AddPhrase => match read_new_phrase() {
+            Some(new_phrase) => disk.add_key(&new_phrase).map(|slot| println!("¡Frase agregada en la ranura {}!", slot)),
+            None => std::process::exit(1)
+        },
+        KeyCommand::AddKeyFile(path) => match Credential::from_key_file(&path) {
+            Ok(key_file) => disk.add_key(&key_file).map(|slot| println!("¡Archivo de llave agregado en la ranura {}!", slot)),
+            Err(e) => {
+                println!("No se pudo leer el archivo de llave {}: {}", path, e);
+                std::process::exit(1);
+            }
+        },
+        KeyCommand::Revoke(slot) => disk.revoke_key(slot).map(|_| println!("¡Ranura {} revocada!", slot))
+    };
+
+    if let Err(e) = result {
+        println!("No se pudieron modificar las llaves: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Reconstruye la llave maestra con las partes guardadas en `share_files`, el texto leído de sus códigos QR, y agrega
+/// al disco una ranura con una frase nueva, o con el archivo de llave `key_file` si se indicó. Con `replace_slot` la
+/// credencial nueva reemplaza a la de esa ranura, que hace falta cuando no queda ninguna libre.
+fn recover_access(root_path: String, key_file: &Option<String>, share_files: &[String], replace_slot: Option<usize>) {
+    let mut shares = Vec::new();
+    for path in share_files {
+        match fs::read_to_string(path).ok().and_then(|text| KeyShare::from_text(&text)) {
+            Some(share) => {
+                println!("{}: {}", path, share.label());
+                shares.push(share);
+            },
+            None => {
+                println!("{} no contiene una parte de la llave de QrFS", path);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let key = match Key::combine(&shares) {
+        Some(key) => key,
+        None => {
+            println!("Las partes no alcanzan para reconstruir la llave o pertenecen a repartos distintos");
+            std::process::exit(1);
+        }
+    };
+
+    let credential = match key_file {
+        Some(_) => match read_credential(key_file) {
+            Ok(credential) => credential,
+            Err(e) => {
+                println!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        None => match read_new_phrase() {
+            Some(new_phrase) => new_phrase,
+            None => std::process::exit(1)
+        }
+    };
+
+    match Disk::recover_access(&root_path, &key, &credential, replace_slot) {
+        Ok(slot) => println!("¡Acceso recuperado en la ranura {}!", slot),
+        Err(e) => {
+            println!("No se pudo recuperar el acceso al disco: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Muestra las formas de invocar mkfs_qrfs.
+fn print_usage() {
+    let program = env::args().next().unwrap();
+    println!("Usage: {} <MOUNTPOINT> [--key-file PATH] [--size BYTES] [--block-size BYTES] [--inodes N]", program);
+    println!("       {} <MOUNTPOINT> [--key-file PATH] --list-keys", program);
+    println!("       {} <MOUNTPOINT> [--key-file PATH] --change-phrase", program);
+    println!("       {} <MOUNTPOINT> [--key-file PATH] --add-phrase", program);
+    println!("       {} <MOUNTPOINT> [--key-file PATH] --add-key-file PATH", program);
+    println!("       {} <MOUNTPOINT> [--key-file PATH] --revoke-key SLOT", program);
+    println!("       {} <MOUNTPOINT> [--key-file PATH] [--replace-slot SLOT] --recover SHARE_FILE...", program);
+}
+
+fn main() {
+    let mountpoint = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            print_usage();
+            return;
+        }
+    };
+
+    let args: Vec<String> = env::args().skip(2).collect();
+    let options = match Options::from_args(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{}", e);
+            print_usage();
+            return;
+        }
+    };
+
+    if let Some(share_files) = options.share_files {
+        recover_access(mountpoint, &options.key_file, &share_files, options.replace_slot);
+        return;
+    }
+
+    if let Some(command) = options.key_command {
+        manage_keys(mountpoint, &options.key_file, command);
+        return;
+    }
+
+    let geometry = match Geometry::from_args(&options.geometry).and_then(|geometry| geometry.validate().map(|_| geometry)) {
+        Ok(geometry) => geometry,
+        Err(e) => {
+            println!("{}", e);
+            print_usage();
+            return;
+        }
+    };
+
+    // La geometría de un disco existente es la de su encabezado: no se puede cambiar sin volver a crearlo
+    if !options.geometry.is_empty() && disk_exists(&mountpoint) {
+        println!("Ya existe un disco en {}; las opciones de geometría solo se usan al crear uno nuevo", mountpoint);
+        std::process::exit(1);
+    }
+
+    let credential = match read_credential(&options.key_file) {
+        Ok(credential) => credential,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    match QrFS::new(mountpoint.clone(), credential, &geometry) {
+        Ok(fs) => println!("Tamaño máximo de un archivo: {} bytes", fs.disk.max_file_size()),
+        Err(e) => {
+            println!("No se pudo crear el disco: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    /// Geometría de las opciones `values`, ya validada.
+    fn geometry(values: &[&str]) -> Result<Geometry, String> {
+        Geometry::from_args(&args(values)).and_then(|geometry| geometry.validate().map(|_| geometry))
+    }
+
+    #[test]
+    fn sizes_accept_the_k_m_and_g_suffixes() {
+        assert_eq!(parse_size("4096"), Some(4096));
+        assert_eq!(parse_size("64K"), Some(64 * 1024));
+        assert_eq!(parse_size("16m"), Some(16 * 1024 * 1024));
+        assert_eq!(parse_size("1G"), Some(1024 * 1024 * 1024));
+        assert_eq!(parse_size(""), None);
+        assert_eq!(parse_size("K"), None);
+        assert_eq!(parse_size("12T"), None);
+        assert_eq!(parse_size("-1"), None);
+        assert_eq!(parse_size(&format!("{}G", usize::MAX / 1024)), None);
+    }
+
+    #[test]
+    fn default_geometry_is_valid() {
+        let geometry = geometry(&[]).unwrap();
+        let table_size = geometry.inode_table_size().unwrap();
+        assert_eq!(table_size % DEFAULT_BLOCK_SIZE, 0);
+        assert_eq!(geometry.block_count(), (DEFAULT_MEMORY_SIZE - table_size) / DEFAULT_BLOCK_SIZE);
+    }
+
+    #[test]
+    fn block_sizes_must_be_powers_of_two_within_the_limits() {
+        assert!(geometry(&["--block-size", "1000"]).is_err());
+        assert!(geometry(&["--block-size", "256"]).is_err());
+        assert!(geometry(&["--block-size", "32M"]).is_err());
+        assert!(geometry(&["--block-size", "512"]).is_ok());
+        assert!(geometry(&["--block-size", "16M"]).is_ok());
+    }
+
+    #[test]
+    fn blocks_cannot_be_larger_than_the_disk() {
+        assert!(geometry(&["--size", "4K", "--block-size", "8K"]).is_err());
+    }
+
+    #[test]
+    fn the_inode_table_must_leave_room_for_data() {
+        assert!(geometry(&["--size", "8K", "--block-size", "4K", "--inodes", "1024"]).is_err());
+        assert!(geometry(&["--size", "64K", "--block-size", "4K", "--inodes", "0"]).is_err());
+        assert!(geometry(&["--size", "64K", "--block-size", "4K", "--inodes", "1"]).is_ok());
+    }
+
+    #[test]
+    fn huge_inode_counts_are_rejected_without_overflowing() {
+        assert!(geometry(&["--inodes", "5000000000000000"]).is_err());
+        assert!(geometry(&["--inodes", &usize::MAX.to_string()]).is_err());
+    }
+
+    #[test]
+    fn geometry_options_need_a_known_name_and_a_value() {
+        assert!(geometry(&["--size"]).is_err());
+        assert!(geometry(&["--sectores", "4"]).is_err());
+        assert!(geometry(&["--size", "muchos"]).is_err());
+    }
+
+    #[test]
+    fn options_separate_key_actions_from_geometry() {
+        let options = Options::from_args(&args(&["--key-file", "llave", "--revoke-key", "2"])).unwrap();
+        assert_eq!(options.key_file.as_deref(), Some("llave"));
+        assert!(matches!(options.key_command, Some(KeyCommand::Revoke(2))));
+        assert!(options.geometry.is_empty());
+
+        let options = Options::from_args(&args(&["--size", "64K", "--inodes", "4"])).unwrap();
+        assert!(options.key_command.is_none());
+        assert_eq!(options.geometry, args(&["--size", "64K", "--inodes", "4"]));
+
+        let options = Options::from_args(&args(&["--replace-slot", "1", "--recover", "a.txt", "b.txt"])).unwrap();
+        assert_eq!(options.replace_slot, Some(1));
+        assert_eq!(options.share_files, Some(args(&["a.txt", "b.txt"])));
+    }
+
+    #[test]
+    fn only_one_key_action_is_accepted() {
+        assert!(Options::from_args(&args(&["--list-keys", "--add-phrase"])).is_err());
+        assert!(Options::from_args(&args(&["--change-phrase", "--change-phrase"])).is_err());
+        assert!(Options::from_args(&args(&["--list-keys", "--recover", "a.txt"])).is_err());
+    }
+
+    #[test]
+    fn geometry_options_cannot_go_with_key_actions() {
+        assert!(Options::from_args(&args(&["--size", "64K", "--list-keys"])).is_err());
+        assert!(Options::from_args(&args(&["--add-key-file", "llave", "--inodes", "4"])).is_err());
+        assert!(Options::from_args(&args(&["--block-size", "4K", "--recover", "a.txt"])).is_err());
+    }
+
+    #[test]
+    fn replace_slot_needs_recover() {
+        assert!(Options::from_args(&args(&["--replace-slot", "1"])).is_err());
+        assert!(Options::from_args(&args(&["--replace-slot", "1", "--list-keys"])).is_err());
+    }
+
+    #[test]
+    fn recover_needs_share_files() {
+        assert!(Options::from_args(&args(&["--recover"])).is_err());
+    }
+
+    #[test]
+    fn slots_must_be_numbers() {
+        assert!(Options::from_args(&args(&["--revoke-key", "uno"])).is_err());
+        assert!(Options::from_args(&args(&["--revoke-key"])).is_err());
+        assert!(Options::from_args(&args(&["--replace-slot", "-1", "--recover", "a.txt"])).is_err());
+    }
+}
@@ -0,0 +1,482 @@
+use argon2::{Algorithm, Argon2, Params, Version}; //Libreria para derivar llaves a partir de la frase (Argon2id)
+use chacha20poly1305::{XChaCha20Poly1305, XNonce}; //Libreria de cifrado autenticado
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload}; //Interfaz comun de los cifrados autenticados
+use chacha20poly1305::aead::rand_core::RngCore; //Libreria para generar bytes aleatorios
+use serde::{Serialize, Deserialize}; //Libreria para el manejo de serealizacion
+use crate::serialization::TimespecDef; //Libreria propietaria del proyecto
+use time::Timespec; //Libreria para el manejo del tiempo
+use std::fs; //Libreria para leer los archivos de llave
+use std::io; //Libreria para el manejo de entradas y salidas
+use std::fmt; //Libreria para dar formato a la llave sin mostrarla
+use std::convert::TryInto; //Libreria para convertir la llave desenvuelta a un arreglo
+
+// Tamaño en bytes de la llave, de la sal y del nonce de XChaCha20-Poly1305
+pub const KEY_SIZE: usize = 32;
+pub const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 24;
+// Bytes que el cifrado agrega a cada contenido: el nonce antepuesto y la etiqueta de autenticación de Poly1305
+pub const SEAL_OVERHEAD: usize = NONCE_SIZE + 16;
+
+// Prefijo y versión del texto de las partes de la llave maestra repartida con Shamir
+#[allow(dead_code)]
+const SHARE_PREFIX: &str = "QRFS-LLAVE";
+#[allow(dead_code)]
+const SHARE_VERSION: u32 = 1;
+
+// Cantidad de ranuras de llave del encabezado
+pub const MAX_KEY_SLOTS: usize = 8;
+
+// Memoria en KiB, iteraciones y paralelismo de Argon2id para las ranuras nuevas. Las pruebas usan los mínimos para no
+// tardar segundos en cada ranura que crean o prueban.
+#[cfg(not(test))]
+const KDF_COSTS: (u32, u32, u32) = (Params::DEFAULT_M_COST, Params::DEFAULT_T_COST, Params::DEFAULT_P_COST);
+#[cfg(test)]
+const KDF_COSTS: (u32, u32, u32) = (Params::MIN_M_COST, Params::MIN_T_COST, Params::MIN_P_COST);
+
+// Contexto autenticado junto con la llave maestra envuelta por la llave de la frase
+const WRAPPED_KEY_CONTEXT: &[u8] = b"qrfs-llave-maestra";
+
+/// Llave simétrica de 256 bits. No se muestra con `Debug` para que no termine en un registro ni en la salida de fsck.
+#[derive(Clone)]
+pub struct Key([u8; KEY_SIZE]);
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Key(<oculta>)")
+    }
+}
+
+impl Key {
+    /// Genera una llave maestra aleatoria nueva.
+    pub fn generate() -> Key {
+        let mut key = [0u8; KEY_SIZE];
+        OsRng.fill_bytes(&mut key);
+        Key(key)
+    }
+
+    /// Cifra `plaintext` con XChaCha20-Poly1305 usando un nonce aleatorio, que se antepone al resultado.
+    /// `context` se autentica junto con los datos, así un bloque no se puede mover a otra posición del disco.
+    pub fn encrypt(&self, plaintext: &[u8], context: &[u8]) -> Vec<u8> {
+        let cipher = XChaCha20Poly1305::new((&self.0).into());
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, Payload { msg: plaintext, aad: context })
+            .expect("fn encrypt: no se pudo cifrar el contenido");
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        sealed
+    }
+
+    /// Reparte la llave en `total` partes de modo que cualquier grupo de `threshold` partes la reconstruye y uno más
+    /// pequeño no revela nada de ella. Se debe cumplir `1 <= threshold <= total`.
+    #[allow(dead_code)]
+    pub fn split(&self, threshold: u8, total: u8) -> Vec<KeyShare> {
+        assert!(threshold >= 1 && threshold <= total, "fn split: cantidad de partes inválida");
+
+        // Coeficientes aleatorios de cada polinomio, el término independiente es el byte de la llave
+        let mut coefficients = vec![[0u8; KEY_SIZE]; threshold as usize];
+        coefficients[0] = self.0;
+        for coefficient in coefficients.iter_mut().skip(1) {
+            OsRng.fill_bytes(coefficient);
+        }
+
+        (1..=total).map(|index| {
+            let mut data = [0u8; KEY_SIZE];
+            for (position, byte) in data.iter_mut().enumerate() {
+                // Evaluación del polinomio en `index` con el método de Horner
+                *byte = coefficients.iter().rev().fold(0, |value, coefficient| gf_mul(value, index) ^ coefficient[position]);
+            }
+            KeyShare { threshold, total, index, data }
+        }).collect()
+    }
+
+    /// Reconstruye la llave a partir de sus partes con la interpolación de Lagrange en `x = 0`. Devuelve `None` si no
+    /// hay suficientes partes distintas o si no pertenecen al mismo reparto.
+    #[allow(dead_code)]
+    pub fn combine(shares: &[KeyShare]) -> Option<Key> {
+        let first = shares.first()?;
+        let mut selected: Vec<&KeyShare> = Vec::new();
+        for share in shares {
+            if share.threshold != first.threshold || share.total != first.total {
+                return None;
+            }
+            if !selected.iter().any(|other| other.index == share.index) {
+                selected.push(share);
+            }
+        }
+
+        if selected.len() < first.threshold as usize {
+            return None;
+        }
+        selected.truncate(first.threshold as usize);
+
+        let mut key = [0u8; KEY_SIZE];
+        for share in selected.iter() {
+            // Base de Lagrange de esta parte evaluada en 0: producto de x_j / (x_j - x_i), la resta es un xor
+            let mut basis = 1;
+            for other in selected.iter().filter(|other| other.index != share.index) {
+                basis = gf_mul(basis, gf_mul(other.index, gf_inv(other.index ^ share.index)));
+            }
+            for (byte, value) in key.iter_mut().zip(share.data.iter()) {
+                *byte ^= gf_mul(*value, basis);
+            }
+        }
+
+        Some(Key(key))
+    }
+
+    /// Descifra un contenido producido por `encrypt` con el mismo `context`.
+    /// Devuelve `None` si la llave no es la correcta o si el contenido fue modificado.
+    pub fn decrypt(&self, sealed: &[u8], context: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < NONCE_SIZE {
+            return None;
+        }
+
+        let cipher = XChaCha20Poly1305::new((&self.0).into());
+        let (nonce, ciphertext) = sealed.split_at(NONCE_SIZE);
+        cipher.decrypt(XNonce::from_slice(nonce), Payload { msg: ciphertext, aad: context }).ok()
+    }
+}
+
+/// Multiplicación en GF(2^8) con el polinomio de AES (x^8 + x^4 + x^3 + x + 1).
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        let carry = a & 0x80 != 0;
+        a <<= 1;
+        if carry {
+            a ^= 0x1b;
+        }
+        b >>= 1;
+    }
+    product
+}
+
+/// Inverso multiplicativo en GF(2^8), como a^254. `a` no puede ser 0.
+#[allow(dead_code)]
+fn gf_inv(a: u8) -> u8 {
+    let mut result = 1;
+    for _ in 0..254 {
+        result = gf_mul(result, a);
+    }
+    result
+}
+
+/// Parte de la llave maestra repartida con el esquema de Shamir: cada byte de la llave es el término independiente de
+/// un polinomio de grado `threshold - 1` y la parte guarda su valor en `x = index`.
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct KeyShare {
+    pub threshold: u8,
+    pub total: u8,
+    pub index: u8,
+    data: [u8; KEY_SIZE]
+}
+
+#[allow(dead_code)]
+impl KeyShare {
+    /// Texto que se guarda en el código QR de la parte: `QRFS-LLAVE:<versión>:<parte>:<total>:<mínimo>:<hex>`.
+    pub fn to_text(&self) -> String {
+        let hex: String = self.data.iter().map(|byte| format!("{:02x}", byte)).collect();
+        format!("{}:{}:{}:{}:{}:{}", SHARE_PREFIX, SHARE_VERSION, self.index, self.total, self.threshold, hex)
+    }
+
+    /// Interpreta el texto leído de un código QR. Devuelve `None` si no es una parte válida.
+    pub fn from_text(text: &str) -> Option<KeyShare> {
+        let fields: Vec<&str> = text.trim().split(':').collect();
+        if fields.len() != 6 || fields[0] != SHARE_PREFIX || fields[1].parse::<u32>().ok()? != SHARE_VERSION {
+            return None;
+        }
+
+        let index: u8 = fields[2].parse().ok()?;
+        let total: u8 = fields[3].parse().ok()?;
+        let threshold: u8 = fields[4].parse().ok()?;
+        if index == 0 || threshold == 0 || index > total || threshold > total || fields[5].len() != KEY_SIZE * 2 {
+            return None;
+        }
+
+        let mut data = [0u8; KEY_SIZE];
+        for (position, byte) in data.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(fields[5].get(position * 2..position * 2 + 2)?, 16).ok()?;
+        }
+
+        Some(KeyShare { threshold, total, index, data })
+    }
+
+    /// Rótulo para identificar la hoja impresa de la parte.
+    pub fn label(&self) -> String {
+        format!("Parte {} de {} de la llave maestra de QrFS (se necesitan {})", self.index, self.total, self.threshold)
+    }
+}
+
+/// Parámetros de Argon2id guardados en el encabezado, junto con la sal, para volver a derivar la misma llave.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+    pub salt: [u8; SALT_SIZE]
+}
+
+impl KdfParams {
+    /// Parámetros de Argon2id de `KDF_COSTS` con una sal aleatoria nueva.
+    pub fn generate() -> Self {
+        let mut salt = [0u8; SALT_SIZE];
+        OsRng.fill_bytes(&mut salt);
+
+        let (memory_kib, iterations, parallelism) = KDF_COSTS;
+        KdfParams { memory_kib, iterations, parallelism, salt }
+    }
+
+    /// Deriva una llave a partir de `phrase`. Devuelve `None` si los parámetros guardados no son válidos.
+    pub fn derive_key(&self, phrase: &[u8]) -> Option<Key> {
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, Some(KEY_SIZE)).ok()?;
+        let mut key = [0u8; KEY_SIZE];
+
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(phrase, &self.salt, &mut key)
+            .ok()?;
+
+        Some(Key(key))
+    }
+}
+
+/// Tipo de credencial que desbloquea una ranura de llave.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum SlotKind {
+    Phrase,
+    KeyFile
+}
+
+impl fmt::Display for SlotKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SlotKind::Phrase => write!(f, "frase"),
+            SlotKind::KeyFile => write!(f, "archivo de llave")
+        }
+    }
+}
+
+/// Secreto con el que se intenta desbloquear el disco: una frase ingresada o el contenido de un archivo de llave.
+pub enum Credential {
+    Phrase(String),
+    KeyFile(Vec<u8>)
+}
+
+impl fmt::Debug for Credential {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Credential({}, <oculta>)", self.kind())
+    }
+}
+
+impl Credential {
+    /// Lee el archivo de llave `path`. Un archivo vacío no se acepta como llave.
+    pub fn from_key_file(path: &str) -> io::Result<Credential> {
+        let content = fs::read(path)?;
+
+        if content.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "el archivo de llave está vacío"));
+        }
+
+        Ok(Credential::KeyFile(content))
+    }
+
+    pub fn kind(&self) -> SlotKind {
+        match self {
+            Credential::Phrase(_) => SlotKind::Phrase,
+            Credential::KeyFile(_) => SlotKind::KeyFile
+        }
+    }
+
+    fn secret(&self) -> &[u8] {
+        match self {
+            Credential::Phrase(phrase) => phrase.as_bytes(),
+            Credential::KeyFile(content) => content
+        }
+    }
+}
+
+/// Ranura de llave: una copia de la llave maestra envuelta (cifrada) con la llave derivada de una credencial.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeySlot {
+    pub kind: SlotKind,
+    #[serde(with = "TimespecDef")]
+    pub created: Timespec,
+    pub kdf: KdfParams,
+    pub wrapped_key: Vec<u8>
+}
+
+impl KeySlot {
+    /// Envuelve la llave maestra `key` con la llave derivada de `credential`, usando una sal nueva.
+    fn wrap(credential: &Credential, key: &Key) -> KeySlot {
+        let kdf = KdfParams::generate();
+        let credential_key = kdf.derive_key(credential.secret()).expect("fn wrap: parámetros de Argon2 inválidos");
+
+        KeySlot {
+            kind: credential.kind(),
+            created: time::now().to_timespec(),
+            wrapped_key: credential_key.encrypt(&key.0, WRAPPED_KEY_CONTEXT),
+            kdf
+        }
+    }
+
+    /// Devuelve la llave maestra solo si `credential` logra desenvolverla.
+    fn unlock(&self, credential: &Credential) -> Option<Key> {
+        let credential_key = self.kdf.derive_key(credential.secret())?;
+        let key = credential_key.decrypt(&self.wrapped_key, WRAPPED_KEY_CONTEXT)?;
+
+        Some(Key(key.try_into().ok()?))
+    }
+}
+
+/// Parte del encabezado del superbloque que permite recuperar la llave maestra del disco. Los bloques se cifran con
+/// la llave maestra y cada ranura guarda una copia envuelta con una credencial distinta, como en LUKS; así agregar,
+/// cambiar o revocar una credencial solo reescribe este encabezado. Las ranuras tienen posiciones fijas para que su
+/// número no cambie al revocar otra.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EncryptionHeader {
+    pub slots: Vec<Option<KeySlot>>
+}
+
+impl EncryptionHeader {
+    /// Genera una llave maestra nueva y la guarda en la primera ranura, envuelta con `credential`.
+    pub fn create(credential: &Credential) -> (EncryptionHeader, Key) {
+        let key = Key::generate();
+        (EncryptionHeader::from_key(credential, &key), key)
+    }
+
+    /// Encabezado con la llave maestra `key` envuelta con `credential` en la primera ranura.
+    pub fn from_key(credential: &Credential, key: &Key) -> EncryptionHeader {
+        let mut slots = vec![None; MAX_KEY_SLOTS];
+        slots[0] = Some(KeySlot::wrap(credential, key));
+
+        EncryptionHeader { slots }
+    }
+
+    /// Prueba `credential` contra cada ranura y devuelve la llave maestra junto con el número de la ranura que abrió.
+    pub fn unlock(&self, credential: &Credential) -> Option<(usize, Key)> {
+        self.slots.iter().enumerate().find_map(|(index, slot)| {
+            slot.as_ref().and_then(|slot| slot.unlock(credential)).map(|key| (index, key))
+        })
+    }
+
+    /// Guarda `key` envuelta con `credential` en la primera ranura libre. Devuelve `None` si no queda ninguna.
+    pub fn add_slot(&mut self, credential: &Credential, key: &Key) -> Option<usize> {
+        let index = self.slots.iter().position(|slot| slot.is_none())?;
+        self.slots[index] = Some(KeySlot::wrap(credential, key));
+        Some(index)
+    }
+
+    /// Reemplaza la credencial de la ranura `index` por `credential`.
+    pub fn replace_slot(&mut self, index: usize, credential: &Credential, key: &Key) {
+        self.slots[index] = Some(KeySlot::wrap(credential, key));
+    }
+
+    /// Cantidad de ranuras en uso.
+    pub fn active_slots(&self) -> usize {
+        self.slots.iter().filter(|slot| slot.is_some()).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Todos los subconjuntos de `size` partes de `shares`, en orden.
+    fn subsets(shares: &[KeyShare], size: usize) -> Vec<Vec<KeyShare>> {
+        if size == 0 {
+            return vec![Vec::new()];
+        }
+        (0..shares.len()).flat_map(|first| {
+            subsets(&shares[first + 1..], size - 1).into_iter().map(move |mut rest| {
+                rest.insert(0, shares[first].clone());
+                rest
+            })
+        }).collect()
+    }
+
+    #[test]
+    fn field_arithmetic_matches_aes() {
+        assert_eq!(gf_mul(0x57, 0x83), 0xc1);
+        assert_eq!(gf_mul(0x57, 0x13), 0xfe);
+        for a in 1..=255u8 {
+            assert_eq!(gf_mul(a, gf_inv(a)), 1, "inverso de {:#04x}", a);
+        }
+    }
+
+    #[test]
+    fn any_threshold_shares_recover_the_key() {
+        let key = Key::generate();
+        let shares = key.split(3, 5);
+
+        for subset in subsets(&shares, 3) {
+            assert_eq!(Key::combine(&subset).unwrap().0, key.0);
+        }
+        // Con más partes de las necesarias, o con alguna repetida, se obtiene la misma llave
+        assert_eq!(Key::combine(&shares).unwrap().0, key.0);
+        let repeated = vec![shares[4].clone(), shares[4].clone(), shares[0].clone(), shares[2].clone()];
+        assert_eq!(Key::combine(&repeated).unwrap().0, key.0);
+        // Un reparto de una sola parte es la llave misma
+        assert_eq!(Key::combine(&key.split(1, 1)).unwrap().0, key.0);
+    }
+
+    #[test]
+    fn fewer_than_threshold_shares_do_not_recover_the_key() {
+        let key = Key::generate();
+        let shares = key.split(3, 5);
+
+        for subset in subsets(&shares, 2) {
+            assert!(Key::combine(&subset).is_none());
+
+            // Aunque se cambie el mínimo escrito en las partes, la interpolación con menos puntos da otra llave
+            let forged: Vec<KeyShare> = subset.into_iter().map(|share| KeyShare { threshold: 2, ..share }).collect();
+            assert_ne!(Key::combine(&forged).unwrap().0, key.0);
+        }
+
+        // Las partes de repartos distintos no se mezclan
+        let mut mixed = key.split(2, 4);
+        mixed.push(shares[0].clone());
+        assert!(Key::combine(&mixed).is_none());
+    }
+
+    #[test]
+    fn share_text_round_trips_and_rejects_garbage() {
+        let key = Key::generate();
+        let share = key.split(2, 3).remove(1);
+        let parsed = KeyShare::from_text(&share.to_text()).unwrap();
+        assert_eq!((parsed.index, parsed.total, parsed.threshold, parsed.data), (2, 3, 2, share.data));
+
+        let text = share.to_text();
+        assert!(KeyShare::from_text(&text.replace("QRFS-LLAVE", "OTRA-COSA")).is_none());
+        assert!(KeyShare::from_text(&text[..text.len() - 2]).is_none());
+        assert!(KeyShare::from_text(&text.replace(":2:3:2:", ":4:3:2:")).is_none());
+    }
+
+    #[test]
+    fn only_the_right_credential_unlocks_the_header() {
+        let (header, key) = EncryptionHeader::create(&Credential::Phrase(String::from("frase correcta")));
+        let (slot, unlocked) = header.unlock(&Credential::Phrase(String::from("frase correcta"))).unwrap();
+        assert_eq!((slot, unlocked.0), (0, key.0));
+
+        assert!(header.unlock(&Credential::Phrase(String::from("frase incorrecta"))).is_none());
+    }
+
+    #[test]
+    fn tampered_or_misplaced_ciphertext_is_rejected() {
+        let key = Key::generate();
+        let sealed = key.encrypt(b"contenido del bloque", b"qrfs-bloque-1");
+        assert_eq!(key.decrypt(&sealed, b"qrfs-bloque-1").unwrap(), b"contenido del bloque");
+
+        let mut tampered = sealed.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(key.decrypt(&tampered, b"qrfs-bloque-1").is_none());
+        assert!(key.decrypt(&sealed, b"qrfs-bloque-2").is_none());
+        assert!(Key::generate().decrypt(&sealed, b"qrfs-bloque-1").is_none());
+        assert!(key.decrypt(&sealed[..NONCE_SIZE - 1], b"qrfs-bloque-1").is_none());
+    }
+}